@@ -0,0 +1,130 @@
+//! Describes files by POSTing them to a caption server, for indexers with
+//! `type = http`.  Speaks the JSON shape of the caption server in
+//! `indexer/caption_server` and stable-diffusion-webui's interrogate endpoint:
+//!
+//! ```text
+//! POST /sdapi/v1/interrogate  {"image": "<base64>", "model": "clip"}
+//! 200 OK                      {"caption": "a cat sitting on a car"}
+//! ```
+//!
+//! Only plain `http://` URLs are supported, which is what those servers listen on.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::path::Path;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{timeout, Duration},
+};
+
+/// How long to wait for the server to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most of a reply that's read, headers included. Captions are a few hundred bytes.
+const MAX_REPLY: u64 = 1 << 20;
+
+#[derive(Debug, PartialEq)]
+pub struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+pub fn parse_url(url: &str) -> Result<Url, String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("only http:// urls are supported: {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(v6) => {
+            let (host, rest) = v6.split_once(']').ok_or_else(|| format!("bad host in {}", url))?;
+            (host, rest.strip_prefix(':'))
+        },
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| format!("bad port in {}", url))?,
+        None => 80,
+    };
+    if host.is_empty() {
+        return Err(format!("no host in {}", url));
+    }
+    Ok(Url { host: host.to_string(), port, path: path.to_string() })
+}
+
+/// Whether the server accepts connections, the check for http indexers without a check command.
+pub async fn reachable(url: &Url) -> bool {
+    matches!(timeout(CONNECT_TIMEOUT, TcpStream::connect((url.host.as_str(), url.port))).await, Ok(Ok(_)))
+}
+
+/// Send the file to the server and return the text in `field` of its reply.
+pub async fn describe(url: &Url, field: &str, file: &Path, limit: Duration) -> Result<String, String> {
+    let data = tokio::fs::read(file).await.map_err(|e| e.to_string())?;
+    let body = serde_json::json!({"image": BASE64.encode(&data), "model": "clip"}).to_string();
+    let reply = match timeout(limit, post(url, &body)).await {
+        Ok(reply) => reply?,
+        Err(_) => return Err(format!("no reply within {:?}", limit)),
+    };
+    let value: serde_json::Value = serde_json::from_str(&reply).map_err(|e| format!("bad reply: {}: {}", e, reply))?;
+    match value.get(field).and_then(serde_json::Value::as_str) {
+        Some(text) => Ok(text.to_string()),
+        None => Err(format!("no \"{}\" string in reply: {}", field, reply)),
+    }
+}
+
+async fn post(url: &Url, body: &str) -> Result<String, String> {
+    let mut stream = match timeout(CONNECT_TIMEOUT, TcpStream::connect((url.host.as_str(), url.port))).await {
+        Ok(stream) => stream.map_err(|e| e.to_string())?,
+        Err(_) => return Err(format!("couldn't connect within {:?}", CONNECT_TIMEOUT)),
+    };
+    let host = if url.host.contains(':') { format!("[{}]", url.host) } else { url.host.clone() };
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nAccept: application/json\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        url.path, host, url.port, body.len());
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+    stream.write_all(body.as_bytes()).await.map_err(|e| e.to_string())?;
+    let mut response = vec![];
+    stream.take(MAX_REPLY + 1).read_to_end(&mut response).await.map_err(|e| e.to_string())?;
+    if response.len() as u64 > MAX_REPLY {
+        return Err(format!("reply over {} bytes", MAX_REPLY));
+    }
+
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").ok_or("incomplete reply")?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let mut body = response[split + 4..].to_vec();
+    let mut lines = head.lines();
+    let status = lines.next().and_then(|l| l.split_whitespace().nth(1)).and_then(|s| s.parse::<u16>().ok())
+        .ok_or("bad status line")?;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("transfer-encoding") && value.trim().eq_ignore_ascii_case("chunked") {
+                body = dechunk(&body).ok_or("bad chunked reply")?;
+            }
+        }
+    }
+    let body = String::from_utf8_lossy(&body).into_owned();
+    if !(200..300).contains(&status) {
+        return Err(format!("HTTP {}: {}", status, body.trim()));
+    }
+    Ok(body)
+}
+
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    loop {
+        let end = data.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&data[..end]).ok()?.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        data = &data[end + 2..];
+        if size == 0 {
+            return Some(out);
+        }
+        out.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
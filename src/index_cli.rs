@@ -0,0 +1,486 @@
+//! `pikeru index ...` subcommands for managing the semantic search index from a terminal.
+use getopts::Options;
+use ignore::WalkBuilder;
+use rusqlite::{Connection, OpenFlags};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    process,
+};
+use zbus::blocking;
+use crate::{index_db_path, semantic_search, IndexerProxyBlocking};
+use serde_json::{value::RawValue, Value};
+use crate::search_ignore::SearchIgnore;
+use crate::query::{Query, SearchItem};
+
+const USAGE: &str = "Usage: pikeru index COMMAND [OPTIONS]
+
+Commands:
+    status              Show what the indexer is doing and how much is indexed
+    add DIR...          Queue directories for indexing
+    pause               Stop indexing new files until resume
+    resume              Continue indexing after pause
+    show FILE           Print the searchable text stored for a file
+    failures            List files the indexer failed on
+    retry [FILE]...     Forget failures, of all files or just these, and index them again
+    search QUERY [DIR]...
+                        List indexed files matching QUERY the way the picker ranks them
+    prune               Remove entries for files that no longer exist
+    export              Write the index to stdout as JSON lines
+    import [FILE]       Add entries written by export, from FILE or stdin";
+
+struct IndexCli {
+    db_path: String,
+    service: Option<String>,
+    object_path: Option<String>,
+}
+
+pub fn run(args: &[String]) -> ! {
+    let mut opts = Options::new();
+    opts.optflag("r", "recursive", "With add, queue every subdirectory too");
+    opts.optopt("", "db", "Path to index database", "PATH");
+    opts.optopt("", "service", "D-Bus service name of the portal (tests only)", "NAME");
+    opts.optopt("", "object-path", "D-Bus object path of the portal (tests only)", "PATH");
+    opts.optflag("h", "help", "Show usage information");
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Bad args: {}", e);
+            process::exit(1);
+        },
+    };
+    if matches.opt_present("h") {
+        println!("{}", opts.usage(USAGE));
+        process::exit(0);
+    }
+    let cli = IndexCli {
+        db_path: matches.opt_str("db").unwrap_or_else(|| index_db_path().to_string_lossy().to_string()),
+        service: matches.opt_str("service"),
+        object_path: matches.opt_str("object-path"),
+    };
+    let free: Vec<&str> = matches.free.iter().map(String::as_str).collect();
+    let res = match free.as_slice() {
+        ["status"] => cli.status(),
+        ["add", dirs @ ..] if !dirs.is_empty() => cli.add(dirs, matches.opt_present("r")),
+        ["pause"] => cli.pause(true),
+        ["resume"] => cli.pause(false),
+        ["show", file] => cli.show(file),
+        ["failures"] => cli.failures(),
+        ["retry", files @ ..] => cli.retry(files),
+        ["search", text, dirs @ ..] => cli.search(text, dirs),
+        ["prune"] => cli.prune(),
+        ["export"] => cli.export(),
+        ["import"] => cli.import(None),
+        ["import", file] => cli.import(Some(file)),
+        _ => Err(opts.usage(USAGE)),
+    };
+    match res {
+        Ok(()) => process::exit(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    }
+}
+
+impl IndexCli {
+    fn open_db(&self) -> Result<Connection, String> {
+        Connection::open_with_flags(&self.db_path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+            .map_err(|e| format!("Can't open index {}: {}", self.db_path, e))
+    }
+
+    fn proxy(&self) -> Result<IndexerProxyBlocking<'static>, String> {
+        let conn = blocking::Connection::session().map_err(|e| e.to_string())?;
+        let mut builder = IndexerProxyBlocking::builder(&conn);
+        if let Some(ref s) = self.service {
+            builder = builder.destination(s.clone()).map_err(|e| e.to_string())?;
+        }
+        if let Some(ref p) = self.object_path {
+            builder = builder.path(p.clone()).map_err(|e| e.to_string())?;
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+
+    fn status(&self) -> Result<(), String> {
+        match self.open_db() {
+            Ok(db) => {
+                let (files, dirs): (u64, u64) = db.query_row(
+                    "select count(*), count(distinct dir) from descriptions", (), |r| Ok((r.get(0)?, r.get(1)?)))
+                    .map_err(|e| e.to_string())?;
+                println!("Indexed: {} files in {} dirs", files, dirs);
+                if has_indexer_names(&db) {
+                    let mut query = db.prepare("select indexer_name, count(*) from descriptions
+                                                where indexer_name is not null group by indexer_name order by indexer_name")
+                        .map_err(|e| e.to_string())?;
+                    let rows = query.query_map((), |r| Ok((r.get::<_, String>(0)?, r.get::<_, u64>(1)?)))
+                        .map_err(|e| e.to_string())?;
+                    for (name, files) in rows.filter_map(|r| r.ok()) {
+                        println!("  {}: {} files", name, files);
+                    }
+                }
+                if has_failures(&db) {
+                    let failed: u64 = db.query_row("select count(*) from failures", (), |r| r.get(0)).map_err(|e| e.to_string())?;
+                    if failed > 0 {
+                        println!("Failed: {} files, listed by pikeru index failures", failed);
+                    }
+                }
+            },
+            Err(e) => println!("{}", e),
+        }
+        match self.proxy().and_then(|p| p.get_status().map_err(|e| e.to_string())) {
+            Ok((queued, dir, done, failed, online)) => {
+                let paused = self.proxy().and_then(|p| p.paused().map_err(|e| e.to_string())).unwrap_or(false);
+                println!("Indexer: {}{}", if online { "online" } else { "offline" }, if paused { ", paused" } else { "" });
+                if queued == 0 {
+                    println!("Queue: empty");
+                } else {
+                    println!("Queue: {} dirs, indexing {}", queued, dir);
+                }
+                println!("This batch: {} indexed, {} failed", done, failed);
+            },
+            Err(e) => println!("Portal not running: {}", e),
+        }
+        Ok(())
+    }
+
+    fn add(&self, dirs: &[&str], recursive: bool) -> Result<(), String> {
+        let mut queue = vec![];
+        for dir in dirs {
+            let dir = std::fs::canonicalize(dir).map_err(|e| format!("{}: {}", dir, e))?;
+            if !dir.is_dir() {
+                return Err(format!("Not a directory: {}", dir.display()));
+            }
+            if recursive {
+                // Skip what the picker's search skips: hidden dirs, [SearchIgnore] and, unless it's off, .gitignore files
+                let settings = SearchIgnore::load(Path::new(&std::env::var("HOME").unwrap_or_default()));
+                let search_ignore = settings.matcher();
+                let walk = WalkBuilder::new(&dir)
+                    .standard_filters(false)
+                    .hidden(true)
+                    .git_ignore(settings.respect_gitignore)
+                    .parents(settings.respect_gitignore)
+                    .require_git(false)
+                    .filter_entry(move |e| !search_ignore.matched(e.path(), e.file_type().is_some_and(|t| t.is_dir())).is_ignore())
+                    .build();
+                for entry in walk.filter_map(|e| e.ok()) {
+                    if entry.file_type().is_some_and(|t| t.is_dir()) {
+                        queue.push(entry.path().to_string_lossy().to_string());
+                    }
+                }
+            } else {
+                queue.push(dir.to_string_lossy().to_string());
+            }
+        }
+        let mut proxy = self.proxy()?;
+        proxy.update(&queue.iter().map(String::as_str).collect()).map_err(|e| e.to_string())?;
+        println!("Queued {} dirs", queue.len());
+        Ok(())
+    }
+
+    fn pause(&self, pause: bool) -> Result<(), String> {
+        let proxy = self.proxy()?;
+        match pause {
+            true => proxy.pause().map_err(|e| e.to_string())?,
+            false => proxy.resume().map_err(|e| e.to_string())?,
+        }
+        println!("Indexing {}", if pause { "paused" } else { "resumed" });
+        Ok(())
+    }
+
+    fn show(&self, file: &str) -> Result<(), String> {
+        let path = std::fs::canonicalize(file).map_err(|e| format!("{}: {}", file, e))?;
+        let (dir, fname) = split_path(&path);
+        let db = self.open_db()?;
+        let exact = has_exact_mtime(&db);
+        let (description, mtime): (String, rusqlite::types::Value) = db.query_row(
+            "select description, mtime from descriptions where dir = ?1 and fname = ?2",
+            (&dir, &fname), |r| Ok((r.get(0)?, r.get(1)?)))
+            .map_err(|_| format!("Not indexed: {}", path.display()))?;
+        println!("{}", description.trim_end());
+        let modified = path.metadata().and_then(|m| m.modified()).ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+        let changed = match (modified, mtime) {
+            (Some(m), rusqlite::types::Value::Integer(ns)) if exact => m.as_nanos() as i64 != ns,
+            (Some(m), rusqlite::types::Value::Real(secs)) => m.as_secs_f32() != secs as f32,
+            _ => false,
+        };
+        if changed {
+            eprintln!("The file has changed since it was indexed");
+        }
+        Ok(())
+    }
+
+    fn failures(&self) -> Result<(), String> {
+        let failures = self.proxy()?.get_failures().map_err(|e| e.to_string())?;
+        if failures.is_empty() {
+            println!("No failures");
+        }
+        for (path, name, code, error, attempts, last, quarantined) in failures {
+            let last = chrono::DateTime::from_timestamp(last, 0)
+                .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+            println!("{}", path);
+            println!("  {} by {} indexer, last {}{}", if attempts == 1 { "1 attempt".to_string() } else { format!("{} attempts", attempts) },
+                name, last, if quarantined { ", skipped until the file changes" } else { "" });
+            if code >= 0 {
+                println!("  exit code {}", code);
+            }
+            for line in error.trim().lines() {
+                println!("  {}", line);
+            }
+        }
+        Ok(())
+    }
+
+    fn retry(&self, files: &[&str]) -> Result<(), String> {
+        let db = self.open_db()?;
+        if !has_failures(&db) {
+            println!("No failures");
+            return Ok(());
+        }
+        let paths: Vec<(String, String)> = if files.is_empty() {
+            let mut query = db.prepare("select dir, fname from failures").map_err(|e| e.to_string())?;
+            let rows = query.query_map((), |r| Ok((r.get(0)?, r.get(1)?))).map_err(|e| e.to_string())?;
+            rows.filter_map(|r| r.ok()).collect()
+        } else {
+            files.iter().map(|f| std::fs::canonicalize(f).map(|p| split_path(&p)).map_err(|e| format!("{}: {}", f, e)))
+                .collect::<Result<_, _>>()?
+        };
+        let mut cleared = 0;
+        let mut dirs: Vec<String> = vec![];
+        for (dir, fname) in &paths {
+            if db.execute("delete from failures where dir = ?1 and fname = ?2", (dir, fname)).map_err(|e| e.to_string())? > 0 {
+                cleared += 1;
+                if !dirs.contains(dir) {
+                    dirs.push(dir.clone());
+                }
+            }
+        }
+        println!("Cleared {} failures", cleared);
+        if !dirs.is_empty() {
+            // The portal only needs to be running to retry them now rather than on the next visit
+            if let Ok(mut proxy) = self.proxy() {
+                if proxy.retry(&dirs.iter().map(String::as_str).collect()).is_ok() {
+                    println!("Queued {} dirs", dirs.len());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn search(&self, text: &str, dirs: &[&str]) -> Result<(), String> {
+        let db = self.open_db()?;
+        let dirs = dirs.iter().map(|d| std::fs::canonicalize(d)
+            .map(|p| p.to_string_lossy().to_string()).map_err(|e| format!("{}: {}", d, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let dirs: Vec<&str> = dirs.iter().map(String::as_str).collect();
+        let dirs = (!dirs.is_empty()).then_some(&dirs[..]);
+        let query = Query::parse(text);
+        // Without the portal or an embed command, only descriptions are searched
+        let proxy = self.proxy().ok();
+        let found: Vec<HashMap<String, i64>> = query.searches().iter().map(|(text, embed)| {
+            let embedding: Option<Vec<f32>> = proxy.as_ref().filter(|_| *embed)
+                .and_then(|p| p.embed(text).ok())
+                .map(|v| v.into_iter().map(|f| f as f32).collect());
+            semantic_search(&db, dirs, text, embedding.as_deref()).into_iter().collect()
+        }).collect();
+        // File names aren't fuzzy matched, since only indexed files are listed
+        let mut query_files = db.prepare("select dir, fname from descriptions").map_err(|e| e.to_string())?;
+        let files = query_files.query_map((), |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))
+            .map_err(|e| e.to_string())?;
+        let mut results: Vec<(String, i64)> = files.filter_map(|r| r.ok())
+            .filter(|(dir, _)| dirs.is_none_or(|dirs| dirs.contains(&dir.as_str())))
+            .filter_map(|(dir, fname)| {
+                // Spelled the way description searches return it
+                let path = format!("{}/{}", dir, fname);
+                let metadata = Path::new(&path).metadata().ok();
+                let item = SearchItem {
+                    path,
+                    size: metadata.as_ref().map(|m| m.len()).unwrap_or_default(),
+                    mtime: metadata.as_ref().and_then(|m| m.modified().ok())
+                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()).map(|d| d.as_secs() as i64).unwrap_or_default(),
+                    dir: metadata.as_ref().is_some_and(|m| m.is_dir()),
+                    kind: None,
+                };
+                let score = query.score(&item, &found, |_, _| None)?;
+                Some((item.path, score))
+            }).collect();
+        results.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let mut out = std::io::stdout().lock();
+        for (path, _) in results {
+            writeln!(out, "{}", path).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn prune(&self) -> Result<(), String> {
+        let db = self.open_db()?;
+        let gone: Vec<i64> = {
+            let mut query = db.prepare("select rowid, dir, fname from descriptions").map_err(|e| e.to_string())?;
+            let rows = query.query_map((), |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?)))
+                .map_err(|e| e.to_string())?;
+            rows.filter_map(|r| r.ok())
+                .filter(|(_, dir, fname)| !Path::new(dir).join(fname).exists())
+                .map(|(id, _, _)| id)
+                .collect()
+        };
+        for id in &gone {
+            db.execute("delete from descriptions where rowid = ?1", [id]).map_err(|e| e.to_string())?;
+        }
+        if has_failures(&db) {
+            let mut query = db.prepare("select dir, fname from failures").map_err(|e| e.to_string())?;
+            let rows = query.query_map((), |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?))).map_err(|e| e.to_string())?;
+            for (dir, fname) in rows.filter_map(|r| r.ok()).filter(|(dir, fname)| !Path::new(dir).join(fname).exists()) {
+                db.execute("delete from failures where dir = ?1 and fname = ?2", (dir, fname)).map_err(|e| e.to_string())?;
+            }
+        }
+        println!("Removed {} entries", gone.len());
+        Ok(())
+    }
+
+    fn export(&self) -> Result<(), String> {
+        let db = self.open_db()?;
+        let mut out = std::io::stdout().lock();
+        if !has_exact_mtime(&db) {
+            let mut query = db.prepare("select dir, fname, description, mtime from descriptions order by dir, fname")
+                .map_err(|e| e.to_string())?;
+            let rows = query.query_map((), |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?, r.get::<_, f64>(3)?)))
+                .map_err(|e| e.to_string())?;
+            for (dir, fname, description, mtime) in rows.filter_map(|r| r.ok()) {
+                writeln!(out, "{{\"dir\":{},\"fname\":{},\"description\":{},\"mtime\":{}}}",
+                    Value::from(dir), Value::from(fname), Value::from(description), mtime).map_err(|e| e.to_string())?;
+            }
+            return Ok(());
+        }
+        let name_col = if has_indexer_names(&db) { "indexer_name" } else { "null" };
+        let mut query = db.prepare(&format!("select dir, fname, description, mtime, size, indexer, {}
+                                             from descriptions order by dir, fname", name_col))
+            .map_err(|e| e.to_string())?;
+        let rows = query.query_map((), |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?,
+                r.get::<_, i64>(3)?, r.get::<_, Option<i64>>(4)?, r.get::<_, Option<String>>(5)?, r.get::<_, Option<String>>(6)?)))
+            .map_err(|e| e.to_string())?;
+        for (dir, fname, description, mtime, size, indexer, indexer_name) in rows.filter_map(|r| r.ok()) {
+            // Seconds with every digit of the nanoseconds, so the mtime survives the trip through JSON
+            write!(out, "{{\"dir\":{},\"fname\":{},\"description\":{},\"mtime\":{}{}.{:09}",
+                Value::from(dir), Value::from(fname), Value::from(description), if mtime < 0 { "-" } else { "" },
+                mtime.unsigned_abs() / 1_000_000_000, mtime.unsigned_abs() % 1_000_000_000).map_err(|e| e.to_string())?;
+            if let Some(size) = size {
+                write!(out, ",\"size\":{}", size).map_err(|e| e.to_string())?;
+            }
+            if let Some(indexer) = indexer {
+                write!(out, ",\"indexer\":{}", Value::from(indexer)).map_err(|e| e.to_string())?;
+            }
+            if let Some(name) = indexer_name {
+                write!(out, ",\"indexer_name\":{}", Value::from(name)).map_err(|e| e.to_string())?;
+            }
+            writeln!(out, "}}").map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn import(&self, file: Option<&str>) -> Result<(), String> {
+        let input: Box<dyn BufRead> = match file {
+            Some(f) => Box::new(BufReader::new(std::fs::File::open(f).map_err(|e| format!("{}: {}", f, e))?)),
+            None => Box::new(BufReader::new(std::io::stdin())),
+        };
+        if let Some(parent) = Path::new(&self.db_path).parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut db = Connection::open(&self.db_path).map_err(|e| e.to_string())?;
+        db.execute("create table if not exists descriptions
+                    (fname text, dir text, description text, mtime real);", ()).map_err(|e| e.to_string())?;
+        let exact = has_exact_mtime(&db);
+        let named = has_indexer_names(&db);
+        let tx = db.transaction().map_err(|e| e.to_string())?;
+        let mut imported = 0;
+        for (i, line) in input.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // Values are kept as written so mtimes don't lose digits to f64
+            let entry: Option<HashMap<String, Box<RawValue>>> = serde_json::from_str(&line).ok();
+            let field = |key: &str| -> Option<String> { serde_json::from_str(entry.as_ref()?.get(key)?.get()).ok() };
+            let mtime = entry.as_ref().and_then(|e| e.get("mtime")).map(|m| m.get());
+            let (Some(dir), Some(fname), Some(description), Some(mtime)) = (field("dir"), field("fname"), field("description"), mtime) else {
+                eprintln!("Skipping bad entry on line {}", i + 1);
+                continue;
+            };
+            if exact {
+                let Some(mtime) = nanos(mtime) else {
+                    eprintln!("Skipping bad entry on line {}", i + 1);
+                    continue;
+                };
+                let size = entry.as_ref().and_then(|e| e.get("size")).and_then(|s| s.get().parse::<i64>().ok());
+                let indexer = field("indexer");
+                if named {
+                    let name = field("indexer_name");
+                    tx.execute("insert into descriptions (dir, fname, description, mtime, size, indexer, indexer_name)
+                                values (?1, ?2, ?3, ?4, ?5, ?6, ?7) on conflict (dir, fname) do update
+                                set description = ?3, mtime = ?4, size = ?5, indexer = ?6, indexer_name = ?7",
+                        (dir, fname, description, mtime, size, indexer, name)).map_err(|e| e.to_string())?;
+                } else {
+                    tx.execute("insert into descriptions (dir, fname, description, mtime, size, indexer) values (?1, ?2, ?3, ?4, ?5, ?6)
+                                on conflict (dir, fname) do update set description = ?3, mtime = ?4, size = ?5, indexer = ?6",
+                        (dir, fname, description, mtime, size, indexer)).map_err(|e| e.to_string())?;
+                }
+            } else {
+                let Some(mtime) = mtime.parse::<f64>().ok() else {
+                    eprintln!("Skipping bad entry on line {}", i + 1);
+                    continue;
+                };
+                let updated = tx.execute("update descriptions set description = ?3, mtime = ?4 where dir = ?1 and fname = ?2",
+                    (&dir, &fname, &description, mtime)).map_err(|e| e.to_string())?;
+                if updated == 0 {
+                    tx.execute("insert into descriptions (dir, fname, description, mtime) values (?1, ?2, ?3, ?4)",
+                        (&dir, &fname, &description, mtime)).map_err(|e| e.to_string())?;
+                }
+            }
+            imported += 1;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        println!("Imported {} entries", imported);
+        Ok(())
+    }
+}
+
+/// A number of seconds as written in JSON, as nanoseconds. Exact for plain decimals.
+fn nanos(text: &str) -> Option<i64> {
+    let (secs, frac) = text.split_once('.').unwrap_or((text, ""));
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if !secs.is_empty() && digits(secs.trim_start_matches('-')) && digits(frac) && frac.len() <= 9 {
+        let nanos: i64 = format!("{:0<9}", frac).parse().ok()?;
+        let whole: i64 = secs.parse().ok()?;
+        let nanos = if secs.starts_with('-') { -nanos } else { nanos };
+        return whole.checked_mul(1_000_000_000)?.checked_add(nanos);
+    }
+    text.parse::<f64>().ok().map(|s| (s * 1e9) as i64)
+}
+
+/// Whether the index has the column yet. What the portal has migrated is looked up this way
+/// rather than by its schema version, so it doesn't matter what order the migrations are in.
+fn has_column(db: &Connection, table: &str, column: &str) -> bool {
+    db.query_row("select 1 from pragma_table_info(?1) where name = ?2", (table, column), |_| Ok(())).is_ok()
+}
+
+/// Whether mtime is stored as integer nanoseconds, which came along with the indexer column.
+fn has_exact_mtime(db: &Connection) -> bool {
+    has_column(db, "descriptions", "indexer")
+}
+
+/// Whether the index stores which named indexer described each file.
+fn has_indexer_names(db: &Connection) -> bool {
+    has_column(db, "descriptions", "indexer_name")
+}
+
+/// Whether the index keeps the files the indexer failed on.
+fn has_failures(db: &Connection) -> bool {
+    has_column(db, "failures", "attempts")
+}
+
+/// Dir and file name the way the portal stores them.
+fn split_path(path: &Path) -> (String, String) {
+    let dir = path.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+    let fname = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+    (dir, fname)
+}
@@ -17,6 +17,15 @@ mod mouse;
 use mouse::mouse_area;
 mod style;
 mod theme;
+mod parent;
+use parent::Parent;
+mod index_cli;
+mod output;
+use output::{Output, Selection};
+mod search_ignore;
+use search_ignore::SearchIgnore;
+mod query;
+use query::{Kind, Query, SearchItem};
 use iced::{
     advanced::widget::Id,
     Rectangle, Padding,
@@ -36,14 +45,14 @@ use iced::{
         Scrollable, scrollable, scrollable::{Direction,Properties},
         Button, TextInput, Text, Checkbox,
         column, row, container,
-        svg, text_input,
+        svg, text_input, pick_list,
         rule::Rule,
     },
     futures::{
         sink::SinkExt,
         StreamExt,
     },
-    event::{self, Status, Event::{Mouse,Keyboard}},
+    event::{self, Status, Event::{Mouse,Keyboard,Window}},
     Point, Size,
 };
 use tokio::{
@@ -58,6 +67,7 @@ use std::{
     ops::{Deref,DerefMut},
     collections::{HashMap,HashSet},
     fs, str, mem,
+    os::unix::fs::OpenOptionsExt,
     path::{PathBuf,Path},
     process::{self, Command as OsCmd},
     sync::Arc,
@@ -124,11 +134,22 @@ fn main() -> iced::Result {
     let resizeable = conf.resizeable_flag.unwrap_or(conf.resizeable.is_true());
     video_rs::init().unwrap();
     let id = mem::take(&mut conf.id);
+    // An X11 parent keeps the picker above it, so the window starts hidden until it's made transient.
+    // Winit can't import xdg-foreign handles, so wayland parents are ignored.
+    let x11_parent = matches!(conf.parent, Some(Parent::X11(_)));
+    if let Some(Parent::Wayland(handle)) = &conf.parent {
+        eprintln!("Wayland parent windows are not supported, ignoring {}", handle);
+    }
+    let modal = conf.modal && !x11_parent;
+    // Closing the window is a cancel that JSON output has to report.
+    let json = conf.output == Output::Json;
     let mut settings = iced::Settings::with_flags(conf);
     settings.id = Some(id);
-    settings.window.level = iced::window::Level::AlwaysOnTop;
+    settings.window.visible = !x11_parent;
+    settings.window.level = if modal { iced::window::Level::AlwaysOnTop } else { iced::window::Level::Normal };
     settings.window.position = iced::window::Position::Centered;
     settings.window.resizable = resizeable;
+    settings.window.exit_on_close_request = !json;
     FilePicker::run(settings)
 }
 
@@ -215,6 +236,14 @@ struct Config {
     auto_icon_threshold: Option<usize>,
     command_confirmation: bool,
     no_cache: bool,
+    filters: Vec<FileFilter>,
+    filter: Option<usize>,
+    choices: Vec<DialogChoice>,
+    accept_label: Option<String>,
+    modal: bool,
+    parent: Option<Parent>,
+    output: Output,
+    result_file: Option<String>,
 }
 
 impl Config {
@@ -226,8 +255,22 @@ impl Config {
     #[inline]
     fn dir(self: &Self) -> bool { self.mode == Mode::Dir }
 
+    /// Whether an item should be displayed given the hidden setting and current filter.
+    fn shows(&self, item: &FItem) -> bool {
+        if item.hidden && !self.show_hidden {
+            return false;
+        }
+        match self.filter {
+            Some(i) if !item.isdir() => self.filters[i].matches(&item.path),
+            _ => true,
+        }
+    }
+
     fn new() -> Self {
         let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.first().is_some_and(|a| a == "index") {
+            index_cli::run(&args[1..]);
+        }
         let mut opts = Options::new();
         let pwd = std::env::var("PWD").unwrap();
         opts.optopt("t", "title", "Title of the filepicker window", "NAME");
@@ -250,10 +293,24 @@ impl Config {
         opts.optflag("h", "help", "Show usage information");
         opts.optflag("v", "version", "Show pikeru version");
         opts.optflag("C", "no-cache", "Do not load or save thumbnails from the cache (rebuilds them every time)");
+        opts.optmulti("F", "filter", "Add a file filter. Patterns are globs or mime types separated by '|' (escape with '\\'), optionally tagged glob: or mime:. May be repeated", "'NAME|PATTERN|...'");
+        opts.optopt("", "current-filter", "Name of the filter to start with. Default is the first one", "NAME");
+        opts.optopt("", "accept-label", "Text for the select button", "LABEL");
+        opts.optflag("", "nonmodal", "Don't keep the window above other windows");
+        opts.optopt("", "parent", "Window to keep the picker above", "[x11:XID, wayland:HANDLE]");
+        opts.optflag("", "print0", "Print selected paths terminated by NUL instead of newline");
+        opts.optflag("", "json", "Print the result as a JSON object with paths, filter, choices, cancelled and overwrite");
+        opts.optmulti("", "choice", "Add a dropdown, or a checkbox if there are no options, whose value is reported in the result file. May be repeated", "'ID|LABEL|INITIAL|OPTION_ID|OPTION_LABEL|...'");
         let matches = match opts.parse(&args) {
             Ok(m) => m,
             Err(e) => die!("Bad args: {}", e),
         };
+        let output = match (matches.opt_present("print0"), matches.opt_present("json")) {
+            (true, true) => die!("Bad args: --print0 and --json can't be used together"),
+            (true, false) => Output::Print0,
+            (false, true) => Output::Json,
+            _ => Output::Lines,
+        };
         if matches.opt_present("h") {
             let cando_pdf = std::process::Command::new("which").arg("pdftoppm").output().map_or(false, |output| output.status.success());
             let cando_epub = std::process::Command::new("which").arg("epub-thumbnailer").output().map_or(false, |output| output.status.success());
@@ -286,7 +343,7 @@ impl Config {
   Space        Toggle image preview
   r            Rename selected file(s)
   q            Exit",
-                "\nFile picker config file is ~/.config/pikeru.conf.\nThe portal config file which includes the semantic search indexer and postprocessor, is by default ~/.config/xdg-desktop-portal-pikeru/config.",
+                "\nRun `pikeru index --help` to manage the semantic search index from the terminal.\nFile picker config file is ~/.config/pikeru.conf.\nThe portal config file which includes the semantic search indexer and postprocessor, is by default ~/.config/xdg-desktop-portal-pikeru/config.",
                 extra_thumbs);
             std::process::exit(0);
         }
@@ -306,9 +363,8 @@ impl Config {
                             Cmd::builtin("Paste"),
                             Cmd::builtin("Terminal"),
                         ];
-        let mut respect_gitignore = true;
         let mut icon_view = true;
-        let mut gitignore = format!("{}\n{}/*\n", ".git/".to_string(), tpath.to_string_lossy());
+        let SearchIgnore { respect_gitignore, patterns: gitignore } = SearchIgnore::parse(&txt, Path::new(&home));
         let mut sort_by = 1;
         let mut thumb_size = 160.0;
         let mut window_size: Size = Size { width: 1024.0, height: 768.0 };
@@ -331,7 +387,7 @@ impl Config {
                 "[Commands]" => section = S::Commands,
                 "[Settings]" => section = S::Settings,
                 "[Bookmarks]" => section = S::Bookmarks,
-                "[SearchIgnore]" => { section = S::Ignore; gitignore.clear(); },
+                "[SearchIgnore]" => section = S::Ignore,
                 _ => {
                     let (k, v) = str::split_once(line, '=').unwrap_or(("",""));
                     let (k, v) = (k.trim(), v.trim());
@@ -344,11 +400,11 @@ impl Config {
                             }
                         },
                         S::Bookmarks => bookmarks.push(Bookmark::new(k,v)),
-                        S::Ignore => {gitignore += line; gitignore += "\n"; },
+                        S::Ignore => {},
                         S::Settings => match k {
                             "thumbnail_size" => { opts_missing -= 1; thumb_size = v.parse().unwrap() },
                             "dpi_scale" => { opts_missing -= 1; dpi_scale = v.parse().unwrap() },
-                            "respect_gitignore" => { opts_missing -= 1; respect_gitignore = v.parse().unwrap() },
+                            "respect_gitignore" => opts_missing -= 1,
                             "show_hidden" => { opts_missing -= 1; show_hidden = v.parse().unwrap() },
                             "delete_confirmation" => {
                                 opts_missing -= 1;
@@ -443,6 +499,11 @@ impl Config {
             icon_view = false;
             auto_icon_threshold = if v.is_empty() { None } else { Some(v.parse().unwrap_or(0)) };
         }
+        let filters: Vec<FileFilter> = matches.opt_strs("F").iter().map(|f| FileFilter::new(f)).collect();
+        let filter = match matches.opt_str("current-filter") {
+            Some(name) => filters.iter().position(|f| f.name == name),
+            None => None,
+        }.or(if filters.is_empty() { None } else { Some(0) });
         Config {
             mode: Mode::from(matches.opt_str("m")),
             path: matches.opt_str("p").unwrap_or(pwd),
@@ -473,6 +534,20 @@ impl Config {
             auto_icon_threshold,
             command_confirmation,
             no_cache: matches.opt_present("no-cache"),
+            filters,
+            filter,
+            choices: matches.opt_strs("choice").iter().map(|c| DialogChoice::new(c)).collect(),
+            accept_label: matches.opt_str("accept-label"),
+            modal: !matches.opt_present("nonmodal"),
+            parent: matches.opt_str("parent").and_then(|p| {
+                let parent = Parent::parse(&p);
+                if parent.is_none() {
+                    eprintln!("Unknown parent window:{}", p);
+                }
+                parent
+            }),
+            output,
+            result_file: std::env::var("PK_RESULT").ok(),
         }
     }
 
@@ -581,11 +656,10 @@ impl Mode {
 
 #[derive(Clone, Debug)]
 enum SearchEvent {
-    NewItems(Vec<String>, u8),
-    AddItems(Vec<String>),
+    NewItems(Vec<SearchItem>, u8),
+    AddItems(Vec<SearchItem>),
     NewView(Vec<usize>),
     AddView(Vec<usize>),
-    AddSemantics(Vec<(String,String)>),
     Results(Vec<(usize, i64)>, u8, usize, String),
     Search(String),
 }
@@ -631,6 +705,8 @@ enum Message {
     ChangeView,
     ArrowKey(Named),
     ShowHidden(bool),
+    SetFilter(usize),
+    SetChoice(usize, String),
     SetRecursive(bool),
     RunCmd(usize),
     InoDelete(String),
@@ -643,6 +719,8 @@ enum Message {
     StartDiscovery,
     DiscoveryComplete((Vec<String>, Vec<(String, PathBuf)>)),
     SearchResult(Box<SearchEvent>),
+    IndexProgress(IndexStatus),
+    Indexed,
     NextRecurse(Vec<FItem>, u8),
     RecurseDone,
     PageUp,
@@ -707,6 +785,19 @@ struct FItemb {
     recursed: bool,
     unicode: bool,
 }
+impl FItemb {
+    fn searchable(&self) -> SearchItem {
+        // Files with an icon rather than a thumbnail may still be audio, which only the name tells
+        let kind = match self.ftype {
+            FType::Image if self.vid => Some(Kind::Video),
+            FType::Image => Some(Kind::Image),
+            FType::PdfEpub => Some(Kind::File),
+            _ => None,
+        };
+        SearchItem { path: self.path.clone(), size: self.size, mtime: self.mtime, dir: self.ftype == FType::Dir, kind }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 struct FItem(Box<FItemb>);
 impl Deref for FItem {
@@ -763,6 +854,32 @@ struct Cmd {
     builtin: bool,
 }
 
+struct FileFilter {
+    name: String,
+    globs: gitignore::Gitignore,
+    mimes: Vec<String>,
+}
+
+/// Extra option from the portal caller, shown in the control bar.
+/// No options means it's a checkbox with "true" or "false" as the value.
+/// The result file reports values by index since the portal keeps track of the ids,
+/// JSON output by id.
+struct DialogChoice {
+    id: String,
+    label: String,
+    options: Vec<(String, String)>,
+    value: String,
+}
+
+/// Dropdown entry, by index since labels may repeat.
+#[derive(Debug, Clone, PartialEq)]
+struct DropdownItem(usize, String);
+impl fmt::Display for DropdownItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.1)
+    }
+}
+
 #[derive(PartialEq)]
 enum FModal {
     None,
@@ -797,6 +914,10 @@ enum RecState {
     Stop,
 }
 
+/// Path, indexer name, exit code or -1, error, attempts, unix time of the last attempt,
+/// and whether the file is skipped until it changes.
+type IndexFailure = (String, String, i32, String, u32, i64, bool);
+
 #[proxy(
     interface = "org.freedesktop.impl.portal.SearchIndexer",
     default_service = "org.freedesktop.impl.portal.desktop.pikeru",
@@ -804,13 +925,57 @@ enum RecState {
 )]
 trait Indexer {
     async fn update(&mut self, path: &Vec<&str>) -> Result<()>;
+    async fn update_recursive(&mut self, root: &str, max_depth: u32) -> Result<()>;
+    async fn retry(&mut self, path: &Vec<&str>) -> Result<()>;
     async fn clear_queue(&self) -> Result<()>;
     async fn configure(&mut self, respect_gitignore: bool, ignore: &str) -> Result<()>;
+    async fn get_status(&self) -> Result<(u32, String, u32, u32, bool)>;
+    async fn embed(&self, text: &str) -> Result<Vec<f64>>;
+    async fn get_failures(&self) -> Result<Vec<IndexFailure>>;
+    async fn pause(&self) -> Result<()>;
+    async fn resume(&self) -> Result<()>;
+    #[zbus(property)]
+    fn paused(&self) -> Result<bool>;
+    #[zbus(signal)]
+    fn progress(&self, dir: String, files_done: u32, files_failed: u32, queued: u32) -> Result<()>;
+    #[zbus(signal)]
+    fn directory_indexed(&self, dir: String) -> Result<()>;
+    #[zbus(signal)]
+    fn indexer_offline(&self, name: String) -> Result<()>;
+}
+
+/// What the portal's indexer is up to, shown next to the spinner.
+#[derive(Debug, Clone, Default)]
+struct IndexStatus {
+    files_done: u32,
+    files_failed: u32,
+    queued: u32,
+    offline: bool,
+}
+
+impl IndexStatus {
+    fn label(&self) -> String {
+        if self.offline {
+            return "Indexer offline".to_string();
+        }
+        let failed = if self.files_failed > 0 { format!(", {} failed", self.files_failed) } else { String::new() };
+        format!("Indexed {}{}, {} dirs left", self.files_done, failed, self.queued)
+    }
 }
+
+enum IndexSignal {
+    Progress(IndexStatus),
+    DirectoryIndexed(String),
+}
+
+/// How many levels below a recursively searched dir the portal is asked to index.
+const INDEX_DEPTH: u32 = 8;
+
 struct IndexProxy<'a> {
     proxy: Option<IndexerProxy<'a>>,
     done: HashSet<String>,
-    sql: Option<rusqlite::Connection>,
+    /// Roots whose whole tree the portal was asked to index.
+    recursed: Vec<String>,
 }
 impl<'a> IndexProxy<'a> {
 
@@ -822,16 +987,10 @@ impl<'a> IndexProxy<'a> {
                 Some(prox)
             }.await
         } else { None };
-        let home = std::env::var("HOME").unwrap();
-        let idxfile = Path::new(&home).join(".cache").join("pikeru").join("index.db");
-        let sql = match rusqlite::Connection::open(&idxfile) {
-            Ok(con) => Some(con),
-            Err(_) => None,
-        };
         Self {
             proxy,
             done: HashSet::new(),
-            sql,
+            recursed: vec![],
         }
     }
 
@@ -862,21 +1021,10 @@ impl<'a> IndexProxy<'a> {
         } 
     }
 
-    async fn update(&mut self, dirs: &Vec<String>) -> Vec<(String,String)> {
-        let mut placeholders = String::new();
-        let mut i = 0;
-        let filtered = dirs.iter().filter(|p|{
-                let needed = self.done.insert(p.to_string());
-                match (needed, &self.sql) {
-                    (true, Some(_)) => {
-                        i += 1;
-                        placeholders = format!("{}{}?{}", placeholders, if i>1 {","} else {""}, i);
-                    },
-                    (_,_) => {},
-                }
-                needed
-            }).map(|s|s.as_str()).collect::<Vec::<&str>>();
-        if filtered.is_empty() { return Vec::new(); }
+    async fn update(&mut self, dirs: &Vec<String>) {
+        let filtered = dirs.iter().filter(|p| self.done.insert(p.to_string()))
+            .map(|s|s.as_str()).collect::<Vec::<&str>>();
+        if filtered.is_empty() { return; }
 
         if let Some(ref mut prox) = self.proxy {
             match prox.update(&filtered).await {
@@ -887,23 +1035,177 @@ impl<'a> IndexProxy<'a> {
                 },
             }
         } 
-        if let Some(sql) = &self.sql {
-            let qtext = format!("select concat(dir, '/', fname), description from descriptions where dir in ({})", placeholders);
-            let mut query = match sql.prepare(qtext.as_str()) {
-                Ok(q) => q,
-                Err(_) => return Vec::new(),
-            };
-            let qmap = query.query_map(rusqlite::params_from_iter(filtered.iter()), |row|{
-                Ok((row.get(0)?, row.get(1)?))
-            });
-            match qmap {
-                Ok(q) => q.filter_map(|r|r.ok()).collect(),
-                Err(_) => Vec::new(),
+    }
+
+    /// Have the portal walk and index everything under roots, skipping trees it was already asked for.
+    async fn update_recursive(&mut self, roots: &[String]) {
+        for root in roots {
+            if self.recursed.iter().any(|r| Path::new(root).starts_with(r)) {
+                continue;
             }
+            self.recursed.push(root.clone());
+            if let Some(ref mut prox) = self.proxy {
+                if let Err(e) = prox.update_recursive(root, INDEX_DEPTH).await {
+                    eprintln!("{}", e);
+                    self.proxy = None;
+                }
+            }
+        }
+    }
+
+    /// Whether this picker asked for dir to be indexed.
+    fn requested(&self, dir: &str) -> bool {
+        self.done.contains(dir) || self.recursed.iter().any(|r| Path::new(dir).starts_with(r))
+    }
+}
+
+fn index_db_path() -> PathBuf {
+    let home = std::env::var("HOME").unwrap();
+    Path::new(&home).join(".cache").join("pikeru").join("index.db")
+}
+
+/// How long typing has to pause before a search is sent to the portal to embed.
+const EMBED_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Errors from a portal that can't embed at all, rather than failing on this text.
+const EMBED_UNSUPPORTED: &[&str] = &[
+    "org.freedesktop.DBus.Error.NotSupported",
+    "org.freedesktop.DBus.Error.UnknownMethod",
+    "org.freedesktop.DBus.Error.ServiceUnknown",
+];
+
+/// Lowest cosine similarity between a file's embedding and the search's that counts as a match.
+const MIN_SIMILARITY: f32 = 0.2;
+
+/// Indexed files whose descriptions contain the words in query, best first, limited to dirs if given.
+/// Files with more of the words come first, then the ones bm25 ranks higher. Every word also matches
+/// as a prefix of a longer one. Indexes from before the full text table fall back to substring matching.
+/// With the query's embedding, files get up to one word's worth more for how similar theirs is.
+fn semantic_search(db: &rusqlite::Connection, dirs: Option<&[&str]>, query: &str, embedding: Option<&[f32]>) -> Vec<(String, i64)> {
+    let words: Vec<String> = query.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty()).map(str::to_lowercase).unique().collect();
+    if words.is_empty() {
+        return vec![];
+    }
+    let dirs = dirs.map(|d| serde_json::Value::from(d).to_string());
+    let mut results: HashMap<String, i64> = text_matches(db, &dirs, &words).into_iter().collect();
+    if let Some(embedding) = embedding {
+        for (path, score) in similar_embeddings(db, &dirs, embedding) {
+            *results.entry(path).or_default() += score;
+        }
+    }
+    let mut results: Vec<(String, i64)> = results.into_iter().collect();
+    results.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    results
+}
+
+/// Files with embeddings at least MIN_SIMILARITY to the query's, scored up to 1000.
+fn similar_embeddings(db: &rusqlite::Connection, dirs: &Option<String>, query: &[f32]) -> Vec<(String, i64)> {
+    let norm = |v: &[f32]| v.iter().map(|f| f * f).sum::<f32>().sqrt();
+    let query_norm = norm(query);
+    if query_norm == 0.0 {
+        return vec![];
+    }
+    let Ok(mut stmt) = db.prepare_cached("select d.dir || '/' || d.fname, e.vector from embeddings e join descriptions d on d.id = e.id
+                                          where ?1 is null or d.dir in (select value from json_each(?1))") else {
+        return vec![];
+    };
+    let Ok(rows) = stmt.query_map([dirs], |r| Ok((r.get::<_, String>(0)?, r.get::<_, Vec<u8>>(1)?))) else {
+        return vec![];
+    };
+    rows.filter_map(|r| r.ok()).filter_map(|(path, blob)| {
+        let vector: Vec<f32> = blob.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        if vector.len() != query.len() {
+            return None;
+        }
+        let similarity = vector.iter().zip(query).map(|(a, b)| a * b).sum::<f32>() / (norm(&vector) * query_norm);
+        (similarity >= MIN_SIMILARITY).then_some((path, (similarity * 1000.0) as i64))
+    }).collect()
+}
+
+/// Scores for files with words in their descriptions, see semantic_search.
+fn text_matches(db: &rusqlite::Connection, dirs: &Option<String>, words: &[String]) -> Vec<(String, i64)> {
+    let run = |sql: &str, arg: &str| -> rusqlite::Result<Vec<(String, f64)>> {
+        let mut stmt = db.prepare_cached(sql)?;
+        let rows = stmt.query_map((arg, &dirs), |r| Ok((r.get(0)?, r.get(1)?)))?;
+        Ok(rows.filter_map(|r| r.ok()).collect())
+    };
+    let has_fts = db.prepare("select 1 from descriptions_fts limit 0").is_ok();
+    let mut scores: HashMap<String, (i64, f64)> = HashMap::new();
+    for word in words {
+        let hits = if has_fts {
+            run("select d.dir || '/' || d.fname, 0.0 from descriptions_fts f join descriptions d on d.id = f.rowid
+                 where descriptions_fts match ?1 and (?2 is null or d.dir in (select value from json_each(?2)))",
+                &format!("\"{}\"*", word))
         } else {
-            Vec::new()
+            run("select dir || '/' || fname, 0.0 from descriptions where description like ?1
+                 and (?2 is null or dir in (select value from json_each(?2)))", &format!("%{}%", word))
+        };
+        for (path, _) in hits.unwrap_or_default() {
+            scores.entry(path).or_default().0 += 1;
         }
     }
+    if has_fts {
+        let any = words.iter().map(|w| format!("\"{}\"*", w)).join(" OR ");
+        let ranked = run("select d.dir || '/' || d.fname, bm25(descriptions_fts) from descriptions_fts f join descriptions d on d.id = f.rowid
+                          where descriptions_fts match ?1 and (?2 is null or d.dir in (select value from json_each(?2)))", &any);
+        for (path, rank) in ranked.unwrap_or_default() {
+            if let Some(score) = scores.get_mut(&path) {
+                score.1 = rank;
+            }
+        }
+    }
+    // bm25 is negative and lower is better, it only breaks ties between files with as many words
+    scores.into_iter()
+        .map(|(path, (count, rank))| (path, count * 1000 + (-rank * 10.0).clamp(0.0, 999.0) as i64))
+        .collect()
+}
+
+impl IndexProxy<'static> {
+    /// Forward the portal's indexer signals, starting with its current status if it's busy.
+    fn watch(&self) -> UReceiver<IndexSignal> {
+        let (tx, rx) = unbounded_channel();
+        let Some(prox) = self.proxy.clone() else { return rx };
+        tokio::spawn(async move {
+            let (mut progress, mut indexed, mut offline) = match tokio::try_join!(
+                prox.receive_progress(), prox.receive_directory_indexed(), prox.receive_indexer_offline()) {
+                Ok(streams) => streams,
+                Err(e) => {
+                    eprintln!("Can't watch indexer: {}", e);
+                    return;
+                },
+            };
+            if let Ok((queued, _dir, files_done, files_failed, online)) = prox.get_status().await {
+                if queued > 0 {
+                    let status = IndexStatus { files_done, files_failed, queued, offline: !online };
+                    _ = tx.send(IndexSignal::Progress(status));
+                }
+            }
+            loop {
+                let signal = tokio::select! {
+                    Some(sig) = progress.next() => match sig.args() {
+                        Ok(a) => IndexSignal::Progress(IndexStatus {
+                            files_done: a.files_done,
+                            files_failed: a.files_failed,
+                            queued: a.queued,
+                            offline: false,
+                        }),
+                        Err(_) => continue,
+                    },
+                    Some(sig) = indexed.next() => match sig.args() {
+                        Ok(a) => IndexSignal::DirectoryIndexed(a.dir),
+                        Err(_) => continue,
+                    },
+                    Some(_) = offline.next() => IndexSignal::Progress(IndexStatus { offline: true, ..Default::default() }),
+                    else => break,
+                };
+                if tx.send(signal).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
 }
 
 enum Preview {
@@ -982,6 +1284,7 @@ struct FilePicker {
     searchbar: String,
     search_running: bool,
     recurse_state: RecState,
+    indexing: Option<IndexStatus>,
     thumb_sender: Option<USender<FItem>>,
     nproc: usize,
     last_loaded: usize,
@@ -1061,12 +1364,13 @@ impl Application for FilePicker {
         } else {
             None
         };
-        let select_button = match conf.mode {
+        let select_button = conf.accept_label.clone().unwrap_or_else(|| match conf.mode {
             Mode::Files|Mode::File => "Open",
             Mode::Save => "Save",
             Mode::Dir => "Select",
-        }.to_string();
+        }.to_string());
         let saving = conf.saving();
+        let parent_win = conf.parent.clone();
         let enable_sel_button = conf.saving() || conf.dir();
         let search_id = text_input::Id::unique();
         let filepath_id = text_input::Id::unique();
@@ -1090,6 +1394,7 @@ impl Application for FilePicker {
                 searchbar: String::new(),
                 search_running: false,
                 recurse_state: RecState::Stop,
+                indexing: None,
                 icons: Arc::new(Icons::new(ts, icon_theme, no_cache)),
                 clicktimer: ClickTimer{ idx:0, time: Instant::now() - Duration::from_secs(1), preclicked: None},
                 ctrl_pressed: false,
@@ -1114,7 +1419,8 @@ impl Application for FilePicker {
                 content_y: 0.0,
                 recursive_search: true,
                 show_goto: false,
-                goto_paths: vec![],
+                // select the file being saved over so it's visible
+                goto_paths: if saving && path.is_file() { vec![pathstr.clone()] } else { vec![] },
                 show_theme_pane: false,
                 discovering_themes_and_fonts: false,
                 enable_sel_button,
@@ -1135,6 +1441,13 @@ impl Application for FilePicker {
             },
             Command::batch({
                 let mut cmds = vec![iced::window::resize(iced::window::Id::MAIN, window_size)];
+                if let Some(Parent::X11(xid)) = parent_win {
+                    cmds.push(iced::window::run_with_handle(iced::window::Id::MAIN, move |handle| {
+                        parent::set_transient_for(handle, xid);
+                        Message::Dummy
+                    }));
+                    cmds.push(iced::window::change_mode(iced::window::Id::MAIN, iced::window::Mode::Windowed));
+                }
                 if saving {
                     cmds.push(text_input::focus(filepath_id));
                 } else {
@@ -1165,7 +1478,9 @@ impl Application for FilePicker {
                 let len = self.items.len();
                 item.display_idx = self.displayed.len();
                 item.items_idx = len;
-                self.displayed.push(len);
+                if self.conf.shows(&item) {
+                    self.displayed.push(len);
+                }
                 if let Some(ts) = self.thumb_sender.as_ref() {
                     tokio::spawn(item.clone().load(ts.clone(), self.icons.clone(), self.conf.thumb_size as u32));
                 }
@@ -1175,15 +1490,18 @@ impl Application for FilePicker {
             Message::InoDelete(file) => {
                 if let Some(i) = self.items.iter().position(|x|x.path == file) {
                     let dix = self.itod(i);
+                    let was_displayed = self.displayed.get(dix) == Some(&i);
                     let was_previewed = self.view_image.0 == i;
                     self.items.iter_mut().for_each(|m|{
                         if m.items_idx >= i { m.items_idx-=1 };
-                        if m.display_idx >= dix { m.display_idx-=1 };
+                        if was_displayed && m.display_idx >= dix { m.display_idx-=1 };
                     });
+                    if was_displayed {
+                        self.displayed.remove(dix);
+                    }
                     self.displayed.iter_mut().for_each(|m| if *m >= i { *m-=1 });
                     self.items.remove(i);
                     self.end_idx -= 1;
-                    self.displayed.remove(dix);
                     // If the deleted item was being previewed, switch to an adjacent one
                     if was_previewed {
                         if let Some((ii, pv)) = self.find_adjacent_preview(dix) {
@@ -1193,7 +1511,7 @@ impl Application for FilePicker {
                             self.view_image = (0, Preview::None);
                         }
                     }
-                    self.update_searcher_items(self.items.iter().map(|item|item.path.clone()).collect());
+                    self.update_searcher_items(self.items.iter().map(|item|item.searchable()).collect());
                 }
             },
             Message::RunCmd(i) => return self.run_command(i),
@@ -1303,7 +1621,7 @@ impl Application for FilePicker {
                 if let Some(rs) = self.recurse_updater.as_ref() {
                     if !matches!(rs.send(RecMsg::SetRecursive(rec)), Ok(_)) {
                         if !rec { // reset searchable items in case already recursed
-                            let items = self.items[..self.end_idx].iter().map(|item|item.path.clone()).collect::<Vec<_>>();
+                            let items = self.items[..self.end_idx].iter().map(|item|item.searchable()).collect::<Vec<_>>();
                             let iidxs = self.items[..self.end_idx].iter().map(|item|item.items_idx).collect::<Vec<_>>();
                             if let Some(ref mut sender) = self.search_commander {
                                 let a = sender.send(SearchEvent::NewItems(items, self.nav_id));
@@ -1317,21 +1635,13 @@ impl Application for FilePicker {
             Message::ShowHidden(show) => {
                 self.conf.show_hidden = show;
                 self.conf.need_update = true;
-                if !show {
-                    self.enable_sel_button = self.conf.saving() || self.conf.dir() || self.items.iter().any(|item|item.sel);
-                }
-                let end = if self.searchbar.is_empty() { self.end_idx } else { self.displayed.len() };
-                let displayed = self.items[..end].iter().enumerate().filter_map(|(i,item)| {
-                    if show || !item.hidden { Some(i)
-                    } else { None }
-                }).collect();
-                if self.searchbar.is_empty() {
-                    self.displayed = displayed;
-                    return self.update(Message::Sort(self.conf.sort_by));
-                } else {
-                    self.update_searcher_visible(displayed);
-                }
+                return self.refilter();
+            },
+            Message::SetFilter(i) => {
+                self.conf.filter = Some(i);
+                return self.refilter();
             },
+            Message::SetChoice(i, value) => self.conf.choices[i].value = value,
             Message::ChangeView => {
                 self.conf.icon_view = !self.conf.icon_view;
                 self.conf.need_update = true;
@@ -1539,13 +1849,13 @@ impl Application for FilePicker {
                 let (txsrch, search_cmds) = unbounded_channel::<SearchEvent>();
                 let (txrec, recurse_cmds) = unbounded_channel::<RecMsg>();
                 tokio::spawn(watch_inotify(watch_cmds, inochan));
-                self.search_commander = Some(txsrch.clone());
+                self.search_commander = Some(txsrch);
                 self.ino_updater = Some(txino);
                 self.thumb_sender = Some(fichan);
-                tokio::spawn(recursive_add(recurse_cmds, more_files, txrec.clone(), txsrch,
+                tokio::spawn(recursive_add(recurse_cmds, more_files, txrec.clone(),
                                            self.conf.gitignore.clone(), self.conf.respect_gitignore, self.conf.do_index));
                 self.recurse_updater = Some(txrec);
-                tokio::spawn(search_loop(search_cmds, search_res));
+                tokio::spawn(search_loop(search_cmds, search_res, self.conf.do_index));
                 return self.update(Message::LoadDir);
             },
             Message::PathTxtInput(txt) => self.pathbar = txt,
@@ -1556,7 +1866,7 @@ impl Application for FilePicker {
                     self.recurse_state = RecState::Stop;
                     let mut have_sel = false;
                     self.displayed = self.items[..self.end_idx].iter().enumerate().filter_map(|(i,item)| {
-                        if self.conf.show_hidden || !item.hidden {
+                        if self.conf.shows(item) {
                             have_sel |= item.sel;
                             Some(i)
                         } else {None}
@@ -1579,6 +1889,15 @@ impl Application for FilePicker {
                     }
                 }
             },
+            Message::IndexProgress(status) => {
+                self.indexing = if status.queued > 0 || status.offline { Some(status) } else { None };
+            },
+            Message::Indexed => {
+                // Rerun the search so newly indexed descriptions can match
+                if !self.searchbar.is_empty() {
+                    return self.update(Message::SearchTxtInput(self.searchbar.clone()));
+                }
+            },
             Message::SearchResult(res) => {
                 let mut still_running = false;
                 if let SearchEvent::Results(res, nav_id, num_items, term) = *res {
@@ -1605,10 +1924,10 @@ impl Application for FilePicker {
                     let mut new_displayed = vec![];
                     let paths = next_items.iter_mut().enumerate().map(|(i,fitem)| {
                         fitem.items_idx = self.items.len() + i;
-                        if self.conf.show_hidden || !fitem.hidden {
+                        if self.conf.shows(fitem) {
                             new_displayed.push(fitem.items_idx);
                         }
-                        fitem.path.clone()
+                        fitem.searchable()
                     }).collect();
                     if let Some(sender) = self.search_commander.as_ref() {
                         self.items.append(&mut next_items);
@@ -1864,8 +2183,7 @@ impl Application for FilePicker {
                 }
             },
             Message::OverWriteOK => {
-                println!("{}", self.pathbar);
-                self.exit();
+                self.exit(vec![self.pathbar.clone()]);
             },
             Message::Select(seltype) => {
                 if self.conf.saving() {
@@ -1879,8 +2197,7 @@ impl Application for FilePicker {
                             self.update_scroll(0.0);
                             return self.update(Message::LoadDir);
                         } else {
-                            println!("{}", self.pathbar);
-                            self.exit();
+                            self.exit(vec![self.pathbar.clone()]);
                         }
                     }
                 } else if self.conf.dir() {
@@ -1893,13 +2210,11 @@ impl Application for FilePicker {
                             self.dirs = vec![self.items.iter().find(|it|it.sel).unwrap().path.clone()];
                             return self.update(Message::LoadDir);
                         } else {
-                            println!("{}", sel.to_string_lossy());
-                            self.exit();
+                            self.exit(vec![sel.to_string_lossy().to_string()]);
                         }
                     } else if sel.is_file() {
                         if let Some(p) = sel.parent() {
-                            println!("{}", p.to_string_lossy());
-                            self.exit();
+                            self.exit(vec![p.to_string_lossy().to_string()]);
                         }
                     }
                 } else {
@@ -1912,8 +2227,7 @@ impl Application for FilePicker {
                         match sels[0].ftype {
                             FType::Dir => {
                                 if self.conf.dir() && sels.len() == 1 && seltype == SelType::Button {
-                                    println!("{}", sels[0].path);
-                                    self.exit();
+                                    self.exit(vec![sels[0].path.clone()]);
                                 } else {
                                     self.dirs = sels.iter().filter_map(|item| match item.ftype {
                                         FType::Dir => Some(item.path.clone()), _ => None}).collect();
@@ -1922,14 +2236,16 @@ impl Application for FilePicker {
                             },
                             FType::NotExist => {},
                             _ => {
-                                println!("{}", sels.iter().map(|item|item.path.as_str()).join("\n"));
-                                self.exit();
+                                self.exit(sels.iter().map(|item|item.path.clone()).collect());
                             }
                         }
                     }
                 }
             },
             Message::Cancel => {
+                if self.conf.output == Output::Json {
+                    print!("{}", Output::Json.format(&self.selection(&[])));
+                }
                 self.conf.update(false);
                 process::exit(0);
             },
@@ -1965,6 +2281,12 @@ impl Application for FilePicker {
                                         RecMsg::Done(_nav_id) => {
                                             messager.send(Message::RecurseDone).await.unwrap();
                                         }
+                                        RecMsg::IndexProgress(status) => {
+                                            messager.send(Message::IndexProgress(status)).await.unwrap();
+                                        }
+                                        RecMsg::Indexed => {
+                                            messager.send(Message::Indexed).await.unwrap();
+                                        }
                                         _ => {}
                                     }
                                 }
@@ -1991,6 +2313,7 @@ impl Application for FilePicker {
             if stat == Status::Ignored {
                 match evt {
                     Mouse(ButtonPressed(Back)) => Some(Message::UpDir),
+                    Window(_, iced::window::Event::CloseRequested) => Some(Message::Cancel),
                     Mouse(ButtonPressed(Forward)) => Some(Message::DownDir),
                     Mouse(WheelScrolled{ delta: ScrollDelta::Lines{ y, ..}}) => Some(Message::NextImage(if y<0.0 {1} else {-1})),
                     Keyboard(KeyPressed{ key: Key::Named(Enter), .. }) => Some(Message::Select(SelType::Click)),
@@ -2238,6 +2561,11 @@ impl Application for FilePicker {
                         (None, true) => row![count, horizontal_space(), top_icon(self.icons.goto.clone(), Message::Goto)],
                         (None, false) => row![count, horizontal_space()]
                     },
+                    self.build_choices(),
+                    match &self.indexing {
+                        Some(status) => Element::from(Text::new(status.label())),
+                        None => Element::from(horizontal_space().width(0)),
+                    },
                     if self.search_running || matches!(self.recurse_state, RecState::Run) {
                         Element::from(Spinner::new().width(16).height(16).circle_radius(1.5))
                     } else {
@@ -2270,6 +2598,16 @@ impl Application for FilePicker {
                     if let Some(f) = self.font { input = input.font(f); }
                     Element::from(input)
                 },
+                if self.conf.filters.is_empty() {
+                    Element::from(horizontal_space().width(0))
+                } else {
+                    let choices: Vec<DropdownItem> = self.conf.filters.iter().enumerate()
+                        .map(|(i,f)| DropdownItem(i, f.name.clone())).collect();
+                    let selected = self.conf.filter.map(|i| choices[i].clone());
+                    Element::from(pick_list(choices, selected, |f: DropdownItem| Message::SetFilter(f.0))
+                        .text_size(14.0)
+                        .padding(Padding::from([2.0, 5.0])))
+                },
                 {
                     let mut input: TextInput<'_, Message, iced::Theme, iced::Renderer> = TextInput::new("search", self.searchbar.as_str())
                         .on_input(Message::SearchTxtInput)
@@ -3044,11 +3382,6 @@ impl FItem {
     }
 }
 
-struct FileIdx<'a> {
-    path: String,
-    text: Option<&'a str>,
-}
-
 enum Inochan {
     NewDirs(Vec<String>),
     Delete(String),
@@ -3126,10 +3459,7 @@ async fn watch_inotify(mut rx: UReceiver<Inochan>, tx: USender<Inochan>) {
 }
 
 fn shquote(s: &str) -> String {
-    if s.contains("\"") {
-        return format!("'{}'", s);
-    }
-    return format!("\"{}\"", s);
+    format!("'{}'", s.replace('\'', "'\\''"))
 }
 
 /// Try to find a working terminal emulator from a list of known terminals.
@@ -3454,7 +3784,7 @@ impl FilePicker {
                     inodirs.push(dir.clone());
                     rd.map(|f| f.unwrap().path()).for_each(|path| {
                         ret.push(FItem::new(path.into(), self.nav_id));
-                        if self.conf.show_hidden || !ret.last().unwrap().hidden {
+                        if self.conf.shows(ret.last().unwrap()) {
                             displayed.push(ret.len()-1);
                         }
                     });
@@ -3469,16 +3799,33 @@ impl FilePicker {
         self.items.iter_mut().enumerate().for_each(|(i,item)|item.items_idx = i);
         self.displayed = displayed;
         self.enable_sel_button = self.conf.saving() || self.conf.dir();
-        self.update_searcher_items(self.items.iter().map(|item|item.path.clone()).collect());
+        self.update_searcher_items(self.items.iter().map(|item|item.searchable()).collect());
     }
 
-    fn update_searcher_items(self: &mut Self, searchable: Vec<String>) {
+    fn update_searcher_items(self: &mut Self, searchable: Vec<SearchItem>) {
         if let Some(ref mut sender) = self.search_commander {
             sender.send(SearchEvent::NewItems(searchable, self.nav_id)).unwrap();
             sender.send(SearchEvent::NewView(self.displayed.clone())).unwrap();
         }
     }
 
+    /// Rebuild the displayed list after the hidden setting or filter changes.
+    fn refilter(&mut self) -> Command<Message> {
+        self.enable_sel_button = self.conf.saving() || self.conf.dir() || self.items.iter().any(|item|item.sel);
+        let end = if self.searchbar.is_empty() { self.end_idx } else { self.items.len() };
+        let displayed = self.items[..end].iter().enumerate().filter_map(|(i,item)| {
+            if self.conf.shows(item) { Some(i)
+            } else { None }
+        }).collect();
+        if self.searchbar.is_empty() {
+            self.displayed = displayed;
+            return self.update(Message::Sort(self.conf.sort_by));
+        } else {
+            self.update_searcher_visible(displayed);
+        }
+        Command::none()
+    }
+
     fn update_searcher_visible(self: &mut Self, displaylist: Vec<usize>) {
         if let Some(ref mut sender) = self.search_commander {
             sender.send(SearchEvent::NewView(displaylist)).unwrap();
@@ -3508,14 +3855,57 @@ impl FilePicker {
         }
     }
 
-    fn exit(self: &mut Self) {
+    /// Print the chosen paths in the requested output format and exit unless keeping the window open.
+    fn exit(self: &mut Self, paths: Vec<String>) {
+        {
+            use std::io::Write;
+            let mut out = std::io::stdout().lock();
+            _ = out.write_all(self.conf.output.format(&self.selection(&paths)).as_bytes());
+            _ = out.flush();
+        }
         if self.conf.keep_open {
             return;
         }
+        self.write_result(&paths);
         self.conf.update(false);
         process::exit(0);
     }
 
+    /// The dialog's result for printing. No paths means the user cancelled.
+    /// Saving only exits on an existing file after the overwrite was confirmed.
+    fn selection<'a>(&'a self, paths: &'a [String]) -> Selection<'a> {
+        Selection {
+            paths,
+            filter: self.conf.filter.map(|i| self.conf.filters[i].name.as_str()),
+            choices: self.conf.choices.iter().map(|c| (c.id.as_str(), c.value.as_str())).collect(),
+            overwrite: self.conf.saving() && paths.first().is_some_and(|p| Path::new(p).is_file()),
+        }
+    }
+
+    /// Write NUL-terminated key=value records for the portal to the PK_RESULT file.
+    /// Paths go there too since they may contain newlines. The portal creates the file,
+    /// so a missing one or a symlink in its place is refused rather than followed.
+    fn write_result(&self, paths: &[String]) {
+        if let Some(ref path) = self.conf.result_file {
+            let mut out = String::new();
+            for p in paths {
+                out.push_str(&format!("path={}\0", p));
+            }
+            if let Some(i) = self.conf.filter {
+                out.push_str(&format!("filter={}\0", i));
+            }
+            for (i, choice) in self.conf.choices.iter().enumerate() {
+                out.push_str(&format!("choice.{}={}\0", i, choice.value));
+            }
+            let written = fs::OpenOptions::new().write(true).truncate(true)
+                .custom_flags(libc::O_NOFOLLOW).open(path)
+                .and_then(|mut f| std::io::Write::write_all(&mut f, out.as_bytes()));
+            if let Err(e) = written {
+                eprintln!("Error writing result file {}: {}", path, e);
+            }
+        }
+    }
+
     /// Build the settings menu items.
     fn build_settings_menu(&self) -> Vec<Item<'static, Message, iced::Theme, iced::Renderer>> {
         use iced_aw::menu::Item;
@@ -3604,6 +3994,27 @@ impl FilePicker {
         Scrollable::new(col).into()
     }
 
+    /// Build the dropdowns and checkboxes for choices from the portal caller.
+    fn build_choices(&self) -> Element<'static, Message> {
+        let mut choices = Row::new().spacing(8).align_items(iced::Alignment::Center);
+        for (i, choice) in self.conf.choices.iter().enumerate() {
+            if choice.options.is_empty() {
+                choices = choices.push(Checkbox::new(choice.label.clone(), choice.value == "true")
+                    .on_toggle(move |checked| Message::SetChoice(i, checked.to_string())));
+            } else {
+                let items: Vec<DropdownItem> = choice.options.iter().enumerate()
+                    .map(|(j,(_, label))| DropdownItem(j, label.clone())).collect();
+                let selected = choice.options.iter().position(|(id, _)| *id == choice.value).map(|j| items[j].clone());
+                let ids: Vec<String> = choice.options.iter().map(|(id, _)| id.clone()).collect();
+                choices = choices.push(Text::new(choice.label.clone()))
+                    .push(pick_list(items, selected, move |item: DropdownItem| Message::SetChoice(i, ids[item.0].clone()))
+                        .text_size(14.0)
+                        .padding(Padding::from([2.0, 5.0])));
+            }
+        }
+        choices.into()
+    }
+
     /// Build the preview pane showing thumbnails of selected files (list mode only).
     fn build_preview_pane(&self) -> Element<'static, Message> {
         let font = self.font;
@@ -3880,6 +4291,81 @@ impl Bookmark {
     }
 }
 
+impl DialogChoice {
+    fn new(arg: &str) -> Self {
+        let mut fields = split_escaped(arg).into_iter();
+        let id = fields.next().unwrap_or_default();
+        let label = fields.next().unwrap_or_default();
+        let mut value = fields.next().unwrap_or_default();
+        let rest: Vec<String> = fields.collect();
+        let options: Vec<(String, String)> = rest.chunks(2).map(|c| (c[0].clone(), c.get(1).cloned().unwrap_or_default())).collect();
+        if options.is_empty() && value != "true" {
+            value = "false".to_string();
+        }
+        Self {
+            id,
+            label,
+            options,
+            value,
+        }
+    }
+}
+
+impl FileFilter {
+    fn new(arg: &str) -> Self {
+        let mut fields = split_escaped(arg).into_iter();
+        let name = fields.next().unwrap_or_default();
+        let mut builder = gitignore::GitignoreBuilder::new("");
+        builder.case_insensitive(true).unwrap();
+        let mut mimes = vec![];
+        for pat in fields {
+            // Untagged patterns are mime types if they have a '/'
+            let (mime, pat) = match pat.split_once(':') {
+                Some(("glob", glob)) => (false, glob),
+                Some(("mime", mime)) => (true, mime),
+                _ => (pat.contains('/'), pat.as_str()),
+            };
+            if mime {
+                mimes.push(pat.to_lowercase());
+            } else if let Err(e) = builder.add_line(None, pat) {
+                eprintln!("Bad filter pattern {}: {}", pat, e);
+            }
+        }
+        Self {
+            name,
+            globs: builder.build().unwrap_or(gitignore::Gitignore::empty()),
+            mimes,
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        if let Match::Ignore(_) = self.globs.matched(path, false) {
+            return true;
+        }
+        mime_guess::from_path(path).iter().any(|mime| self.mimes.iter().any(|pat| {
+            match pat.strip_suffix("/*") {
+                Some("*") => true,
+                Some(top) => mime.type_() == top,
+                None => mime.essence_str() == pat,
+            }
+        }))
+    }
+}
+
+/// Split a '|' separated list where '\' escapes the next character.
+fn split_escaped(s: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => if let Some(next) = chars.next() { fields.last_mut().unwrap().push(next) },
+            '|' => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
 impl Cmd {
     fn new(label: &str, cmd: &str) -> Self {
         Cmd {
@@ -3986,35 +4472,39 @@ fn vid_frame(src: &str, thumbnail: Option<u32>, savepath: Option<&PathBuf>) -> O
 }
 
 async fn search_loop(mut commands: UReceiver<SearchEvent>,
-                     result_sender: USender<SearchEvent>) {
-    let mut items = vec![];
+                     result_sender: USender<SearchEvent>,
+                     do_index: bool) {
+    let mut items: Vec<SearchItem> = vec![];
     let mut displayed = vec![];
     let mut nav_id = 0;
-    let semantics = RefCell::new(HashMap::<String,&'static str>::new());
+    let mut db = rusqlite::Connection::open(index_db_path()).ok();
+    // Only asks the portal to embed searches while it can, and once per text
+    let mut embedder = if do_index {
+        async { IndexerProxy::new(&Connection::session().await.ok()?).await.ok() }.await
+    } else { None };
+    let mut embeddings: HashMap<String, Option<Vec<f32>>> = HashMap::new();
+    // Searched without its embedding until typing pauses
+    let mut pending: Option<String> = None;
     let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
     loop {
-        match commands.recv().await {
+        let mut typing_paused = false;
+        let command = match pending {
+            Some(_) => match tokio::time::timeout(EMBED_DEBOUNCE, commands.recv()).await {
+                Ok(command) => command,
+                Err(_) => {
+                    typing_paused = true;
+                    pending.take().map(SearchEvent::Search)
+                },
+            },
+            None => commands.recv().await,
+        };
+        match command {
             Some(SearchEvent::NewItems(paths, nid)) => {
-                items = paths.into_iter().map(|path| {
-                    nav_id = nid;
-                    let sem = semantics.borrow();
-                    let text = sem.get(&path).copied();
-                    FileIdx {
-                        path,
-                        text,
-                    }
-                }).collect();
+                nav_id = nid;
+                items = paths;
             },
-            Some(SearchEvent::AddItems(paths)) => {
-                let sem = semantics.borrow();
-                let mut new_items = paths.into_iter().map(|path| {
-                    let text = sem.get(&path).copied();
-                    FileIdx {
-                        path,
-                        text,
-                    }
-                }).collect::<Vec<FileIdx<'_>>>();
-                items.append(&mut new_items);
+            Some(SearchEvent::AddItems(mut paths)) => {
+                items.append(&mut paths);
             },
             Some(SearchEvent::NewView(didxs)) => {
                 displayed = didxs;
@@ -4022,33 +4512,64 @@ async fn search_loop(mut commands: UReceiver<SearchEvent>,
             Some(SearchEvent::AddView(mut didxs)) => {
                 displayed.append(&mut didxs);
             }
-            Some(SearchEvent::AddSemantics(new_sem)) => {
-                let mut sem = semantics.borrow_mut();
-                new_sem.into_iter().for_each(|ps|{sem.insert(ps.0, Box::leak(ps.1.into_boxed_str()));});
-                items.iter_mut().for_each(|item|{
-                    if item.text == None {
-                        item.text = sem.get(&item.path).copied();
-                    }
-                });
-            },
             Some(SearchEvent::Search(term)) => {
-                let mut results = displayed.iter().filter_map(|i| {
-                    let item = &items[*i];
-                    let path = Path::new(item.path.as_str());
-                    let name_match = matcher.fuzzy_match(match path.file_name() {
-                        Some(p) => p.to_string_lossy(),
-                        None => path.to_string_lossy(),
-                    }.as_ref(), term.as_str());
-                    let sem_match = match item.text {
-                        Some(text) => matcher.fuzzy_match(text, term.as_str()),
+                let query = Query::parse(&term);
+                let unembedded = query.searches().iter().any(|(text, embed)| *embed && !embeddings.contains_key(text));
+                pending = (unembedded && embedder.is_some() && !typing_paused).then(|| term.clone());
+                for (text, _) in query.searches().iter().filter(|(_, embed)| *embed && pending.is_none()) {
+                    if embeddings.contains_key(text) {
+                        continue;
+                    }
+                    let vector = match embedder {
+                        Some(ref prox) => match prox.embed(text).await {
+                            Ok(v) => Some(v.into_iter().map(|f| f as f32).collect()),
+                            Err(zbus::Error::MethodError(name, _, _)) if EMBED_UNSUPPORTED.contains(&name.as_str()) => {
+                                eprintln!("Not searching embeddings: {}", name);
+                                embedder = None;
+                                None
+                            },
+                            Err(e) => {
+                                eprintln!("Couldn't embed {:?}: {}", text, e);
+                                None
+                            },
+                        },
                         None => None,
                     };
-                    match (name_match, sem_match) {
-                        (Some(a), Some(b)) => Some((*i, a.max(b))),
-                        (Some(a), None) => Some((*i, a)),
-                        (None, Some(b)) => Some((*i, b)),
-                        (None, None) => None,
+                    if embeddings.len() >= 32 {
+                        embeddings.clear();
                     }
+                    embeddings.insert(text.clone(), vector);
+                }
+                let dirs: HashSet<String> = displayed.iter()
+                    .filter_map(|i| Some(Path::new(items[*i].path.as_str()).parent()?.to_str()?.to_string())).collect();
+                let searches: Vec<(String, Option<Vec<f32>>)> = query.searches().iter().map(|(text, embed)| {
+                    (text.clone(), if *embed { embeddings.get(text).cloned().flatten() } else { None })
+                }).collect();
+                // The connection goes along to a blocking thread and comes back with what it found
+                let found: Vec<HashMap<String, i64>> = match db.take() {
+                    Some(conn) => {
+                        let searched = tokio::task::spawn_blocking(move || {
+                            let dirs: Vec<&str> = dirs.iter().map(String::as_str).collect();
+                            let found = searches.iter().map(|(text, embedding)| {
+                                semantic_search(&conn, Some(&dirs), text, embedding.as_deref()).into_iter().collect()
+                            }).collect();
+                            (conn, found)
+                        }).await;
+                        match searched {
+                            Ok((conn, found)) => {
+                                db = Some(conn);
+                                found
+                            },
+                            Err(e) => {
+                                eprintln!("Description search failed: {}", e);
+                                vec![HashMap::new(); query.searches().len()]
+                            },
+                        }
+                    },
+                    None => vec![HashMap::new(); query.searches().len()],
+                };
+                let mut results = displayed.iter().filter_map(|i| {
+                    query.score(&items[*i], &found, |name, text| matcher.fuzzy_match(name, text)).map(|score| (*i, score))
                 }).collect::<Vec<_>>();
                 results.sort_by(|a,b|b.1.cmp(&a.1));
                 result_sender.send(SearchEvent::Results(results, nav_id, items.len(), term)).unwrap();
@@ -4064,30 +4585,49 @@ enum RecMsg {
     NextItems(Vec<FItem>, u8),
     SetRecursive(bool),
     Done(u8),
+    IndexProgress(IndexStatus),
+    Indexed,
 }
 
 async fn recursive_add(mut updates: UReceiver<RecMsg>,
                        results: USender<RecMsg>,
                        selfy: USender<RecMsg>,
-                       semchan: USender<SearchEvent>,
                        gitignore_txt: String,
                        respect_gitignore: bool,
                        do_index: bool) {
     let mut nav_id = 0;
     let mut recursive = true;
+    let mut roots = vec![];
     let mut dirs = vec![];
     let mut ignores: Vec<Vec<Arc<gitignore::Gitignore>>> = vec![];
     let mut indexer = IndexProxy::new(do_index).await;
     indexer.configure(respect_gitignore, gitignore_txt.as_str()).await;
+    let mut index_signals = indexer.watch();
     let mut ig_builder = gitignore::GitignoreBuilder::new("");
     gitignore_txt.lines().for_each(|line|{ig_builder.add_line(None, line).unwrap();});
     let top_ignore = Arc::new(ig_builder.build().unwrap());
     loop {
-        match updates.recv().await {
+        let update = tokio::select! {
+            update = updates.recv() => update,
+            Some(signal) = index_signals.recv() => {
+                match signal {
+                    IndexSignal::Progress(status) => results.send(RecMsg::IndexProgress(status)).unwrap(),
+                    IndexSignal::DirectoryIndexed(dir) => {
+                        // Only dirs this picker asked for are worth searching
+                        if indexer.requested(&dir) {
+                            results.send(RecMsg::Indexed).unwrap();
+                        }
+                    },
+                }
+                continue;
+            },
+        };
+        match update {
             Some(RecMsg::SetRecursive(rec)) => {
                 recursive = rec;
             },
             Some(RecMsg::NewNav(new_dirs, nid)) => {
+                roots = new_dirs.clone();
                 dirs = new_dirs;
                 ignores = dirs.iter().map(|_|vec![top_ignore.clone()]).collect();
                 nav_id = nid;
@@ -4100,9 +4640,11 @@ async fn recursive_add(mut updates: UReceiver<RecMsg>,
                 let mut new_items = vec![];
                 let mut next_dirs = vec![];
                 let mut next_ignores = vec![];
-                let semantics = indexer.update(&dirs).await;
-                if !semantics.is_empty() {
-                    semchan.send(SearchEvent::AddSemantics(semantics)).unwrap();
+                // Once the search goes below the top, the portal walks the rest of the tree itself
+                if dirs == roots {
+                    indexer.update(&dirs).await;
+                } else if recursive {
+                    indexer.update_recursive(&roots).await;
                 }
                 if !recursive {
                     results.send(RecMsg::Done(nid)).unwrap();
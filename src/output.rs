@@ -0,0 +1,37 @@
+//! What pikeru prints to stdout when the dialog closes.
+use serde_json::Value;
+
+/// How the chosen paths are printed to stdout.
+#[derive(PartialEq, Clone, Copy)]
+pub enum Output {
+    Lines,
+    Print0,
+    Json,
+}
+
+/// What the user ended up with. No paths means they cancelled.
+pub struct Selection<'a> {
+    pub paths: &'a [String],
+    /// Name of the chosen filter
+    pub filter: Option<&'a str>,
+    /// Id and value of each --choice
+    pub choices: Vec<(&'a str, &'a str)>,
+    /// Saving over an existing file, after the user confirmed it
+    pub overwrite: bool,
+}
+
+impl Output {
+    /// Everything to print for the selection.
+    pub fn format(self, sel: &Selection) -> String {
+        match self {
+            Output::Lines => format!("{}\n", sel.paths.join("\n")),
+            Output::Print0 => sel.paths.iter().map(|p| format!("{}\0", p)).collect(),
+            Output::Json => {
+                // Written out rather than as a serde_json map so keys keep their order
+                let choices: Vec<String> = sel.choices.iter().map(|(id, v)| format!("{}:{}", Value::from(*id), Value::from(*v))).collect();
+                format!("{{\"paths\":{},\"filter\":{},\"choices\":{{{}}},\"cancelled\":{},\"overwrite\":{}}}\n",
+                    Value::from(sel.paths), Value::from(sel.filter), choices.join(","), sel.paths.is_empty(), sel.overwrite)
+            },
+        }
+    }
+}
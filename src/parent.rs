@@ -0,0 +1,43 @@
+use iced::window::raw_window_handle::{RawWindowHandle, WindowHandle};
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{AtomEnum, PropMode},
+    wrapper::ConnectionExt,
+};
+
+/// Window of the app that opened the picker, in the xdg portal's "x11:XID" or "wayland:HANDLE" format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Parent {
+    X11(u32),
+    Wayland(String),
+}
+
+impl Parent {
+    pub fn parse(handle: &str) -> Option<Self> {
+        match handle.split_once(':')? {
+            ("x11", xid) => u32::from_str_radix(xid.trim_start_matches("0x"), 16).ok().map(Parent::X11),
+            ("wayland", h) if !h.is_empty() => Some(Parent::Wayland(h.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Set WM_TRANSIENT_FOR on the picker window so the window manager keeps it above the parent.
+pub fn set_transient_for(handle: &WindowHandle<'_>, parent: u32) {
+    let window = match handle.as_raw() {
+        RawWindowHandle::Xlib(h) => h.window as u32,
+        RawWindowHandle::Xcb(h) => h.window.get(),
+        _ => {
+            eprintln!("Parent window is on X11 but the picker isn't");
+            return;
+        },
+    };
+    let res = x11rb::connect(None).map_err(|e| e.to_string()).and_then(|(conn, _)| {
+        conn.change_property32(PropMode::REPLACE, window, AtomEnum::WM_TRANSIENT_FOR, AtomEnum::WINDOW, &[parent])
+            .map_err(|e| e.to_string())?;
+        conn.flush().map_err(|e| e.to_string())
+    });
+    if let Err(e) = res {
+        eprintln!("Could not set parent window: {}", e);
+    }
+}
@@ -2,31 +2,40 @@
 //https://docs.rs/zbus/latest/zbus/index.html
 use getopts::Options;
 use zbus::{
-    connection, interface,
-    zvariant::{Value,OwnedValue,ObjectPath,
-    to_bytes,LE,serialized::Context
-    }
+    connection, interface, ObjectServer, SignalContext,
+    zvariant::{Value,OwnedValue,ObjectPath}
 };
 use std::{
     fs,
     error::Error, future::pending,
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     borrow::Cow,
-    path::Path,
+    ffi::{OsStr, OsString},
+    os::unix::{ffi::{OsStrExt, OsStringExt}, fs::{DirBuilderExt, OpenOptionsExt}},
+    path::{Path, PathBuf},
     mem::take,
-    sync::Arc,
+    process::Stdio,
+    sync::{Arc, atomic::{AtomicU32, Ordering as AtomicOrdering}},
 };
 use std::time::SystemTime;
 use rusqlite;
 use std::cmp::Ordering;
 use tokio::{
-    sync::Mutex as AsyncMtx,
-    time::{sleep, Duration, Instant},
+    io::unix::AsyncFd,
+    sync::{Mutex as AsyncMtx, Notify, Semaphore},
+    task::JoinSet,
+    time::{sleep, sleep_until, Duration, Instant},
 };extern crate chrono;
 mod logger;
+mod http_indexer;
+// Shared with pikeru, the portal builds its own matcher in update_ignore
+#[allow(dead_code)]
+mod search_ignore;
+use crate::search_ignore::SearchIgnore;
 use crate::logger::{LevelFilter, Builder};
 use ctrlc;
 use ignore::{gitignore,Match};
+use inotify::{Inotify, WatchMask, WatchDescriptor, EventMask};
 
 
 #[derive(Default, Debug)]
@@ -63,15 +72,111 @@ fn build_cumulative_gitignore(start_dir: &Path) -> gitignore::Gitignore {
 struct IndexerInner {
     shtate: Arc<AsyncMtx<Shtate>>,
     con: Arc<std::sync::Mutex<rusqlite::Connection>>,
+    /// Queued dirs and whether they're done. The index_queue table mirrors it so the queue survives restarts.
     done_map: HashMap<String,bool>,
-    cmd: String,
-    check: String,
-    exts: Vec<&'static str>,
+    /// Dirs that changed while they were being indexed, to go through again.
+    requeued: HashSet<String>,
+    /// Indexer commands in the order they're tried for a file's extension.
+    routes: Vec<Route>,
+    /// Command that turns a file, or text after --text, into a vector. Empty when not configured.
+    embed: String,
     search_ignore: gitignore::Gitignore,
     igtxt: String,
+    /// Bumped by ClearQueue so trees still being walked for UpdateRecursive are dropped.
+    walks: Arc<AtomicU32>,
     idx_running: bool,
     indexer_enabled: bool,
     respect_gitignore: bool,
+    /// Progress of the current batch, reported by GetStatus and the Progress signal.
+    current_dir: String,
+    files_done: u32,
+    files_failed: u32,
+    schedule: Schedule,
+    /// Failed attempts at an unchanged file before it's skipped until it changes.
+    max_attempts: u32,
+    /// Set by Pause, so no new files are started until Resume.
+    paused: bool,
+    /// Wakes files waiting on Pause when Resume is called.
+    resumed: Arc<Notify>,
+    /// When the next file may start under max_files_per_minute.
+    next_turn: Instant,
+    /// Where signals are emitted, set once the object is on the bus.
+    signals: Option<SignalContext<'static>>,
+}
+
+/// An indexer command and what the portal keeps track of for it.
+struct Route {
+    conf: IndexerCmd,
+    /// One permit per file allowed to be indexed at once.
+    workers: Arc<Semaphore>,
+    /// Whether the check command passed when last run.
+    online: bool,
+}
+
+impl IndexerInner {
+    /// The indexer for files with this lowercase extension.
+    fn route(&self, ext: &str) -> Option<usize> {
+        self.routes.iter().position(|r| r.conf.exts.iter().any(|e| e == ext))
+    }
+
+    fn online(&self) -> bool {
+        self.routes.iter().all(|r| r.online)
+    }
+
+    fn store_queued(&self, dirs: &[String]) {
+        let c = self.con.lock().unwrap();
+        // One transaction, since a recursive update can queue thousands of dirs
+        let tx = match c.unchecked_transaction() {
+            Ok(tx) => tx,
+            Err(e) => {
+                error!("sqlite error: {}", e);
+                return;
+            },
+        };
+        for dir in dirs {
+            if let Err(e) = tx.execute("insert or ignore into index_queue (dir) values (?1)", [dir]) {
+                error!("sqlite error: {}", e);
+            }
+        }
+        if let Err(e) = tx.commit() {
+            error!("sqlite error: {}", e);
+        }
+    }
+
+    fn store_undone(&self, dir: &str) {
+        if let Err(e) = self.con.lock().unwrap().execute("update index_queue set done = 0 where dir = ?1", [dir]) {
+            error!("sqlite error: {}", e);
+        }
+    }
+
+    fn store_done(&self, dir: &str) {
+        if let Err(e) = self.con.lock().unwrap().execute("update index_queue set done = 1 where dir = ?1", [dir]) {
+            error!("sqlite error: {}", e);
+        }
+    }
+
+    fn clear_stored_queue(&self) {
+        if let Err(e) = self.con.lock().unwrap().execute("delete from index_queue", ()) {
+            error!("sqlite error: {}", e);
+        }
+    }
+
+    fn queued(&self) -> u32 {
+        self.done_map.values().filter(|done| !**done).count() as u32
+    }
+
+    fn load_stored_queue(&self) -> HashMap<String,bool> {
+        let c = self.con.lock().unwrap();
+        let mut query = c.prepare("select dir, done from index_queue").unwrap();
+        let rows = query.query_map((), |r| Ok((r.get(0)?, r.get(1)?)));
+        match rows {
+            Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
+            Err(e) => {
+                error!("sqlite error: {}", e);
+                HashMap::new()
+            },
+        }
+    }
 }
 
 /// Thin handle wrapper — like FItem(Box<FItemb>) pattern.
@@ -93,7 +198,10 @@ impl Indexer {
         let mut inner = self.write().await;
         if !inner.indexer_enabled { return; }
         inner.idx_running = false;
+        inner.walks.fetch_add(1, AtomicOrdering::Relaxed);
         inner.done_map.clear();
+        inner.requeued.clear();
+        inner.clear_stored_queue();
         info!("Cleared indexing queue");
     }
     async fn update(&self, dirs: Vec<String>) {
@@ -109,7 +217,12 @@ impl Indexer {
             if should_spawn {
                 // Fresh loop — start with a clean done_map populated by the new dirs.
                 inner.done_map.clear();
+                inner.requeued.clear();
+                inner.clear_stored_queue();
+                inner.files_done = 0;
+                inner.files_failed = 0;
             }
+            inner.store_queued(&dirs);
             for dir in dirs { inner.done_map.entry(dir).or_default(); }
         }
         // Spawn a new loop only if one wasn't already running.
@@ -120,6 +233,28 @@ impl Indexer {
             });
         }
     }
+    /// Queue dirs even if the running batch already indexed them, for files whose failures were cleared.
+    async fn retry(&self, dirs: Vec<String>) {
+        self.requeue(dirs).await;
+    }
+    /// Queue root and the dirs below it, at most max_depth levels down or all of them for 0.
+    /// The tree is walked in the background, skipping the same dirs as watch_roots, until ClearQueue.
+    async fn update_recursive(&self, root: String, max_depth: u32) {
+        if !self.read().await.indexer_enabled {
+            return;
+        }
+        let walk = self.read().await.walks.load(AtomicOrdering::Relaxed);
+        let this = self.clone();
+        tokio::spawn(async move {
+            let Some(dirs) = this.walk_tree(Path::new(&root), max_depth, Some(walk)).await else {
+                debug!("Stopped walking {} for a cleared queue", root);
+                return;
+            };
+            debug!("Found {} dirs under {}", dirs.len(), root);
+            this.update(dirs).await;
+        });
+    }
+
     async fn configure(&self, respect_gitignore: bool, search_ignore: String) {
         trace!("Got gitignore configure request: {}", search_ignore);
         // Update indexer-local state in a scoped block so the lock is released
@@ -132,7 +267,82 @@ impl Indexer {
         // Rebuild the ignore matcher.
         self.update_ignore().await;
     }
+    /// Dirs left to index, the dir being indexed, files indexed and failed in this batch,
+    /// and whether the indexer was online when last checked.
+    async fn get_status(&self) -> (u32, String, u32, u32, bool) {
+        let inner = self.read().await;
+        (inner.queued(), inner.current_dir.clone(), inner.files_done, inner.files_failed, inner.online())
+    }
+
+    /// The embed command's vector for text, to compare with the stored file embeddings.
+    async fn embed(&self, text: String) -> zbus::fdo::Result<Vec<f64>> {
+        let embed = self.read().await.embed.clone();
+        if embed.is_empty() {
+            return Err(zbus::fdo::Error::NotSupported("No embed command configured".to_string()));
+        }
+        match run_embed(&embed, &["--text", &text]).await {
+            Ok(vector) => Ok(vector.into_iter().map(f64::from).collect()),
+            Err(e) => Err(zbus::fdo::Error::Failed(e)),
+        }
+    }
+
+    /// Stop starting new files until Resume. Files already being indexed are finished.
+    async fn pause(&self) {
+        self.write().await.paused = true;
+        info!("Indexing paused");
+        self.notify_paused().await;
+    }
+
+    async fn resume(&self) {
+        let resumed = {
+            let mut inner = self.write().await;
+            inner.paused = false;
+            inner.resumed.clone()
+        };
+        resumed.notify_waiters();
+        info!("Indexing resumed");
+        self.notify_paused().await;
+    }
+
+    #[zbus(property)]
+    async fn paused(&self) -> bool {
+        self.read().await.paused
+    }
+
+    /// Files the indexer failed on: path, indexer name, exit code or -1, error, attempts including
+    /// those that took the indexer offline, unix time of the last attempt, and whether it's skipped until the file changes.
+    async fn get_failures(&self) -> Vec<(String, String, i32, String, u32, i64, bool)> {
+        let guard = self.read().await;
+        let c = guard.con.lock().unwrap();
+        let mut query = c.prepare("select dir, fname, mtime, indexer_name, exit_code, error, attempts, offline_attempts, last_attempt
+                                   from failures order by last_attempt desc, dir, fname").unwrap();
+        let rows = query.query_map((), |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, i64>(2)?,
+            r.get::<_, Option<String>>(3)?, r.get::<_, Option<i32>>(4)?, r.get::<_, String>(5)?, (r.get::<_, u32>(6)?, r.get::<_, u32>(7)?), r.get::<_, i64>(8)?)));
+        match rows {
+            Ok(rows) => rows.filter_map(|r| r.ok()).map(|(dir, fname, mtime, name, code, error, (attempts, offline), last)| {
+                let path = Path::new(&dir).join(&fname);
+                let unchanged = path.metadata().is_ok_and(|md| mtime_nanos(&md) == mtime);
+                (path.to_string_lossy().to_string(), name.unwrap_or_default(), code.unwrap_or(-1), error,
+                 attempts + offline, last, unchanged && attempts.max(offline) >= guard.max_attempts)
+            }).collect(),
+            Err(e) => {
+                error!("sqlite error: {}", e);
+                vec![]
+            },
+        }
+    }
+
+    /// Sent after each file, and with an empty dir when a batch ends.
+    #[zbus(signal)]
+    async fn progress(ctxt: &SignalContext<'_>, dir: &str, files_done: u32, files_failed: u32, queued: u32) -> zbus::Result<()>;
+
+    /// Sent when every file in dir has been indexed.
+    #[zbus(signal)]
+    async fn directory_indexed(ctxt: &SignalContext<'_>, dir: &str) -> zbus::Result<()>;
 
+    /// Sent when the named indexer's check command starts failing.
+    #[zbus(signal)]
+    async fn indexer_offline(ctxt: &SignalContext<'_>, name: &str) -> zbus::Result<()>;
 }
 
 impl Indexer {
@@ -149,8 +359,12 @@ impl Indexer {
             let uptodate = timeout.cmp(&Instant::now()) == Ordering::Greater;
             if !uptodate {
                 timeout = timeout.checked_add(Duration::from_secs(60)).unwrap();
-                let online = self.indexer_online().await;
-                if !online { warn!("indexer offline"); }
+                let routes = self.read().await.routes.len();
+                for route in 0..routes {
+                    if !self.indexer_online(route).await {
+                        warn!("{} indexer offline", self.read().await.routes[route].conf.name);
+                    }
+                }
             }
             if !self.read().await.idx_running {
                 debug!("index_loop: idx_running cleared, exiting");
@@ -161,33 +375,55 @@ impl Indexer {
                 .find(|(_, done)| !**done)
                 .map(|(dir, _)| dir.clone());
             if let Some(dir) = maybe_dir {
+                self.write().await.current_dir = dir.clone();
                 let result = self.update_dir(&dir).await;
                 if result == DirResult::Fail {
                     error!("Indexing batch failed");
-                    let mut inner = self.write().await;
-                    inner.done_map.clear();
-                    inner.idx_running = false;
+                    {
+                        let mut inner = self.write().await;
+                        inner.done_map.clear();
+                        inner.clear_stored_queue();
+                        inner.idx_running = false;
+                        inner.current_dir.clear();
+                    }
+                    self.emit_progress().await;
                     break;
                 }
-                // Mark this dir as done.
-                self.write().await.done_map.entry(dir).and_modify(|v| *v = true);
+                // Mark this dir as done, unless files changed in it while it was being indexed.
+                {
+                    let mut inner = self.write().await;
+                    if !inner.requeued.remove(&dir) {
+                        inner.store_done(&dir);
+                        inner.done_map.entry(dir.clone()).and_modify(|v| *v = true);
+                    }
+                }
+                if result == DirResult::Success {
+                    if let Some(ctxt) = self.signal_context().await {
+                        if let Err(e) = Indexer::directory_indexed(&ctxt, &dir).await {
+                            warn!("Could not send DirectoryIndexed: {}", e);
+                        }
+                    }
+                }
             } else {
                 debug!("Indexing batch finished");
-                let mut inner = self.write().await;
-                inner.done_map.clear();
-                inner.idx_running = false;
+                {
+                    let mut inner = self.write().await;
+                    inner.done_map.clear();
+                    inner.clear_stored_queue();
+                    inner.idx_running = false;
+                    inner.current_dir.clear();
+                }
+                self.emit_progress().await;
                 break;
             }
         }
     }
 
     fn new(shtate: Arc<AsyncMtx<Shtate>>, config: &mut Config, con: Arc<std::sync::Mutex<rusqlite::Connection>>) -> Self {
-        { let c = con.lock().unwrap();
-            match c.execute("create table if not exists descriptions
-                          (fname text, dir text, description text, mtime real);", ()) {
-             Ok(_) => {},
-             Err(e) => eprintln!("{}", e),
-            };
+        { let mut c = con.lock().unwrap();
+            if let Err(e) = migrate(&mut c, &config.indexers) {
+                eprintln!("Failed to migrate index db: {}", e);
+            }
             let _ = c.pragma_update(None, "journal_mode", "WAL");
         }
         let con2 = con.clone();
@@ -202,17 +438,114 @@ impl Indexer {
             shtate,
             con,
             done_map: HashMap::new(),
-            cmd: take(&mut config.indexer_cmd),
-            check: take(&mut config.indexer_check),
-            exts: Box::new(take(&mut config.indexer_exts)).leak().split(',').collect(),
+            requeued: HashSet::new(),
+            routes: take(&mut config.indexers).into_iter().map(|conf| Route {
+                workers: Arc::new(Semaphore::new(conf.workers)),
+                conf,
+                online: true,
+            }).collect(),
+            embed: take(&mut config.indexer_embed),
             search_ignore: gitignore::Gitignore::new("").0,
             igtxt: String::new(),
+            walks: Arc::new(AtomicU32::new(0)),
             idx_running: false,
             indexer_enabled: config.indexer_enabled,
             respect_gitignore: true,
+            current_dir: String::new(),
+            files_done: 0,
+            files_failed: 0,
+            schedule: config.indexer_schedule.clone(),
+            max_attempts: config.indexer_max_attempts,
+            paused: false,
+            resumed: Arc::new(Notify::new()),
+            next_turn: Instant::now(),
+            signals: None,
         })))
     }
 
+    async fn set_signal_context(&self, ctxt: SignalContext<'static>) {
+        self.write().await.signals = Some(ctxt);
+    }
+
+    async fn signal_context(&self) -> Option<SignalContext<'static>> {
+        self.read().await.signals.clone()
+    }
+
+    async fn notify_paused(&self) {
+        if let Some(ctxt) = self.signal_context().await {
+            if let Err(e) = self.paused_changed(&ctxt).await {
+                warn!("Could not send Paused change: {}", e);
+            }
+        }
+    }
+
+    /// Wait while indexing is paused or the schedule holds it back.
+    async fn wait_until_allowed(&self) {
+        let mut held = None;
+        loop {
+            let (reason, resumed) = {
+                let inner = self.read().await;
+                let reason = if inner.paused { Some("paused") } else { inner.schedule.hold() };
+                (reason, inner.resumed.clone())
+            };
+            let Some(reason) = reason else { break };
+            if held != Some(reason) {
+                info!("Indexing waits: {}", reason);
+                held = Some(reason);
+            }
+            tokio::select! {
+                _ = resumed.notified() => {},
+                _ = sleep(SCHEDULE_POLL) => {},
+            }
+        }
+    }
+
+    /// Wait until a new file may be indexed, spacing files out under max_files_per_minute.
+    async fn wait_for_turn(&self) {
+        self.wait_until_allowed().await;
+        let turn = {
+            let mut inner = self.write().await;
+            let Some(interval) = inner.schedule.interval else { return };
+            let turn = inner.next_turn.max(Instant::now());
+            inner.next_turn = turn + interval;
+            turn
+        };
+        sleep_until(turn).await;
+    }
+
+    async fn emit_progress(&self) {
+        let (dir, done, failed, queued) = {
+            let inner = self.read().await;
+            (inner.current_dir.clone(), inner.files_done, inner.files_failed, inner.queued())
+        };
+        if let Some(ctxt) = self.signal_context().await {
+            if let Err(e) = Indexer::progress(&ctxt, &dir, done, failed, queued).await {
+                warn!("Could not send Progress: {}", e);
+            }
+        }
+    }
+
+    /// Pick up the queue a previous portal left unfinished.
+    async fn resume_stored_queue(&self) {
+        let mut inner = self.write().await;
+        if !inner.indexer_enabled {
+            return;
+        }
+        let queue = inner.load_stored_queue();
+        if queue.values().all(|done| *done) {
+            inner.clear_stored_queue();
+            return;
+        }
+        info!("Resuming indexing queue of {} dirs", queue.values().filter(|done| !**done).count());
+        inner.done_map = queue;
+        inner.idx_running = true;
+        drop(inner);
+        let this = self.clone();
+        tokio::spawn(async move {
+            this.index_loop().await;
+        });
+    }
+
     async fn update_ignore(self: &Self) {
         let inner = self.read().await;
         let txt = inner.shtate.lock().await.current_searchignore.clone();
@@ -234,22 +567,41 @@ impl Indexer {
         inner.search_ignore = ignore;
     }
 
-    async fn indexer_online(&self) -> bool {
-        match tokio::process::Command::new("sh").arg("-c").arg(&self.read().await.check).output().await {
-            Ok(out) => out.status.success(),
-            Err(_) => false,
+    async fn indexer_online(&self, route: usize) -> bool {
+        let conf = self.read().await.routes[route].conf.clone();
+        let online = if conf.http && conf.check.is_empty() {
+            match http_indexer::parse_url(&conf.url) {
+                Ok(url) => http_indexer::reachable(&url).await,
+                Err(_) => false,
+            }
+        } else {
+            match tokio::process::Command::new("sh").arg("-c").arg(&conf.check).output().await {
+                Ok(out) => out.status.success(),
+                Err(_) => false,
+            }
+        };
+        let was_online = std::mem::replace(&mut self.write().await.routes[route].online, online);
+        if was_online && !online {
+            if let Some(ctxt) = self.signal_context().await {
+                if let Err(e) = Indexer::indexer_offline(&ctxt, &conf.name).await {
+                    warn!("Could not send IndexerOffline: {}", e);
+                }
+            }
         }
+        online
     }
 
-    async fn already_done(self: &Self, dir: &String, fname: &str, mtime: f32) -> Entry {
+    async fn already_done(self: &Self, dir: &String, fname: &str, mtime: i64, cmd: &str) -> Entry {
         let guard = self.read().await;
         let c = guard.con.lock().unwrap();
-        let mut query = c.prepare("select mtime from descriptions where dir = ?1 and fname = ?2").unwrap();
+        let mut query = c.prepare("select mtime, indexer from descriptions where dir = ?1 and fname = ?2").unwrap();
         let ret = match query.query([dir.as_str(), fname.as_ref()]).unwrap().next() {
             Ok(q) => match q {
                 Some(r) => {
-                    let prev_time: f32 = r.get(0).unwrap();
-                    match prev_time == mtime {
+                    let prev_time: i64 = r.get(0).unwrap();
+                    // Descriptions from another indexer command are stale, unknown ones are kept
+                    let indexer: Option<String> = r.get(1).unwrap();
+                    match prev_time == mtime && indexer.is_none_or(|i| i == cmd) {
                         true => Entry::Done,
                         false => Entry::Old,
                     }
@@ -264,19 +616,352 @@ impl Indexer {
         ret
     }
 
-    async fn save(self: &Self, dir: &String, fname: &str, desc: &str, mtime: f32, stat: Entry) {
+    async fn save(self: &Self, dir: &String, fname: &str, desc: &str, mtime: i64, size: u64, conf: &IndexerCmd) {
         let guard = self.write().await;
         let c = guard.con.lock().unwrap();
-        let mut query = c.prepare(match stat {
-            Entry::None => "insert into descriptions (dir, fname, description, mtime) values (?1, ?2, ?3, ?4)",
-            Entry::Old => "update descriptions set description = ?3, mtime = ?4 where dir = ?1 and fname = ?2",
-            Entry::Done => unreachable!(),
-        }).unwrap();
-        query.execute((dir, fname, desc, mtime)).unwrap();
+        let mut query = c.prepare("insert into descriptions (dir, fname, description, mtime, size, indexer, indexer_name)
+                                   values (?1, ?2, ?3, ?4, ?5, ?6, ?7) on conflict (dir, fname) do update
+                                   set description = ?3, mtime = ?4, size = ?5, indexer = ?6, indexer_name = ?7").unwrap();
+        query.execute((dir, fname, desc, mtime, size, conf.id(), &conf.name)).unwrap();
+        if let Err(e) = c.execute("delete from failures where dir = ?1 and fname = ?2", (dir, fname)) {
+            error!("sqlite error: {}", e);
+        }
+    }
+
+    /// Whether the indexer has failed on this version of the file as many times as it may.
+    async fn quarantined(&self, dir: &String, fname: &str, mtime: i64, cmd: &str) -> bool {
+        let guard = self.read().await;
+        let c = guard.con.lock().unwrap();
+        let attempts = c.query_row("select max(attempts, offline_attempts) from failures where dir = ?1 and fname = ?2 and mtime = ?3 and indexer = ?4",
+                                   (dir, fname, mtime, cmd), |r| r.get::<_, u32>(0));
+        attempts.is_ok_and(|a| a >= guard.max_attempts)
+    }
+
+    /// Count a failed attempt at the file, starting over if it or the indexer changed since the last one.
+    /// Attempts that left the indexer offline are counted apart from the ones it was still online after.
+    /// Returns the attempts so far of the same kind.
+    async fn record_failure(&self, dir: &String, fname: &str, mtime: i64, conf: &IndexerCmd, failure: &Failure, online: bool) -> u32 {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        let guard = self.write().await;
+        let c = guard.con.lock().unwrap();
+        let (counted, offline) = if online { (1, 0) } else { (0, 1) };
+        let attempts = c.query_row("insert into failures (dir, fname, mtime, indexer, indexer_name, exit_code, error, attempts, offline_attempts, last_attempt)
+                                    values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10) on conflict (dir, fname) do update
+                                    set attempts = case when mtime = ?3 and indexer = ?4 then attempts else 0 end + ?8,
+                                    offline_attempts = case when mtime = ?3 and indexer = ?4 then offline_attempts else 0 end + ?9,
+                                    mtime = ?3, indexer = ?4, indexer_name = ?5, exit_code = ?6, error = ?7, last_attempt = ?10
+                                    returning case when ?8 then attempts else offline_attempts end",
+                                   (dir, fname, mtime, conf.id(), &conf.name, failure.code, &failure.error, counted, offline, now), |r| r.get(0));
+        match attempts {
+            Ok(attempts) => attempts,
+            Err(e) => {
+                error!("sqlite error: {}", e);
+                1
+            },
+        }
+    }
+
+    /// Move the row of a missing file with the same size and mtime to this file,
+    /// since it's most likely the same file renamed or moved. Returns whether one was found.
+    async fn follow_rename(&self, dir: &String, fname: &str, mtime: i64, size: u64, cmd: &str) -> bool {
+        let candidates: Vec<(i64, String, String)> = {
+            let guard = self.read().await;
+            let c = guard.con.lock().unwrap();
+            let mut query = c.prepare("select rowid, dir, fname from descriptions
+                                       where size = ?1 and mtime = ?2 and (indexer is null or indexer = ?3)").unwrap();
+            let rows = match query.query_map((size, mtime, cmd), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?))) {
+                Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
+                Err(e) => {
+                    error!("sqlite error: {}", e);
+                    return false;
+                },
+            };
+            rows
+        };
+        // Checked without holding the locks, since the disk may be slow
+        let Some((rowid, old_dir, old_fname)) = candidates.into_iter()
+            .find(|(_, d, f)| !Path::new(d).join(f).exists()) else {
+            return false;
+        };
+        debug!("{}/{} was renamed to {}/{}", old_dir, old_fname, dir, fname);
+        let guard = self.write().await;
+        let c = guard.con.lock().unwrap();
+        // Unless another worker moved it meanwhile
+        match c.execute("update descriptions set dir = ?1, fname = ?2 where rowid = ?3 and dir = ?4 and fname = ?5",
+                        (dir, fname, rowid, &old_dir, &old_fname)) {
+            Ok(n) => n > 0,
+            Err(e) => {
+                error!("sqlite error: {}", e);
+                false
+            },
+        }
+    }
+
+    /// Embed an indexed file if there's an embed command and the file has no vector from it yet.
+    async fn ensure_embedding(&self, path: &Path, dir: &String, fname: &str) {
+        let (embed, id) = {
+            let guard = self.read().await;
+            if guard.embed.is_empty() {
+                return;
+            }
+            let c = guard.con.lock().unwrap();
+            let row = c.query_row("select d.id, e.embedder from descriptions d left join embeddings e on e.id = d.id
+                                   where d.dir = ?1 and d.fname = ?2", (dir, fname),
+                                  |r| Ok((r.get::<_, i64>(0)?, r.get::<_, Option<String>>(1)?)));
+            match row {
+                Ok((_, Some(embedder))) if embedder == guard.embed => return,
+                Ok((id, _)) => (guard.embed.clone(), id),
+                Err(e) => {
+                    error!("sqlite error: {}", e);
+                    return;
+                },
+            }
+        };
+        self.wait_until_allowed().await;
+        let vector = match run_embed(&embed, &[path.to_string_lossy().as_ref()]).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Embedding {:?} failed: {}", path, e);
+                return;
+            },
+        };
+        let blob: Vec<u8> = vector.iter().flat_map(|f| f.to_le_bytes()).collect();
+        let guard = self.write().await;
+        let c = guard.con.lock().unwrap();
+        if let Err(e) = c.execute("insert or replace into embeddings (id, vector, embedder) values (?1, ?2, ?3)", (id, blob, &embed)) {
+            error!("sqlite error: {}", e);
+        }
+    }
+
+    /// Delete the rows of files in dir that no longer exist.
+    async fn prune_dir(&self, dir: &String) {
+        for table in ["descriptions", "failures"] {
+            let fnames: Vec<String> = {
+                let guard = self.read().await;
+                let c = guard.con.lock().unwrap();
+                let mut query = c.prepare(&format!("select fname from {} where dir = ?1", table)).unwrap();
+                let rows = match query.query_map([dir], |r| r.get(0)) {
+                    Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
+                    Err(e) => {
+                        error!("sqlite error: {}", e);
+                        return;
+                    },
+                };
+                rows
+            };
+            // Checked without holding the locks, since the disk may be slow
+            let gone: Vec<String> = fnames.into_iter().filter(|f| !Path::new(dir).join(f).exists()).collect();
+            if gone.is_empty() {
+                continue;
+            }
+            let guard = self.write().await;
+            let c = guard.con.lock().unwrap();
+            for fname in &gone {
+                if let Err(e) = c.execute(&format!("delete from {} where dir = ?1 and fname = ?2", table), (dir, fname)) {
+                    error!("sqlite error: {}", e);
+                }
+            }
+            debug!("Pruned {} deleted files from {} in {}", gone.len(), table, dir);
+        }
+    }
+
+    /// Delete rows of deleted files from table, descriptions or failures, checking at most
+    /// limit rows after rowid `after`. Rows whose whole dir is missing are kept since it may be
+    /// an unmounted drive. Returns where the next sweep should start.
+    async fn gc_sweep(&self, table: &str, after: i64, limit: u32) -> i64 {
+        let rows: Vec<(i64, String, String)> = {
+            let guard = self.read().await;
+            let c = guard.con.lock().unwrap();
+            let mut query = c.prepare(&format!("select rowid, dir, fname from {} where rowid > ?1 order by rowid limit ?2", table)).unwrap();
+            let rows = match query.query_map((after, limit), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?))) {
+                Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
+                Err(e) => {
+                    error!("sqlite error: {}", e);
+                    return 0;
+                },
+            };
+            rows
+        };
+        let next = if rows.len() < limit as usize { 0 } else { rows.last().map_or(0, |r| r.0) };
+        let gone: Vec<i64> = rows.into_iter()
+            .filter(|(_, d, f)| Path::new(d).is_dir() && !Path::new(d).join(f).exists())
+            .map(|(rowid, _, _)| rowid).collect();
+        if !gone.is_empty() {
+            let guard = self.write().await;
+            let c = guard.con.lock().unwrap();
+            for rowid in &gone {
+                if let Err(e) = c.execute(&format!("delete from {} where rowid = ?1", table), [rowid]) {
+                    error!("sqlite error: {}", e);
+                }
+            }
+            debug!("Swept {} deleted files from {}", gone.len(), table);
+        }
+        next
+    }
+
+    /// Queue dirs even if this batch already indexed them, because their files changed since.
+    async fn requeue(&self, dirs: Vec<String>) {
+        {
+            let mut inner = self.write().await;
+            if !inner.indexer_enabled { return; }
+            if inner.idx_running {
+                for dir in &dirs {
+                    if *dir == inner.current_dir {
+                        inner.requeued.insert(dir.clone());
+                    } else if let Some(done) = inner.done_map.get_mut(dir) {
+                        *done = false;
+                        inner.store_undone(dir);
+                    }
+                }
+            }
+        }
+        self.update(dirs).await;
+    }
+
+    /// Index everything under roots and keep indexing files as they're created or changed there.
+    async fn watch_loop(self, roots: Vec<String>) {
+        let mut ino = match Inotify::init().and_then(AsyncFd::new) {
+            Ok(ino) => ino,
+            Err(e) => {
+                error!("Can't watch {}: {}", roots.join(", "), e);
+                return;
+            },
+        };
+        let mut watches = HashMap::<WatchDescriptor, String>::new();
+        let mut dirs = vec![];
+        for root in &roots {
+            self.watch_tree(ino.get_ref(), &mut watches, Path::new(root), &mut dirs).await;
+        }
+        info!("Watching {} dirs under {}", watches.len(), roots.join(", "));
+        self.requeue(dirs).await;
+
+        let mut buf = [0; 4096];
+        let mut changed = HashSet::<String>::new();
+        let mut deadline = Instant::now();
+        loop {
+            let events: Vec<(WatchDescriptor, EventMask, Option<std::ffi::OsString>)> = tokio::select! {
+                guard = ino.readable_mut() => {
+                    let mut guard = match guard {
+                        Ok(guard) => guard,
+                        Err(e) => { error!("inotify error: {}", e); return; },
+                    };
+                    match guard.get_inner_mut().read_events(&mut buf) {
+                        Ok(events) => events.map(|e| (e.wd, e.mask, e.name.map(|n| n.to_owned()))).collect(),
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            guard.clear_ready();
+                            continue;
+                        },
+                        Err(e) => { error!("inotify error: {}", e); return; },
+                    }
+                },
+                // Wait a moment so a burst of new files queues each dir once.
+                _ = sleep_until(deadline), if !changed.is_empty() => {
+                    self.requeue(changed.drain().collect()).await;
+                    continue;
+                },
+            };
+            if changed.is_empty() {
+                deadline = Instant::now() + WATCH_DELAY;
+            }
+            for (wd, mask, name) in events {
+                if mask.contains(EventMask::Q_OVERFLOW) {
+                    warn!("Missed some file changes, checking every watched dir");
+                    changed.extend(watches.values().cloned());
+                    continue;
+                }
+                if mask.intersects(EventMask::IGNORED | EventMask::MOVE_SELF) {
+                    if let Some(dir) = watches.remove(&wd) {
+                        trace!("Stopped watching {}", dir);
+                        let _ = ino.get_ref().watches().remove(wd);
+                    }
+                    continue;
+                }
+                let (Some(dir), Some(name)) = (watches.get(&wd), name) else { continue };
+                let path = Path::new(dir).join(name);
+                if mask.contains(EventMask::ISDIR) {
+                    let mut dirs = vec![];
+                    self.watch_tree(ino.get_ref(), &mut watches, &path, &mut dirs).await;
+                    changed.extend(dirs);
+                } else if mask.intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO) {
+                    // Files are only looked at once they're written, not as soon as they're created.
+                    changed.insert(dir.clone());
+                }
+            }
+        }
+    }
+
+    /// Watch root and the dirs below it that aren't hidden or ignored, adding them to dirs.
+    async fn watch_tree(&self, ino: &Inotify, watches: &mut HashMap<WatchDescriptor, String>, root: &Path, dirs: &mut Vec<String>) {
+        for dir in self.walk_tree(root, 0, None).await.unwrap_or_default() {
+            let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE | WatchMask::MOVE_SELF | WatchMask::ONLYDIR;
+            match ino.watches().add(&dir, mask) {
+                Ok(wd) => { watches.insert(wd, dir.clone()); },
+                Err(e) => warn!("Can't watch {:?}: {}", dir, e),
+            }
+            dirs.push(dir);
+        }
+    }
+
+    /// Root and the dirs below it, nearest first, down to max_depth levels or all of them for 0.
+    /// Hidden, search-ignored and gitignored dirs are skipped along with everything under them.
+    /// Walked on a blocking thread, and given the walk generation it stops with None once the queue is cleared.
+    async fn walk_tree(&self, root: &Path, max_depth: u32, walk: Option<u32>) -> Option<Vec<String>> {
+        let (respect_gitignore, search_ignore, walks) = {
+            let inner = self.read().await;
+            (inner.respect_gitignore, inner.search_ignore.clone(), inner.walks.clone())
+        };
+        let root = root.to_path_buf();
+        let walked = tokio::task::spawn_blocking(move || {
+            let mut dirs = vec![];
+            let mut queue = VecDeque::from([(root, 0)]);
+            while let Some((dir, depth)) = queue.pop_front() {
+                if walk.is_some_and(|w| w != walks.load(AtomicOrdering::Relaxed)) {
+                    return None;
+                }
+                if let Match::Ignore(_) = search_ignore.matched(&dir, true) {
+                    continue;
+                }
+                if respect_gitignore {
+                    if let Match::Ignore(_) = build_cumulative_gitignore(&dir).matched(&dir, true) {
+                        continue;
+                    }
+                }
+                dirs.push(dir.to_string_lossy().to_string());
+                if max_depth != 0 && depth >= max_depth {
+                    continue;
+                }
+                let Ok(read_dir) = std::fs::read_dir(&dir) else { continue };
+                for entry in read_dir.filter_map(|e| e.ok()) {
+                    let hidden = entry.file_name().as_encoded_bytes().starts_with(b".");
+                    if !hidden && entry.file_type().is_ok_and(|t| t.is_dir()) {
+                        queue.push_back((entry.path(), depth + 1));
+                    }
+                }
+            }
+            Some(dirs)
+        }).await;
+        walked.unwrap_or_else(|e| {
+            error!("Walking the tree failed: {}", e);
+            Some(vec![])
+        })
+    }
+
+    /// Sweep the whole index a batch at a time, skipping turns while a batch is indexing
+    /// so rows that are about to be followed to a rename aren't deleted.
+    async fn gc_loop(self, interval: Duration) {
+        let (mut after, mut failed_after) = (0, 0);
+        loop {
+            sleep(interval).await;
+            if self.read().await.idx_running {
+                continue;
+            }
+            after = self.gc_sweep("descriptions", after, GC_BATCH).await;
+            failed_after = self.gc_sweep("failures", failed_after, GC_BATCH).await;
+        }
     }
 
     /// returns online status if file exists, otherwise true to keep going
-    async fn update_file(self: &Self, path: &Path, dir: &String) -> bool {
+    async fn update_file(self: &Self, path: &Path, dir: &String, route: usize) -> bool {
         let metadata = match path.metadata() {
             Ok(md) => md,
             Err(_) => {
@@ -284,28 +969,58 @@ impl Indexer {
                 return true;
             },
         };
-        let mtime = metadata.modified().unwrap().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f32();
+        let mtime = mtime_nanos(&metadata);
+        let size = metadata.len();
         let fname = path.file_name().unwrap().to_string_lossy();
-        let stat = self.already_done(dir, &fname, mtime).await;
+        let conf = self.read().await.routes[route].conf.clone();
+        let stat = self.already_done(dir, &fname, mtime, conf.id()).await;
         if stat == Entry::Done {
+            self.ensure_embedding(path, dir, &fname).await;
             return true;
         }
-        let cmd = format!("{} {}", self.read().await.cmd, shquote(path.to_string_lossy().as_ref()));
-        match tokio::process::Command::new("sh").arg("-c").arg(&cmd).output().await {
-            Ok(out) => {
-                if !out.status.success() || out.stdout.len() == 0 {
-                    error!("CMD FAILED {}: {}", cmd, unsafe { std::str::from_utf8_unchecked(&out.stderr) });
-                    return self.indexer_online().await;
-                } else {
-                    let description = unsafe { std::str::from_utf8_unchecked(&out.stdout) };
-                    trace!("{:?} DESC:{}", path, description.trim());
-                    self.save(dir, &fname, &description, mtime, stat).await;
-                    return true;
-                }
+        if stat == Entry::None && self.follow_rename(dir, &fname, mtime, size, conf.id()).await {
+            self.write().await.files_done += 1;
+            self.emit_progress().await;
+            return true;
+        }
+        if self.quarantined(dir, &fname, mtime, conf.id()).await {
+            trace!("Skipping {:?}, the indexer keeps failing on it", path);
+            return true;
+        }
+        self.wait_for_turn().await;
+        if !self.read().await.idx_running {
+            return true;
+        }
+        let description = if conf.http {
+            describe_http(path, &conf).await
+        } else {
+            describe_cmd(path, &conf).await
+        };
+        let failure = match description {
+            Ok(description) => {
+                trace!("{:?} DESC:{}", path, description.trim());
+                self.save(dir, &fname, &description, mtime, size, &conf).await;
+                self.ensure_embedding(path, dir, &fname).await;
+                self.write().await.files_done += 1;
+                self.emit_progress().await;
+                return true;
             },
-            Err(e) => {error!("Process error: {}", e)},
+            Err(e) => e,
         };
-        return self.indexer_online().await;
+        error!("{} indexer failed on {:?}: {}", conf.name, path, failure);
+        // An online indexer that can't handle the file won't do better on a retry, so skip it.
+        // So is one that keeps going offline on it, rather than failing the whole batch.
+        let online = self.indexer_online(route).await;
+        let attempts = self.record_failure(dir, &fname, mtime, &conf, &failure, online).await;
+        let quarantined = attempts >= self.read().await.max_attempts;
+        if quarantined {
+            warn!("Skipping {:?} until it changes after {} failed attempts", path, attempts);
+        }
+        if online || quarantined {
+            self.write().await.files_failed += 1;
+            self.emit_progress().await;
+        }
+        online || quarantined
     }
 
     async fn update_dir(self: &Self, dir: &String) -> DirResult {
@@ -318,8 +1033,11 @@ impl Indexer {
             return DirResult::Ignore;
         }
         trace!("Updating dir:{}", dir);
+        let mut by_route = vec![vec![]; self.read().await.routes.len()];
+        let mut listed = false;
         match std::fs::read_dir(dir) {
             Ok(read_dir) => {
+                listed = true;
                 for dir_entry in read_dir {
                     if !self.read().await.idx_running {
                         break;
@@ -328,28 +1046,15 @@ impl Indexer {
                         let path = de.path();
                         match path.extension() {
                             Some(ext) => {
-                                if self.read().await.exts.contains(&ext.to_ascii_lowercase().to_string_lossy().as_ref()) {
+                                let route = self.read().await.route(&ext.to_ascii_lowercase().to_string_lossy());
+                                if let Some(route) = route {
                                     if let Match::Ignore(_) = self.read().await.search_ignore.matched(&path, path.is_dir()) {
                                         continue;
                                     }
                                     if let Match::Ignore(_) = local_ignore.matched(&path, false) {
                                         continue;
                                     }
-                                    let mut online = true;
-                                    let mut tries_left = 5;
-                                    loop {
-                                        if online && self.update_file(path.as_path(), dir).await {
-                                            break;
-                                        } else  {
-                                            warn!("Retrying {:?} in a minute...", path);
-                                            tries_left -= 1;
-                                            sleep(Duration::from_secs(60)).await;
-                                            online = self.indexer_online().await;
-                                            if !online && tries_left == 0 {
-                                                return DirResult::Fail;
-                                            }
-                                        }
-                                    };
+                                    by_route[route].push(path);
                                 }
                             },
                             None => {},
@@ -359,15 +1064,92 @@ impl Indexer {
             },
             Err(e) => error!("Error reading dir {}: {}", dir, e),
         }
+        // Each route waits for its own workers, so a slow indexer doesn't hold up the others.
+        let mut routes = JoinSet::new();
+        for (route, paths) in by_route.into_iter().enumerate().filter(|(_, paths)| !paths.is_empty()) {
+            let this = self.clone();
+            let dir = dir.clone();
+            routes.spawn(async move { this.update_route(&dir, route, paths).await });
+        }
+        let mut failed = false;
+        while let Some(res) = routes.join_next().await {
+            failed |= matches!(res, Ok(false));
+            if failed {
+                routes.abort_all();
+            }
+        }
+        if failed {
+            return DirResult::Fail;
+        }
+        // After indexing so renamed files can take over their old rows first
+        if listed {
+            self.prune_dir(dir).await;
+        }
         return DirResult::Success;
     }
 
+    /// Index a dir's files for one route, no more at once than it has workers.
+    /// Returns false if a file failed because the indexer stayed offline.
+    async fn update_route(&self, dir: &str, route: usize, paths: Vec<PathBuf>) -> bool {
+        let workers = self.read().await.routes[route].workers.clone();
+        let mut tasks = JoinSet::new();
+        let mut failed = false;
+        for path in paths {
+            if !self.read().await.idx_running {
+                break;
+            }
+            // Wait for a free worker before spawning so the batch stays bounded.
+            let permit = workers.clone().acquire_owned().await.unwrap();
+            while let Some(res) = tasks.try_join_next() {
+                failed |= matches!(res, Ok(false));
+            }
+            if failed {
+                break;
+            }
+            let this = self.clone();
+            let dir = dir.to_string();
+            tasks.spawn(async move {
+                let indexed = this.retry_file(&path, &dir, route).await;
+                drop(permit);
+                indexed
+            });
+        }
+        if failed {
+            tasks.abort_all();
+        }
+        while let Some(res) = tasks.join_next().await {
+            failed |= matches!(res, Ok(false));
+        }
+        !failed
+    }
+
+    /// Index a file, retrying while the indexer is offline.
+    /// Returns false once it has stayed offline through every retry.
+    async fn retry_file(&self, path: &Path, dir: &String, route: usize) -> bool {
+        let mut online = true;
+        let mut tries_left = RETRIES;
+        let mut delay = self.read().await.schedule.retry_delay;
+        loop {
+            if online && self.update_file(path, dir, route).await {
+                return true;
+            }
+            warn!("Retrying {:?} in {:?}...", path, delay);
+            tries_left = tries_left.saturating_sub(1);
+            sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+            online = self.indexer_online(route).await;
+            if !online && tries_left == 0 {
+                return false;
+            }
+        }
+    }
+
 }
 
 
 struct FilePicker {
-    prev_path: String,
-    prev_path_set_at: SystemTime,
+    /// Shared by concurrent requests, so only lock it briefly.
+    prev_path: std::sync::Mutex<PrevPath>,
     postproc_dir: String,
     postprocessor: String,
     def_save_dir: String,
@@ -378,11 +1160,267 @@ struct FilePicker {
     use_prev: bool,
 }
 
-fn shquote(s: &str) -> String {
-    if s.contains("\"") {
-        return format!("'{}'", s);
+/// Where the last picked files were, so the next dialog can start there.
+struct PrevPath {
+    path: String,
+    set_at: SystemTime,
+}
+
+/// Exported at a dialog's request handle while the picker is open so the caller can close it.
+struct Request {
+    closed: Arc<Notify>,
+}
+
+impl Request {
+    /// Export a request at handle, returning what gets notified when it's closed.
+    async fn export(server: &ObjectServer, handle: &ObjectPath<'_>) -> Arc<Notify> {
+        let closed = Arc::new(Notify::new());
+        if let Err(e) = server.at(handle, Request { closed: closed.clone() }).await {
+            error!("Could not export request {}: {}", handle, e);
+        }
+        closed
+    }
+
+    async fn remove(server: &ObjectServer, handle: &ObjectPath<'_>) {
+        let _ = server.remove::<Request, _>(handle).await;
+    }
+}
+
+#[interface(name = "org.freedesktop.impl.portal.Request")]
+impl Request {
+    async fn close(&self) {
+        debug!("Request closed");
+        self.closed.notify_one();
+    }
+}
+
+/// file:// URI for a path. Every byte but unreserved characters and '/' is percent-encoded,
+/// so names with spaces, '#', '?', non-ASCII or newlines stay one valid URI.
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &b in path.as_os_str().as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(b as char),
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+/// Join fields with '|', escaping '|' and '\\' so pikeru can split them back apart.
+fn join_escaped<'a>(fields: impl IntoIterator<Item = &'a str>) -> String {
+    fields.into_iter().map(|f| f.replace('\\', "\\\\").replace('|', "\\|")).collect::<Vec<_>>().join("|")
+}
+
+/// A FileChooser filter: name and a list of (0 = glob, 1 = mime type) patterns.
+type Filter = (String, Vec<(u32, String)>);
+
+/// A FileChooser choice: id, label, (id, label) options and the initial selection.
+/// No options means it's a boolean choice with "true" or "false" as the selection.
+type Choice = (String, String, Vec<(String, String)>, String);
+
+/// Options from the portal request that get forwarded to pikeru.
+#[derive(Default)]
+struct Dialog {
+    /// Caller's window, "x11:XID" or "wayland:HANDLE". pikeru parses it.
+    parent: Option<String>,
+    title: String,
+    accept_label: Option<String>,
+    modal: bool,
+    /// Existing file to save over, for SaveFile.
+    current_file: Option<String>,
+    filters: Vec<Filter>,
+    current_filter: Option<usize>,
+    choices: Vec<Choice>,
+    /// File names to save into the chosen directory, for SaveFiles.
+    files: Vec<String>,
+}
+
+/// Decode a NUL terminated bytestring option like a path.
+fn get_bytes_opt(options: &HashMap<&str, Value<'_>>, key: &str) -> Option<String> {
+    let bytes: Vec<u8> = get_opt(options, key)?;
+    Some(String::from_utf8_lossy(bytes.strip_suffix(&[0]).unwrap_or(&bytes)).to_string())
+}
+
+/// Drop the mnemonic underscores from labels like "_Open", keeping escaped ones from "__".
+fn strip_mnemonic(label: &str) -> String {
+    let mut out = String::new();
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        if c == '_' {
+            out.extend(chars.next());
+        } else {
+            out.push(c);
+        }
     }
-    return format!("\"{}\"", s);
+    out
+}
+
+fn get_opt<'a, T: TryFrom<Value<'a>>>(options: &HashMap<&str, Value<'a>>, key: &str) -> Option<T> {
+    let val = options.get(key)?.try_clone().ok()?;
+    match T::try_from(val) {
+        Ok(v) => Some(v),
+        Err(_) => { error!("{} type error", key); None },
+    }
+}
+
+impl Dialog {
+    fn new(parent: &str, title: &str, options: &HashMap<&str, Value<'_>>) -> Self {
+        let mut filters: Vec<Filter> = get_opt(options, "filters").unwrap_or_default();
+        // current_filter may also be given on its own to apply a filter unconditionally
+        let current_filter = get_opt::<Filter>(options, "current_filter").map(|cur| {
+            match filters.iter().position(|f| *f == cur) {
+                Some(i) => i,
+                None => { filters.push(cur); filters.len() - 1 },
+            }
+        });
+        Self {
+            parent: (!parent.is_empty()).then(|| parent.to_string()),
+            title: title.to_string(),
+            accept_label: get_opt::<String>(options, "accept_label").map(|l| strip_mnemonic(&l)),
+            modal: get_opt(options, "modal").unwrap_or(true),
+            current_file: get_bytes_opt(options, "current_file"),
+            filters,
+            current_filter,
+            choices: get_opt(options, "choices").unwrap_or_default(),
+            files: get_opt::<Vec<Vec<u8>>>(options, "files").unwrap_or_default().iter().map(|f| {
+                String::from_utf8_lossy(f.strip_suffix(&[0]).unwrap_or(f)).to_string()
+            }).collect(),
+        }
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if !self.title.is_empty() {
+            args.push("--title".to_string());
+            args.push(self.title.clone());
+        }
+        if let Some(label) = &self.accept_label {
+            args.push("--accept-label".to_string());
+            args.push(label.clone());
+        }
+        if !self.modal {
+            args.push("--nonmodal".to_string());
+        }
+        if let Some(parent) = &self.parent {
+            args.push("--parent".to_string());
+            args.push(parent.clone());
+        }
+        for (name, patterns) in &self.filters {
+            args.push("--filter".to_string());
+            // Tagged with their kind, since a glob may contain '/' too
+            let patterns: Vec<String> = patterns.iter().map(|(kind, pat)| match kind {
+                1 => format!("mime:{}", pat),
+                _ => format!("glob:{}", pat),
+            }).collect();
+            args.push(join_escaped(std::iter::once(name.as_str()).chain(patterns.iter().map(String::as_str))));
+        }
+        if let Some(i) = self.current_filter {
+            args.push("--current-filter".to_string());
+            args.push(self.filters[i].0.clone());
+        }
+        for (id, label, options, initial) in &self.choices {
+            args.push("--choice".to_string());
+            args.push(join_escaped([id.as_str(), label.as_str(), initial.as_str()].into_iter()
+                .chain(options.iter().flat_map(|(id, label)| [id.as_str(), label.as_str()]))));
+        }
+        args
+    }
+
+    /// Paths in dir to save the requested files to. Names that already exist,
+    /// or repeat in the list, get a number appended like name_1.ext.
+    fn save_paths(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut taken = HashSet::new();
+        self.files.iter().map(|name| {
+            // only use the file name so callers can't write outside of dir
+            let name = Path::new(name).file_name().map_or_else(|| "download".to_string(), |s| s.to_string_lossy().to_string());
+            let stem = Path::new(&name).file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let ext = Path::new(&name).extension().map_or_else(|| "".to_string(), |e| format!(".{}", e.to_string_lossy()));
+            let mut path = dir.join(&name);
+            let mut n = 1;
+            while path.exists() || taken.contains(&path) {
+                path = dir.join(format!("{}_{}{}", stem, n, ext));
+                n += 1;
+            }
+            taken.insert(path.clone());
+            path
+        }).collect()
+    }
+
+    /// Add result keys from the key=value records pikeru wrote to PK_RESULT.
+    fn results(&self, records: &[(String, OsString)], ret: &mut HashMap<String, OwnedValue>) {
+        let mut choices = vec![];
+        for (key, val) in records {
+            let val = val.to_string_lossy().to_string();
+            if let Some(i) = key.strip_prefix("choice.") {
+                match i.parse::<usize>().ok().and_then(|i| self.choices.get(i)) {
+                    Some(choice) => choices.push((choice.0.clone(), val)),
+                    None => warn!("Unknown choice from filepicker:{}={}", key, val),
+                }
+                continue;
+            }
+            match key.as_str() {
+                "path" => {},
+                "filter" => {
+                    if let Some(filter) = val.parse::<usize>().ok().and_then(|i| self.filters.get(i)) {
+                        ret.insert("current_filter".to_string(), Value::from(filter.clone()).try_to_owned().unwrap());
+                    }
+                },
+                _ => warn!("Unknown result from filepicker:{}={}", key, val),
+            }
+        }
+        if !choices.is_empty() {
+            ret.insert("choices".to_string(), Value::from(choices).try_to_owned().unwrap());
+        }
+    }
+}
+
+/// XDG_RUNTIME_DIR, or else a dir of the portal's own in the shared temp dir. It's made
+/// there rather than reused, so nobody else can have put anything in it.
+fn private_dir() -> std::io::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(dir.into());
+    }
+    static DIR: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+    let mut dir = DIR.lock().unwrap();
+    if let Some(ref dir) = *dir {
+        return Ok(dir.clone());
+    }
+    for n in 0.. {
+        let path = std::env::temp_dir().join(format!("pikeru-portal-{}-{}", std::process::id(), n));
+        match fs::DirBuilder::new().mode(0o700).create(&path) {
+            Ok(()) => return Ok(dir.insert(path).clone()),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+/// A new empty file only this user can read, for pikeru to write its NUL-separated key=value results to.
+/// It's created here so pikeru never writes to a file or symlink someone else put in its place.
+fn result_file() -> std::io::Result<PathBuf> {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let dir = private_dir()?;
+    loop {
+        let n = COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+        let path = dir.join(format!("pikeru-{}-{}.result", std::process::id(), n));
+        match fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The records in the result file, with values kept as bytes since paths needn't be UTF-8.
+fn read_results(path: &Path) -> Vec<(String, OsString)> {
+    let txt = fs::read(path).unwrap_or_default();
+    let _ = fs::remove_file(path);
+    txt.split(|b| *b == 0).filter_map(|rec| {
+        let eq = rec.iter().position(|b| *b == b'=')?;
+        Some((String::from_utf8_lossy(&rec[..eq]).to_string(), OsString::from_vec(rec[eq + 1..].to_vec())))
+    }).collect()
 }
 
 #[derive(PartialEq)]
@@ -392,6 +1430,285 @@ enum Entry {
     Done,
 }
 
+/// Schema changes to index.db in order. PRAGMA user_version counts how many have been applied.
+/// Each gets the configured indexer commands.
+type Migration = fn(&rusqlite::Transaction, &[IndexerCmd]) -> rusqlite::Result<()>;
+const MIGRATIONS: &[Migration] = &[
+    migrate_base_tables,
+    migrate_exact_mtime,
+    migrate_full_text,
+    migrate_embeddings,
+    migrate_indexer_names,
+    migrate_failures,
+    migrate_http_indexer,
+    migrate_offline_failures,
+];
+
+fn migrate(c: &mut rusqlite::Connection, indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    let version: usize = c.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    if version > MIGRATIONS.len() {
+        warn!("index db version {} is newer than this portal knows about", version);
+    }
+    for (i, step) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = c.transaction()?;
+        step(&tx, indexers)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+        info!("Migrated index db to version {}", i + 1);
+    }
+    Ok(())
+}
+
+/// The tables as they were before the index had a version.
+fn migrate_base_tables(tx: &rusqlite::Transaction, _indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists descriptions
+                (fname text, dir text, description text, mtime real, size integer);", ())?;
+    if tx.prepare("select size from descriptions limit 0").is_err() {
+        tx.execute("alter table descriptions add column size integer", ())?;
+    }
+    tx.execute("create table if not exists index_queue
+                (dir text primary key, done integer not null default 0);", ())?;
+    Ok(())
+}
+
+/// Rebuild descriptions with one row per path, mtime in integer nanoseconds instead of f32 seconds,
+/// and the indexer command that wrote each description.
+fn migrate_exact_mtime(tx: &rusqlite::Transaction, indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    tx.execute("create table descriptions_v2 (fname text not null, dir text not null,
+                description text, mtime integer not null, size integer, indexer text);", ())?;
+    // Assume what's indexed came from the current command rather than reindexing everything
+    let cmd = indexers.iter().find(|i| i.name == DEFAULT_INDEXER).map_or("", |i| i.cmd.as_str());
+    let indexer = if cmd.is_empty() { None } else { Some(cmd) };
+    let mut select = tx.prepare("select dir, fname, description, mtime, size from descriptions
+                                 where rowid in (select max(rowid) from descriptions group by dir, fname)
+                                 and dir is not null and fname is not null")?;
+    let mut insert = tx.prepare("insert into descriptions_v2 (dir, fname, description, mtime, size, indexer)
+                                 values (?1, ?2, ?3, ?4, ?5, ?6)")?;
+    let mut rows = select.query(())?;
+    while let Some(r) = rows.next()? {
+        let (dir, fname): (String, String) = (r.get(0)?, r.get(1)?);
+        let old: f64 = r.get::<_, Option<f64>>(3)?.unwrap_or_default();
+        // Take the exact time from files that haven't changed by the old f32 comparison
+        let mtime = match Path::new(&dir).join(&fname).metadata() {
+            Ok(md) if Duration::from_nanos(mtime_nanos(&md) as u64).as_secs_f32() == old as f32 => mtime_nanos(&md),
+            _ => (old * 1e9) as i64,
+        };
+        insert.execute((&dir, &fname, r.get::<_, Option<String>>(2)?, mtime, r.get::<_, Option<i64>>(4)?, indexer))?;
+    }
+    drop(rows);
+    tx.execute("drop table descriptions", ())?;
+    tx.execute("alter table descriptions_v2 rename to descriptions", ())?;
+    tx.execute("create unique index descriptions_path on descriptions (dir, fname)", ())?;
+    tx.execute("create index descriptions_size on descriptions (size, mtime)", ())?;
+    Ok(())
+}
+
+/// Full text search over descriptions for the picker. The descriptions need a real id column
+/// for the fts table to point at, since a vacuum may renumber plain rowids.
+fn migrate_full_text(tx: &rusqlite::Transaction, _indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    tx.execute_batch("
+        create table descriptions_v3 (id integer primary key, fname text not null, dir text not null,
+            description text, mtime integer not null, size integer, indexer text);
+        insert into descriptions_v3 (id, fname, dir, description, mtime, size, indexer)
+            select rowid, fname, dir, description, mtime, size, indexer from descriptions;
+        drop table descriptions;
+        alter table descriptions_v3 rename to descriptions;
+        create unique index descriptions_path on descriptions (dir, fname);
+        create index descriptions_size on descriptions (size, mtime);
+
+        create virtual table descriptions_fts using fts5
+            (description, content = 'descriptions', content_rowid = 'id', prefix = '2 3');
+        insert into descriptions_fts (descriptions_fts) values ('rebuild');
+        create trigger descriptions_fts_insert after insert on descriptions begin
+            insert into descriptions_fts (rowid, description) values (new.id, new.description);
+        end;
+        create trigger descriptions_fts_delete after delete on descriptions begin
+            insert into descriptions_fts (descriptions_fts, rowid, description) values ('delete', old.id, old.description);
+        end;
+        create trigger descriptions_fts_update after update of description on descriptions begin
+            insert into descriptions_fts (descriptions_fts, rowid, description) values ('delete', old.id, old.description);
+            insert into descriptions_fts (rowid, description) values (new.id, new.description);
+        end;
+    ")
+}
+
+/// Vectors from the embed command as little-endian f32s, one per description.
+/// They go with the description's row, and are dropped when its file changes.
+fn migrate_embeddings(tx: &rusqlite::Transaction, _indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    tx.execute_batch("
+        create table embeddings (id integer primary key, vector blob not null, embedder text);
+        create trigger embeddings_delete after delete on descriptions begin
+            delete from embeddings where id = old.id;
+        end;
+        create trigger embeddings_stale after update of mtime on descriptions when new.mtime is not old.mtime begin
+            delete from embeddings where id = old.id;
+        end;
+    ")
+}
+
+/// Which indexer section wrote each description, for descriptions whose command is still configured.
+fn migrate_indexer_names(tx: &rusqlite::Transaction, indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    tx.execute("alter table descriptions add column indexer_name text", ())?;
+    for conf in indexers {
+        tx.execute("update descriptions set indexer_name = ?1 where indexer = ?2", (&conf.name, conf.id()))?;
+    }
+    Ok(())
+}
+
+/// Why an indexer couldn't describe a file, as kept in the failures table.
+#[derive(Debug)]
+struct Failure {
+    /// Exit code of a command that ran and failed.
+    code: Option<i32>,
+    error: String,
+}
+
+impl Failure {
+    fn new(error: impl Into<String>) -> Self {
+        Self { code: None, error: error.into() }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "exit code {}: {}", code, self.error),
+            None => f.write_str(&self.error),
+        }
+    }
+}
+
+/// Files the indexer failed on, one row per path, so ones that always fail can be skipped until they change.
+fn migrate_failures(tx: &rusqlite::Transaction, _indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    tx.execute("create table failures (dir text not null, fname text not null, mtime integer not null,
+                indexer text, indexer_name text, exit_code integer, error text not null,
+                attempts integer not null, last_attempt integer not null, primary key (dir, fname));", ())?;
+    Ok(())
+}
+
+/// An http default indexer is known by its url, but versioning the index assumed existing
+/// descriptions came from the default indexer's command, which an http indexer doesn't run.
+fn migrate_http_indexer(tx: &rusqlite::Transaction, indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    if let Some(conf) = indexers.iter().find(|i| i.name == DEFAULT_INDEXER && i.http) {
+        tx.execute("update descriptions set indexer = ?1, indexer_name = ?2 where indexer is null or indexer = ?3",
+            (conf.id(), &conf.name, &conf.cmd))?;
+    }
+    Ok(())
+}
+
+/// Failures that took the indexer offline may have been the indexer's fault rather than the file's,
+/// so they're counted apart from the ones it stayed online for.
+fn migrate_offline_failures(tx: &rusqlite::Transaction, _indexers: &[IndexerCmd]) -> rusqlite::Result<()> {
+    tx.execute("alter table failures add column offline_attempts integer not null default 0", ())?;
+    Ok(())
+}
+
+/// Run the indexer's command on the file and return what it printed.
+async fn describe_cmd(path: &Path, conf: &IndexerCmd) -> Result<String, Failure> {
+    let cmd = format!("{} \"$1\"", conf.cmd);
+    let mut command = tokio::process::Command::new("sh");
+    command.arg("-c").arg(&cmd).arg("sh").arg(path).kill_on_drop(true)
+        .stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let output = match conf.timeout {
+        // In its own process group so a slow command can be stopped along with anything it started.
+        Some(t) => match command.process_group(0).spawn() {
+            Ok(child) => {
+                let pid = child.id();
+                match tokio::time::timeout(t, child.wait_with_output()).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        if let Some(pid) = pid {
+                            unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL); }
+                        }
+                        return Err(Failure::new(format!("{} took over {:?}", cmd, t)));
+                    },
+                }
+            },
+            Err(e) => Err(e),
+        },
+        None => command.output().await,
+    };
+    match output {
+        Ok(out) if out.status.success() && !out.stdout.is_empty() => Ok(String::from_utf8_lossy(&out.stdout).into_owned()),
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            // The end of stderr is where the error usually is
+            let mut start = stderr.len().saturating_sub(MAX_ERROR_LEN);
+            while !stderr.is_char_boundary(start) {
+                start += 1;
+            }
+            Err(Failure {
+                code: out.status.code(),
+                error: match stderr[start..].trim() {
+                    "" => format!("{} printed nothing", cmd),
+                    e => format!("{} failed: {}", cmd, e),
+                },
+            })
+        },
+        Err(e) => Err(Failure::new(format!("Process error: {}", e))),
+    }
+}
+
+/// POST the file to the indexer's url and return the text from the reply.
+async fn describe_http(path: &Path, conf: &IndexerCmd) -> Result<String, Failure> {
+    let url = http_indexer::parse_url(&conf.url).map_err(Failure::new)?;
+    let description = http_indexer::describe(&url, &conf.field, path, conf.timeout.unwrap_or(HTTP_TIMEOUT)).await
+        .map_err(Failure::new)?;
+    match description.trim().is_empty() {
+        true => Err(Failure::new("empty description")),
+        false => Ok(description),
+    }
+}
+
+/// Run the embed command with args and parse what it prints.
+async fn run_embed(cmd: &str, args: &[&str]) -> Result<Vec<f32>, String> {
+    let out = tokio::process::Command::new("sh").arg("-c").arg(format!("{} \"$@\"", cmd)).arg("sh").args(args)
+        .kill_on_drop(true).output().await.map_err(|e| e.to_string())?;
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+    }
+    parse_vector(&out.stdout).ok_or_else(|| "embed command didn't print a vector".to_string())
+}
+
+/// A JSON array of numbers, or else raw little-endian f32s.
+fn parse_vector(out: &[u8]) -> Option<Vec<f32>> {
+    let text = std::str::from_utf8(out).map(str::trim);
+    let vector: Vec<f32> = match text {
+        Ok(t) if t.starts_with('[') => {
+            let inner = t.strip_prefix('[')?.strip_suffix(']')?;
+            inner.split(',').map(|n| n.trim().parse().ok()).collect::<Option<_>>()?
+        },
+        _ if out.len().is_multiple_of(4) => out.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+        _ => return None,
+    };
+    (!vector.is_empty() && vector.iter().all(|f| f.is_finite())).then_some(vector)
+}
+
+fn mtime_nanos(metadata: &fs::Metadata) -> i64 {
+    metadata.modified().unwrap().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as i64
+}
+
+/// How long an http indexer may take on a file when it has no timeout set.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How many times a file is retried while its indexer is offline before the batch fails.
+const RETRIES: u32 = 6;
+
+/// Longest wait between retries, however many there have been.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+/// Most bytes of an indexer's stderr kept with a failure.
+const MAX_ERROR_LEN: usize = 2000;
+
+/// How often held back files check the load and battery again.
+const SCHEDULE_POLL: Duration = Duration::from_secs(2);
+
+/// How long file changes under watch_roots are collected before their dirs are queued.
+const WATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Most rows the background sweep checks per turn.
+const GC_BATCH: u32 = 500;
+
 #[derive(PartialEq)]
 enum DirResult {
     Fail,
@@ -402,6 +1719,9 @@ enum DirResult {
 enum Section {
     FileChooser,
     Indexer,
+    NamedIndexer(usize),
+    /// A section that's been reported as bad, whose values are ignored
+    Bad,
     Global,
 }
 fn tilda<'a>(home: &String, dir: &'a str) -> Cow<'a,str> {
@@ -412,6 +1732,132 @@ fn tilda<'a>(home: &String, dir: &'a str) -> Cow<'a,str> {
     Cow::from(dir)
 }
 
+/// When files may be indexed, from the [indexer] scheduling options.
+#[derive(Debug, Clone)]
+struct Schedule {
+    /// Time between files, from max_files_per_minute.
+    interval: Option<Duration>,
+    only_when_idle: bool,
+    pause_on_battery: bool,
+    /// First wait before retrying a file while its indexer is offline, doubled each time.
+    retry_delay: Duration,
+    /// Where the load average and batteries are read from, which tests point at fake files.
+    loadavg: PathBuf,
+    power_supply: PathBuf,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            only_when_idle: false,
+            pause_on_battery: false,
+            retry_delay: Duration::from_secs(10),
+            loadavg: PathBuf::from("/proc/loadavg"),
+            power_supply: PathBuf::from("/sys/class/power_supply"),
+        }
+    }
+}
+
+impl Schedule {
+    /// Why indexing should wait right now, if it should.
+    fn hold(&self) -> Option<&'static str> {
+        if self.only_when_idle && !system_idle(&self.loadavg) {
+            return Some("system busy");
+        }
+        if self.pause_on_battery && on_battery(&self.power_supply) {
+            return Some("on battery");
+        }
+        None
+    }
+}
+
+/// Whether the 1 minute load average is under half the number of CPUs.
+fn system_idle(loadavg: &Path) -> bool {
+    let loadavg = fs::read_to_string(loadavg).unwrap_or_default();
+    match loadavg.split_whitespace().next().and_then(|l| l.parse::<f32>().ok()) {
+        Some(load) => load < num_cpus::get() as f32 / 2.0,
+        None => true,
+    }
+}
+
+/// Whether a battery is discharging.
+fn on_battery(power_supply: &Path) -> bool {
+    let Ok(supplies) = fs::read_dir(power_supply) else { return false };
+    supplies.filter_map(|s| s.ok()).any(|s| {
+        let read = |f: &str| fs::read_to_string(s.path().join(f)).unwrap_or_default();
+        read("type").trim() == "Battery" && read("status").trim() == "Discharging"
+    })
+}
+
+/// Name of the indexer set up by the [indexer] section itself.
+const DEFAULT_INDEXER: &str = "default";
+
+/// A command that describes files with some extensions, from [indexer] or an [indexer.NAME] section.
+#[derive(Debug, Clone)]
+struct IndexerCmd {
+    name: String,
+    cmd: String,
+    check: String,
+    exts: Vec<String>,
+    /// POST files to `url` instead of running `cmd`, with `type = http`.
+    http: bool,
+    url: String,
+    /// Where the description is in the server's JSON reply.
+    field: String,
+    /// How long a file may take before it counts as failed.
+    timeout: Option<Duration>,
+    workers: usize,
+}
+
+impl IndexerCmd {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            cmd: String::new(),
+            check: String::new(),
+            exts: vec![],
+            http: false,
+            url: String::new(),
+            field: "caption".to_string(),
+            timeout: None,
+            workers: 1,
+        }
+    }
+
+    /// Set a key from the indexer's section, returning false if it isn't one of its keys.
+    fn set(&mut self, k: &str, v: &str, line: &str) -> bool {
+        match k {
+            "type" => match v {
+                "command" => self.http = false,
+                "http" => self.http = true,
+                _ => eprintln!("indexer type must be command or http:{}", line),
+            },
+            "cmd" => self.cmd = v.to_string(),
+            "url" => self.url = v.to_string(),
+            "field" => self.field = v.to_string(),
+            "check" => self.check = v.to_string(),
+            "extensions" => self.exts = v.split(',').map(|e| e.trim().to_ascii_lowercase()).filter(|e| !e.is_empty()).collect(),
+            "workers" => match v.parse() {
+                Ok(n) if n > 0 => self.workers = n,
+                _ => eprintln!("indexer workers must be a positive number:{}", line),
+            },
+            "timeout" => match v.parse() {
+                Ok(0) => self.timeout = None,
+                Ok(n) => self.timeout = Some(Duration::from_secs(n)),
+                _ => eprintln!("indexer timeout must be a number of seconds:{}", line),
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Stored with each description, to tell when a different indexer made it.
+    fn id(&self) -> &str {
+        if self.http { &self.url } else { &self.cmd }
+    }
+}
+
 #[derive(Debug)]
 struct Config {
     home: String,
@@ -422,10 +1868,15 @@ struct Config {
     postprocessor: String,
     def_save_dir: String,
     file_cmd: String,
-    indexer_cmd: String,
-    indexer_check: String,
-    indexer_exts: String,
+    /// In the order they're tried for a file, the [indexer] section's command last.
+    indexers: Vec<IndexerCmd>,
+    indexer_embed: String,
     indexer_enabled: bool,
+    indexer_gc_interval: u64,
+    indexer_schedule: Schedule,
+    indexer_max_attempts: u32,
+    /// Dirs indexed in the background and watched for new files, without pikeru visiting them.
+    indexer_watch_roots: Vec<String>,
     use_prev_path_for_save: bool,
 }
 
@@ -463,14 +1914,73 @@ postprocess_dir = /tmp/pk_postprocess
 
 enable = false
 
-# bash command that will be given an additional filepath arg and prints searchable text to stdout.
-cmd = python /usr/share/xdg-desktop-portal-pikeru/img_indexer.py http://127.0.0.1:7860/sdapi/v1/interrogate
+# 'http' sends files straight to url, 'command' runs cmd on each of them instead.
+type = http
+
+# caption server or stable diffusion webui endpoint. Files are posted base64 encoded as
+# {"image": ..., "model": "clip"} and the searchable text is read from 'field' in the JSON reply.
+url = http://127.0.0.1:7860/sdapi/v1/interrogate
+field = caption
+
+# for type = command, a bash command that will be given an additional filepath arg and prints
+# searchable text to stdout.
+#cmd = python /usr/share/xdg-desktop-portal-pikeru/img_indexer.py http://127.0.0.1:7860/sdapi/v1/interrogate
 
-# bash command that only returns status code 0 when the indexer is online
-check = curl http://127.0.0.1:7860/sdapi/v1/interrogate
+# bash command that only returns status code 0 when the indexer is online. Without one, an http
+# indexer is online while url accepts connections.
+#check = curl http://127.0.0.1:7860/sdapi/v1/interrogate
 
-# comma-separated list of file types that 'cmd' can process.
+# comma-separated list of file types that the indexer can process.
 extensions = png,jpg,jpeg,gif,webp,tiff,bmp
+
+# optional bash command that prints a vector for a filepath arg, as a JSON array or raw
+# little-endian f32s. It's also given --text and a search, so files can be ranked by similarity.
+#embed = python ~/clip_embed.py
+
+# how many files to index at once. Raise this if the indexer runs on another machine.
+workers = 1
+
+# seconds a file may take before it counts as failed. 0 waits as long as it takes, or 120
+# seconds for http.
+timeout = 0
+
+# seconds between sweeps that remove deleted files from the index. 0 turns them off.
+gc_interval = 3600
+
+# comma-separated dirs that are indexed in the background and watched for new or changed
+# files, so they're searchable before pikeru opens them. Hidden and ignored dirs are skipped.
+#watch_roots = ~/Pictures,~/Documents
+
+# the most files to index per minute, spread out evenly. 0 means no limit.
+max_files_per_minute = 0
+
+# only index while the load average is below half the number of cpus.
+only_when_idle = false
+
+# hold off indexing while running on battery.
+pause_on_battery = false
+
+# where only_when_idle reads the load average and pause_on_battery looks for batteries.
+#loadavg_path = /proc/loadavg
+#power_supply_path = /sys/class/power_supply
+
+# seconds to wait before retrying a file when the indexer is offline. It doubles each try.
+retry_delay = 10
+
+# how many times the indexer may fail on a file before it's skipped until the file changes.
+# Failures that took the indexer offline are counted separately, against the same limit.
+max_attempts = 3
+
+# More indexers can be added in [indexer.NAME] sections with their own type, cmd, url, field,
+# check, extensions, workers and timeout. They're tried in order before [indexer] and what
+# they print is searched the same way, so documents can be found by their content. The NAME
+# is stored with each result.
+#[indexer.documents]
+#cmd = sh -c 'pdftotext -l 20 "$0" - | head -c 20000'
+#check = command -v pdftotext
+#extensions = pdf
+#workers = 2
+#timeout = 60
 "#;
         fs::write(target_path, content.trim_start()).expect("Unable to create config file");
     }
@@ -529,10 +2039,14 @@ extensions = png,jpg,jpeg,gif,webp,tiff,bmp
         let mut fp_cmd = fp_cmds.iter().find_map(|c|if Path::new(c).is_file() {Some(*c)} else {None})
             .unwrap_or(fp_cmds[0]).to_string();
         let mut postprocessor = "".to_string();
-        let mut indexer_cmd = "".to_string();
-        let mut indexer_check = "".to_string();
-        let mut indexer_exts = "".to_string();
+        let mut default_indexer = IndexerCmd::new(DEFAULT_INDEXER);
+        let mut indexers: Vec<IndexerCmd> = vec![];
+        let mut indexer_embed = "".to_string();
         let mut indexer_enabled = false;
+        let mut indexer_gc_interval = 3600;
+        let mut indexer_watch_roots = vec![];
+        let mut indexer_schedule = Schedule::default();
+        let mut indexer_max_attempts = 3;
         let mut use_prev_path_for_save = false;
         let mut log_level = "info".to_string();
         let mut dbus_service = String::from("org.freedesktop.impl.portal.desktop.pikeru");
@@ -554,19 +2068,67 @@ extensions = png,jpg,jpeg,gif,webp,tiff,bmp
             match line {
                 "[filepicker]" => section = Section::FileChooser,
                 "[indexer]" => section = Section::Indexer,
+                _ if line.starts_with("[indexer.") && line.ends_with(']') => {
+                    let name = &line["[indexer.".len()..line.len() - 1];
+                    section = match indexers.iter().position(|i| i.name == name) {
+                        _ if name.is_empty() => {
+                            eprintln!("Named indexer section without a name, it's ignored:{}", line);
+                            Section::Bad
+                        },
+                        // Its descriptions and IndexerOffline signals would be mixed up with [indexer]'s
+                        _ if name == DEFAULT_INDEXER => {
+                            eprintln!("The {} indexer is set up in [indexer], this section is ignored:{}", DEFAULT_INDEXER, line);
+                            Section::Bad
+                        },
+                        Some(i) => Section::NamedIndexer(i),
+                        None => {
+                            indexers.push(IndexerCmd::new(name));
+                            Section::NamedIndexer(indexers.len() - 1)
+                        },
+                    };
+                },
                 _ => {
                     let (k, v) = str::split_once(line, '=').unwrap();
                     let (k, v) = (k.trim(), v.trim());
                     match section {
                         Section::Indexer => {
                             match k {
-                                "cmd" => indexer_cmd = v.to_string(),
-                                "check" => indexer_check = v.to_string(),
-                                "extensions" => indexer_exts = v.to_string(),
+                                "embed" => indexer_embed = v.to_string(),
                                 "enable" => indexer_enabled = v.parse().unwrap_or(false),
-                                _ => eprintln!("Unknown indexer config value:{}", line),
+                                "gc_interval" => match v.parse() {
+                                    Ok(n) => indexer_gc_interval = n,
+                                    _ => eprintln!("indexer gc_interval must be a number of seconds:{}", line),
+                                },
+                                "max_files_per_minute" => match v.parse::<u32>() {
+                                    Ok(0) => indexer_schedule.interval = None,
+                                    Ok(n) => indexer_schedule.interval = Some(Duration::from_secs(60) / n),
+                                    _ => eprintln!("indexer max_files_per_minute must be a number:{}", line),
+                                },
+                                "only_when_idle" => indexer_schedule.only_when_idle = v.parse().unwrap_or(false),
+                                "pause_on_battery" => indexer_schedule.pause_on_battery = v.parse().unwrap_or(false),
+                                "retry_delay" => match v.parse::<f32>().ok().filter(|n| *n > 0.0).map(Duration::try_from_secs_f32) {
+                                    Some(Ok(delay)) => indexer_schedule.retry_delay = delay,
+                                    _ => eprintln!("indexer retry_delay must be a positive number of seconds:{}", line),
+                                },
+                                "loadavg_path" => indexer_schedule.loadavg = PathBuf::from(v),
+                                "power_supply_path" => indexer_schedule.power_supply = PathBuf::from(v),
+                                "max_attempts" => match v.parse::<u32>() {
+                                    Ok(n) if n > 0 => indexer_max_attempts = n,
+                                    _ => eprintln!("indexer max_attempts must be a positive number:{}", line),
+                                },
+                                "watch_roots" => indexer_watch_roots = v.split(',').map(str::trim)
+                                    .filter(|r| !r.is_empty()).map(str::to_string).collect(),
+                                _ => if !default_indexer.set(k, v, line) {
+                                    eprintln!("Unknown indexer config value:{}", line);
+                                },
                             }
                         },
+                        Section::NamedIndexer(i) => {
+                            if !indexers[i].set(k, v, line) {
+                                eprintln!("Unknown indexer.{} config value:{}", indexers[i].name, line);
+                            }
+                        },
+                        Section::Bad => {},
                         Section::FileChooser => {
                             match k {
                                 "cmd" => fp_cmd = v.to_string(),
@@ -605,15 +2167,34 @@ extensions = png,jpg,jpeg,gif,webp,tiff,bmp
             eprintln!("No filepicker executable found: {}", fp_cmd);
             std::process::exit(1);
         }
+        indexers.push(default_indexer);
+        indexers.retain(|i| {
+            if i.http {
+                if let Err(e) = http_indexer::parse_url(&i.url) {
+                    eprintln!("Bad url for the {} indexer, its extensions won't be indexed: {}", i.name, e);
+                    return false;
+                }
+            } else if i.cmd.is_empty() && (i.name != DEFAULT_INDEXER || !i.exts.is_empty()) {
+                eprintln!("No cmd for the {} indexer, its extensions won't be indexed", i.name);
+            }
+            i.http || !i.cmd.is_empty()
+        });
+        for indexer in indexers.iter_mut() {
+            indexer.cmd = tilda(&home, &indexer.cmd).to_string();
+            indexer.check = tilda(&home, &indexer.check).to_string();
+        }
         Self {
             postproc_dir: tilda(&home, &postproc_dir).to_string(),
             postprocessor: tilda(&home, &postprocessor).to_string(),
             def_save_dir: tilda(&home, &def_save_dir).to_string(),
             file_cmd: tilda(&home, &fp_cmd).to_string(),
-            indexer_cmd: tilda(&home, &indexer_cmd).to_string(),
-            indexer_check: tilda(&home, &indexer_check).to_string(),
-            indexer_exts,
+            indexers,
+            indexer_embed: tilda(&home, &indexer_embed).to_string(),
             indexer_enabled,
+            indexer_gc_interval,
+            indexer_schedule,
+            indexer_max_attempts,
+            indexer_watch_roots: indexer_watch_roots.iter().map(|r| tilda(&home, r).to_string()).collect(),
             home,
             db_path: db_path_override.unwrap_or(default_db),
             dbus_service,
@@ -627,8 +2208,10 @@ impl FilePicker {
 
     fn new(conf: &mut Config, shtate: Arc<AsyncMtx<Shtate>>, db: Arc<std::sync::Mutex<rusqlite::Connection>>) -> Self {
         Self {
-            prev_path: conf.home.clone(),
-            prev_path_set_at: SystemTime::now(),
+            prev_path: std::sync::Mutex::new(PrevPath {
+                path: conf.home.clone(),
+                set_at: SystemTime::now(),
+            }),
             postproc_dir: take(&mut conf.postproc_dir),
             postprocessor: take(&mut conf.postprocessor),
             def_save_dir: take(&mut conf.def_save_dir),
@@ -640,45 +2223,97 @@ impl FilePicker {
         }
     }
 
-    async fn select_files(self: &mut Self, multi: bool, dir: bool, save: bool, path: &str) -> (u32, HashMap<String, OwnedValue>) {
+    /// Run the picker. When opening, an empty path starts where the last dialog left off.
+    async fn select_files(&self, closed: &Notify, multi: bool, dir: bool, save: bool, path: &str, dialog: &Dialog) -> (u32, HashMap<String, OwnedValue>) {
         let dir = if dir   { 1 } else { 0 };
         let multi = if multi { 1 } else { 0 };
         let savenum = if save  { 1 } else { 0 };
-        {
+        let prev_path = {
             const TIMEOUT: u64 = 60*60*24;
-            if SystemTime::now().duration_since(self.prev_path_set_at).unwrap_or_default().as_secs() > TIMEOUT {
-                self.prev_path = self.home.clone();
-                self.prev_path_set_at = SystemTime::now();
+            let mut prev = self.prev_path.lock().unwrap();
+            if SystemTime::now().duration_since(prev.set_at).unwrap_or_default().as_secs() > TIMEOUT {
+                prev.path = self.home.clone();
+                prev.set_at = SystemTime::now();
             }
-        }
-        let cmd = if save {
-            let final_path = if self.use_prev {
+            prev.path.clone()
+        };
+        let start_path = if save {
+            let final_path = if let Some(file) = &dialog.current_file {
+                file.clone()
+            } else if self.use_prev && dir == 1 {
+                prev_path.clone()
+            } else if self.use_prev {
                 let file_name = Path::new(path).file_name().map_or_else(|| "".to_string(), |s| s.to_string_lossy().to_string());
-                Path::new(&self.prev_path).join(file_name).to_string_lossy().to_string()
+                Path::new(&prev_path).join(file_name).to_string_lossy().to_string()
             } else {
                 path.to_string()
             };
-            format!("PK_XDG=1 {} {} {} {} \"{}\"", self.cmd, multi, dir, savenum, tilda(&self.home,&final_path))
+            tilda(&self.home,&final_path).to_string()
         } else {
-            format!("PK_XDG=1 POSTPROCESS_DIR=\"{}\" POSTPROCESSOR=\"{}\" {} {} {} {} {}",
-                    self.postproc_dir, self.postprocessor, self.cmd, multi, dir, savenum,
-                    shquote(tilda(&self.home,&self.prev_path).as_ref()))
+            tilda(&self.home, if path.is_empty() { &prev_path } else { path }).to_string()
         };
         self.db.lock().unwrap().cache_flush().unwrap();
-        debug!("CMD:{}", cmd);
-        let output = match tokio::process::Command::new("sh").arg("-c").arg(cmd).output().await {
-            Ok(out) => {
-                if out.stderr.len() > 0 {
-                    let txt = unsafe { std::str::from_utf8_unchecked(&out.stderr) };
-                    if out.status.success() {
-                        info!("From filepicker:{}", txt);
-                    } else {
-                        error!("From filepicker:{}", txt);
-                    }
+        let result_file = match result_file() {
+            Ok(path) => Some(path),
+            Err(e) => {
+                // The picker's stdout still has the paths
+                warn!("Could not create a result file: {}", e);
+                None
+            },
+        };
+        // Only the configured command goes through the shell. Everything from the request is
+        // passed as separate arguments so file names can't be interpreted by it.
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.arg("-c").arg(format!("exec {} \"$@\"", self.cmd)).arg("sh")
+            .args([multi.to_string(), dir.to_string(), savenum.to_string(), start_path])
+            .args(dialog.args())
+            .env("PK_XDG", "1");
+        if let Some(ref result_file) = result_file {
+            cmd.env("PK_RESULT", result_file);
+        }
+        if !save {
+            cmd.env("POSTPROCESS_DIR", &self.postproc_dir).env("POSTPROCESSOR", &self.postprocessor);
+        }
+        debug!("CMD:{:?}", cmd.as_std());
+        // The picker gets its own process group so closing the request can kill the wrapper and pikeru together.
+        let child = cmd
+            .stdout(Stdio::piped()).stderr(Stdio::piped())
+            .process_group(0)
+            .spawn();
+        let output = match child {
+            Ok(child) => {
+                let pid = child.id();
+                tokio::select! {
+                    out = child.wait_with_output() => match out {
+                        Ok(out) => {
+                            if out.stderr.len() > 0 {
+                                let txt = String::from_utf8_lossy(&out.stderr);
+                                if out.status.success() {
+                                    info!("From filepicker:{}", txt);
+                                } else {
+                                    error!("From filepicker:{}", txt);
+                                }
+                            }
+                            Some(out.stdout)
+                        },
+                        Err(e) => {eprintln!("Process error: {}", e); Some(vec![])},
+                    },
+                    _ = closed.notified() => {
+                        info!("Closing filepicker");
+                        if let Some(pid) = pid {
+                            unsafe { libc::killpg(pid as libc::pid_t, libc::SIGTERM); }
+                        }
+                        None
+                    },
                 }
-                unsafe { std::str::from_utf8_unchecked(&out.stdout).to_owned() }
             },
-            Err(e) => {eprintln!("Process error: {}", e); "".to_owned()},
+            Err(e) => {eprintln!("Process error: {}", e); Some(vec![])},
+        };
+        let Some(output) = output else {
+            if let Some(ref result_file) = result_file {
+                let _ = fs::remove_file(result_file);
+            }
+            return (2, HashMap::new());
         };
         let mut gotfirst = false;
         let mut arr = Vec::new();
@@ -687,7 +2322,14 @@ impl FilePicker {
             let _ = builder.add_line(None, line);
         });
         let ignorer = builder.build().ok();
-        for line in output.lines() {
+        let records = result_file.as_deref().map(read_results).unwrap_or_default();
+        // pikeru writes the paths to the result file so they can contain newlines.
+        // Wrappers that don't run pikeru only print them.
+        let mut paths: Vec<&Path> = records.iter().filter(|(k, _)| k == "path").map(|(_, v)| Path::new(v)).collect();
+        if paths.is_empty() {
+            paths = output.split(|b| *b == b'\n').filter(|l| !l.is_empty()).map(|l| Path::new(OsStr::from_bytes(l))).collect();
+        }
+        for line in paths {
             if !gotfirst {
                 gotfirst = true;
                 if let Some(par_dir) = self.get_dir(line) {
@@ -696,38 +2338,58 @@ impl FilePicker {
                         update_prevpath = match gi.matched(&par_dir, true) {Match::Ignore(_) => false, _ => true};
                     }
                     if update_prevpath {
-                        self.prev_path = par_dir;
-                        self.prev_path_set_at = SystemTime::now();
+                        *self.prev_path.lock().unwrap() = PrevPath {
+                            path: par_dir,
+                            set_at: SystemTime::now(),
+                        };
                     }
                 }
             }
-            trace!("Selected: {}", line);
-            arr.push(format!("file://{}", line));
+            trace!("Selected: {:?}", line);
+            if dialog.files.is_empty() {
+                arr.push(file_uri(line));
+            } else {
+                // SaveFiles picks a directory to put all the files in
+                arr.extend(dialog.save_paths(line).iter().map(|p| file_uri(p)));
+                break;
+            }
         }
         let mut ret = HashMap::new();
         let status = if arr.is_empty() { 1 } else {
             ret.insert("uris".to_string(), Value::from(arr).try_to_owned().unwrap());
+            dialog.results(&records, &mut ret);
             0
         };
         (status, ret)
     }
 
-    fn get_dir(self: &Self, path: &str) -> Option<String> {
-        let p = Path::new(path);
-        let parent = p.parent()?;
-        let ps =  parent.to_string_lossy();
+    /// The current_folder option, or the default save dir.
+    fn current_folder(&self, options: &HashMap<&str, Value<'_>>) -> String {
+        get_bytes_opt(options, "current_folder").unwrap_or_else(|| self.def_save_dir.clone())
+    }
+
+    fn get_dir(self: &Self, path: &Path) -> Option<String> {
+        let parent = path.parent()?;
+        // The previous path is passed on as text, so a dir that isn't UTF-8 can't be one
+        let ps = parent.to_str()?;
         if !parent.is_dir() || ps == self.postproc_dir {
             return None;
         }
-        Some(ps.to_string()) 
+        Some(ps.to_string())
     }
 
 }
 
 #[interface(name = "org.freedesktop.impl.portal.FileChooser")]
 impl FilePicker {
-    async fn open_file(&mut self, _ob: ObjectPath<'_>, _caller: &str, _parent: &str,
-                 _title: &str, options: HashMap<&str, Value<'_>>) -> (u32, HashMap<String, OwnedValue>) {
+    /// Version of the FileChooser backend interface that's implemented.
+    #[zbus(property(emits_changed_signal = "const"), name = "version")]
+    fn version(&self) -> u32 {
+        4
+    }
+
+    async fn open_file(&self, #[zbus(object_server)] server: &ObjectServer, handle: ObjectPath<'_>, _caller: &str, parent: &str,
+                 title: &str, options: HashMap<&str, Value<'_>>) -> (u32, HashMap<String, OwnedValue>) {
         let dir = match options.get("directory").unwrap_or(&Value::Bool(false)) {
             &Value::Bool(b) => b,
             _ => { error!("DIR type error"); false},
@@ -736,30 +2398,34 @@ impl FilePicker {
             &Value::Bool(b) => b,
             _ => { error!("MULTI type error"); false},
         };
-        self.select_files(multi, dir, false, "/").await
+        let closed = Request::export(server, &handle).await;
+        let folder = get_bytes_opt(&options, "current_folder").unwrap_or_default();
+        let ret = self.select_files(&closed, multi, dir, false, &folder, &Dialog::new(parent, title, &options)).await;
+        Request::remove(server, &handle).await;
+        ret
     }
 
-    async fn save_file(&mut self, _ob: ObjectPath<'_>, _caller: &str, _parent: &str,
-                 _title: &str, options: HashMap<&str, Value<'_>>) -> (u32, HashMap<String, OwnedValue>) {
-        let dir = match options.get("current_folder").unwrap_or(&Value::from(&self.def_save_dir)) {
-            Value::Array(s) => {
-                let b = to_bytes(Context::new_dbus(LE, 0), s).unwrap();
-                match std::str::from_utf8(&b[4..b.len()-1]) {
-                    Ok(s) => s.to_string(),
-                    Err(e) => {
-                        error!("Error reading dir:{}", e);
-                        self.def_save_dir.clone()
-                    },
-                }
-            },
-            _ => self.def_save_dir.clone(),
-        };
+    async fn save_file(&self, #[zbus(object_server)] server: &ObjectServer, handle: ObjectPath<'_>, _caller: &str, parent: &str,
+                 title: &str, options: HashMap<&str, Value<'_>>) -> (u32, HashMap<String, OwnedValue>) {
+        let dir = self.current_folder(&options);
         let fname = match options.get("current_name").unwrap_or(&Value::from("download")) {
             Value::Str(s) => s.to_string(),
             _ => "download".to_string(),
         };
         let path = Path::new(&dir).join(fname);
-        self.select_files(false, false, true, &path.to_string_lossy()).await
+        let closed = Request::export(server, &handle).await;
+        let ret = self.select_files(&closed, false, false, true, &path.to_string_lossy(), &Dialog::new(parent, title, &options)).await;
+        Request::remove(server, &handle).await;
+        ret
+    }
+
+    async fn save_files(&self, #[zbus(object_server)] server: &ObjectServer, handle: ObjectPath<'_>, _caller: &str, parent: &str,
+                 title: &str, options: HashMap<&str, Value<'_>>) -> (u32, HashMap<String, OwnedValue>) {
+        let dir = self.current_folder(&options);
+        let closed = Request::export(server, &handle).await;
+        let ret = self.select_files(&closed, false, true, true, &dir, &Dialog::new(parent, title, &options)).await;
+        Request::remove(server, &handle).await;
+        ret
     }
 }
 
@@ -771,8 +2437,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
     std::fs::create_dir_all(idxfile.parent().unwrap()).unwrap();
     let db = Arc::new(std::sync::Mutex::new(rusqlite::Connection::open(&idxfile).unwrap()));
     let sht = Arc::new(AsyncMtx::new(Shtate::default()));
+    let settings = SearchIgnore::load(Path::new(&config.home));
     let picker = FilePicker::new(&mut config, sht.clone(), db.clone());
     let indexer = Indexer::new(sht.clone(), &mut config, db);
+    let indexer_handle = indexer.clone();
     let service_name = config.dbus_service.clone();
     let object_path = config.dbus_object_path.clone();
     eprintln!("D-Bus: {} @ {}", service_name, object_path);
@@ -783,6 +2451,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .serve_at(&obj, indexer)?
         .build()
         .await?;
+    indexer_handle.set_signal_context(SignalContext::new(&_conn, obj)?.into_owned()).await;
+    // Follow the picker's search settings before one opens to configure them
+    indexer_handle.configure(settings.respect_gitignore, settings.patterns).await;
+    indexer_handle.resume_stored_queue().await;
+    if config.indexer_enabled && config.indexer_gc_interval > 0 {
+        tokio::spawn(indexer_handle.clone().gc_loop(Duration::from_secs(config.indexer_gc_interval)));
+    }
+    if config.indexer_enabled && !config.indexer_watch_roots.is_empty() {
+        tokio::spawn(indexer_handle.watch_loop(take(&mut config.indexer_watch_roots)));
+    }
     pending::<()>().await;
     Ok(())
 }
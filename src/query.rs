@@ -0,0 +1,318 @@
+//! The search box's query syntax. Plain words are fuzzy matched against file names and searched
+//! for in indexed descriptions like before, and these narrow the results down:
+//!
+//! ```text
+//! ext:jpg|png        extension
+//! type:video|dir     image, video, audio, dir or file
+//! size:>10M          also >=, < and <=, with K, M, G or T for powers of 1024. size:10M means at least 10M
+//! modified:<7d       changed in the last week, with min, h, d, w or y. >7d is longer ago than that
+//! modified:>2024-06-01
+//!                    changed after that day. Dates also take >=, < and <=, or alone mean on that day
+//! name:"exact"       file names containing this, ignoring case
+//! desc:beach         files whose indexed description matches, without looking at file names
+//! "two words"        file names with the phrase, or descriptions with all of its words
+//! -ext:tmp -draft    leaves out files that the filter or word would match
+//! ```
+//!
+//! A filter that doesn't parse, like `size:big`, is searched for as a plain word.
+use std::{collections::{HashMap, HashSet}, path::Path, time::SystemTime};
+
+/// What searches get to know about a file.
+#[derive(Debug, Clone)]
+pub struct SearchItem {
+    pub path: String,
+    pub size: u64,
+    /// Seconds since the epoch
+    pub mtime: i64,
+    pub dir: bool,
+    /// What the picker found the file to be when it loaded it, or None to go by its name.
+    pub kind: Option<Kind>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Image,
+    Video,
+    Audio,
+    Dir,
+    File,
+}
+
+#[derive(Debug)]
+enum Filter {
+    Ext(Vec<String>),
+    Type(Vec<Kind>),
+    /// Smallest and largest size in bytes
+    Size(u64, u64),
+    /// Earliest mtime and the first one too late, in seconds
+    Modified(i64, i64),
+    Name(String),
+    /// Which of the searches finds it
+    Desc(usize),
+    /// Lowercase phrase and which of the searches finds it
+    Phrase(String, usize),
+}
+
+#[derive(Debug, Default)]
+pub struct Query {
+    /// The plain words, for fuzzy matching names and searching descriptions.
+    text: String,
+    /// Each filter, and whether it's negated.
+    filters: Vec<(Filter, bool)>,
+    /// Text to search descriptions for, and whether its embedding should be compared too.
+    searches: Vec<(String, bool)>,
+    /// Which of the searches is for the plain words.
+    text_search: usize,
+}
+
+/// Added for a file name with the plain words in it as typed, so it comes before files that
+/// only match by description however well.
+const NAME_MATCH: i64 = 1_000_000;
+/// Most a description match of the plain words counts for, well under NAME_MATCH along with any fuzzy score.
+const MAX_DESCRIPTION: i64 = NAME_MATCH / 2;
+
+const FIELDS: &[&str] = &["ext", "type", "size", "modified", "name", "desc"];
+
+impl Query {
+    pub fn parse(input: &str) -> Self {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default();
+        let mut query = Query::default();
+        let mut words = vec![];
+        let mut chars = input.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+            let mut negated = false;
+            if chars.next_if_eq(&'-').is_some() {
+                if chars.peek().is_none_or(|c| c.is_whitespace()) {
+                    words.push("-".to_string());
+                    continue;
+                }
+                negated = true;
+            }
+            let mut field = None;
+            let mut value = String::new();
+            let mut quoted = false;
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                match c {
+                    '"' => {
+                        quoted = true;
+                        value.extend(chars.by_ref().take_while(|c| *c != '"'));
+                    },
+                    ':' if field.is_none() && !quoted && FIELDS.contains(&value.to_lowercase().as_str()) => {
+                        field = Some(std::mem::take(&mut value).to_lowercase());
+                    },
+                    c => value.push(c),
+                }
+            }
+            let filter = match field {
+                Some(ref field) => query.filter(field, &value, now),
+                None if quoted || negated => query.phrase(&value),
+                None => None,
+            };
+            match (filter, field) {
+                (Some(filter), _) => query.filters.push((filter, negated)),
+                (None, Some(field)) if negated => {
+                    let filter = query.phrase(&format!("{}:{}", field, value));
+                    query.filters.extend(filter.map(|f| (f, true)));
+                },
+                (None, Some(field)) => words.push(format!("{}:{}", field, value)),
+                (None, None) if !value.is_empty() && !quoted && !negated => words.push(value),
+                (None, None) => {},
+            }
+        }
+        query.text = words.join(" ");
+        if !query.text.is_empty() {
+            query.text_search = query.searches.len();
+            query.searches.push((query.text.clone(), true));
+        }
+        query
+    }
+
+    fn filter(&mut self, field: &str, value: &str, now: i64) -> Option<Filter> {
+        let list = |v: &str| v.split(['|', ',']).map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty()).collect::<Vec<_>>();
+        match field {
+            "ext" => {
+                let exts: Vec<String> = list(value).into_iter().map(|e| e.trim_start_matches('.').to_string()).collect();
+                (!exts.is_empty()).then_some(Filter::Ext(exts))
+            },
+            "type" => {
+                let kinds = list(value).iter().map(|k| match k.as_str() {
+                    "image" => Some(Kind::Image),
+                    "video" => Some(Kind::Video),
+                    "audio" => Some(Kind::Audio),
+                    "dir" => Some(Kind::Dir),
+                    "file" => Some(Kind::File),
+                    _ => None,
+                }).collect::<Option<Vec<_>>>()?;
+                (!kinds.is_empty()).then_some(Filter::Type(kinds))
+            },
+            "size" => {
+                let (op, n) = split_op(value);
+                let n = parse_bytes(n)?;
+                Some(match op {
+                    ">" => Filter::Size(n.checked_add(1)?, u64::MAX),
+                    "<" => Filter::Size(0, n.checked_sub(1)?),
+                    "<=" => Filter::Size(0, n),
+                    _ => Filter::Size(n, u64::MAX),
+                })
+            },
+            "modified" => {
+                let (op, v) = split_op(value);
+                if let Some((start, end)) = parse_day(v) {
+                    return Some(match op {
+                        "<" => Filter::Modified(i64::MIN, start),
+                        "<=" => Filter::Modified(i64::MIN, end),
+                        ">" => Filter::Modified(end, i64::MAX),
+                        ">=" => Filter::Modified(start, i64::MAX),
+                        _ => Filter::Modified(start, end),
+                    });
+                }
+                // An age, so < means newer
+                let since = now - parse_age(v)?;
+                Some(match op {
+                    "<" => Filter::Modified(since + 1, i64::MAX),
+                    ">" => Filter::Modified(i64::MIN, since),
+                    ">=" => Filter::Modified(i64::MIN, since + 1),
+                    _ => Filter::Modified(since, i64::MAX),
+                })
+            },
+            "name" if !value.is_empty() => Some(Filter::Name(value.to_lowercase())),
+            "desc" if !value.is_empty() => {
+                self.searches.push((value.to_string(), true));
+                Some(Filter::Desc(self.searches.len() - 1))
+            },
+            _ => None,
+        }
+    }
+
+    fn phrase(&mut self, value: &str) -> Option<Filter> {
+        if value.trim().is_empty() {
+            return None;
+        }
+        // Without the embedding, so a description only matches with all the words
+        self.searches.push((value.to_string(), false));
+        Some(Filter::Phrase(value.to_lowercase(), self.searches.len() - 1))
+    }
+
+    /// What to search descriptions for, and whether to compare embeddings too.
+    /// The results go to `score` in the same order.
+    pub fn searches(&self) -> &[(String, bool)] {
+        &self.searches
+    }
+
+    /// How well the file matches, or None if it doesn't. `found` has the files each of the searches
+    /// found with their scores, and `fuzzy` matches a file name against the plain words.
+    pub fn score(&self, item: &SearchItem, found: &[HashMap<String, i64>], fuzzy: impl Fn(&str, &str) -> Option<i64>) -> Option<i64> {
+        let path = Path::new(&item.path);
+        let name = match path.file_name() {
+            Some(n) => n.to_string_lossy(),
+            None => path.to_string_lossy(),
+        };
+        let description = |search: &usize| found.get(*search).and_then(|f| f.get(&item.path)).copied();
+        let mut score = 0;
+        for (filter, negated) in &self.filters {
+            let (hit, points) = match filter {
+                Filter::Ext(exts) => {
+                    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+                    (ext.is_some_and(|e| exts.contains(&e)), 0)
+                },
+                Filter::Type(kinds) => (kinds.contains(&kind(item)) || (!item.dir && kinds.contains(&Kind::File)), 0),
+                Filter::Size(min, max) => (!item.dir && (*min..=*max).contains(&item.size), 0),
+                Filter::Modified(from, until) => ((*from..*until).contains(&item.mtime), 0),
+                Filter::Name(part) => (name.to_lowercase().contains(part.as_str()), 0),
+                Filter::Desc(search) => match description(search) {
+                    Some(points) => (true, points),
+                    None => (false, 0),
+                },
+                Filter::Phrase(phrase, search) => {
+                    let all_words = description(search).filter(|points| *points >= word_count(phrase) * 1000);
+                    (name.to_lowercase().contains(phrase.as_str()) || all_words.is_some(), all_words.unwrap_or(0))
+                },
+            };
+            if hit == *negated {
+                return None;
+            }
+            score += points;
+        }
+        if !self.text.is_empty() {
+            match (fuzzy(&name, &self.text), found.get(self.text_search).and_then(|f| f.get(&item.path))) {
+                (None, None) => return None,
+                (a, b) => score += a.unwrap_or(0) + b.copied().unwrap_or(0).min(MAX_DESCRIPTION),
+            }
+            if name.to_lowercase().contains(&self.text.to_lowercase()) {
+                score += NAME_MATCH;
+            }
+        }
+        Some(score)
+    }
+}
+
+/// A leading comparison, if there is one, and the rest.
+fn split_op(value: &str) -> (&str, &str) {
+    for op in [">=", "<=", ">", "<"] {
+        if let Some(rest) = value.strip_prefix(op) {
+            return (op, rest);
+        }
+    }
+    ("", value)
+}
+
+fn parse_bytes(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let unit = value.trim_end_matches("ib").trim_end_matches('b');
+    let (number, scale) = match unit.char_indices().last()? {
+        (i, 'k') => (&unit[..i], 1u64 << 10),
+        (i, 'm') => (&unit[..i], 1 << 20),
+        (i, 'g') => (&unit[..i], 1 << 30),
+        (i, 't') => (&unit[..i], 1 << 40),
+        _ => (unit, 1),
+    };
+    let number: f64 = number.parse().ok().filter(|n: &f64| *n >= 0.0)?;
+    Some((number * scale as f64) as u64)
+}
+
+/// Seconds in an age like 7d, 3h or 2w. A plain number is days.
+fn parse_age(value: &str) -> Option<i64> {
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let number: f64 = value[..split].parse().ok()?;
+    let unit = match value[split..].to_lowercase().as_str() {
+        "min" => 60,
+        "h" => 3600,
+        "" | "d" => 86400,
+        "w" => 7 * 86400,
+        "y" => 365 * 86400,
+        _ => return None,
+    };
+    Some((number * unit as f64) as i64)
+}
+
+/// Local start of a YYYY-MM-DD day and of the next one.
+fn parse_day(value: &str) -> Option<(i64, i64)> {
+    let day = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    let start = |d: chrono::NaiveDate| d.and_hms_opt(0, 0, 0)?.and_local_timezone(chrono::Local).earliest().map(|t| t.timestamp());
+    Some((start(day)?, start(day.succ_opt()?)?))
+}
+
+fn kind(item: &SearchItem) -> Kind {
+    if item.dir {
+        return Kind::Dir;
+    }
+    if let Some(kind) = item.kind {
+        return kind;
+    }
+    match mime_guess::from_path(&item.path).first().as_ref().map(|m| m.type_().as_str()) {
+        Some("image") => Kind::Image,
+        Some("video") => Kind::Video,
+        Some("audio") => Kind::Audio,
+        _ => Kind::File,
+    }
+}
+
+/// Distinct words the way description searches split them.
+fn word_count(text: &str) -> i64 {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(str::to_lowercase)
+        .collect::<HashSet<_>>().len() as i64
+}
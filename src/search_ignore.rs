@@ -0,0 +1,58 @@
+//! The picker's search ignore settings in ~/.config/pikeru.conf, for what has to follow them
+//! without a picker passing them along: `pikeru index add -r` and the portal when it starts.
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+pub struct SearchIgnore {
+    /// Whether .gitignore files are followed as well.
+    pub respect_gitignore: bool,
+    /// Gitignore style lines from the [SearchIgnore] section.
+    pub patterns: String,
+}
+
+impl SearchIgnore {
+    /// The settings in the picker's config text, or the picker's defaults for missing ones.
+    pub fn parse(txt: &str, home: &Path) -> Self {
+        let thumbs = home.join(".cache").join("pikeru").join("thumbnails");
+        let mut settings = Self {
+            respect_gitignore: true,
+            patterns: format!(".git/\n{}/*\n", thumbs.to_string_lossy()),
+        };
+        let mut section = "[Commands]";
+        for line in txt.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            match line {
+                "[Commands]" | "[Settings]" | "[Bookmarks]" => section = line,
+                "[SearchIgnore]" => {
+                    section = line;
+                    settings.patterns.clear();
+                },
+                _ if section == "[SearchIgnore]" => {
+                    settings.patterns += line;
+                    settings.patterns += "\n";
+                },
+                _ if section == "[Settings]" => match line.split_once('=') {
+                    Some((k, v)) if k.trim() == "respect_gitignore" => settings.respect_gitignore = v.trim().parse().unwrap_or(true),
+                    _ => {},
+                },
+                _ => {},
+            }
+        }
+        settings
+    }
+
+    /// The settings in the picker's config under home.
+    pub fn load(home: &Path) -> Self {
+        let txt = std::fs::read_to_string(home.join(".config").join("pikeru.conf")).unwrap_or_default();
+        Self::parse(&txt, home)
+    }
+
+    pub fn matcher(&self) -> Gitignore {
+        let mut builder = GitignoreBuilder::new("");
+        for line in self.patterns.lines() {
+            if let Err(e) = builder.add_line(None, line) {
+                eprintln!("Bad search ignore pattern {}: {}", line, e);
+            }
+        }
+        builder.build().unwrap_or(Gitignore::empty())
+    }
+}
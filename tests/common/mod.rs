@@ -1,11 +1,13 @@
 //! Shared test infrastructure for the xdg-desktop-portal-pikeru backend.
 //! All test artifacts live under /tmp/pikeru_tests and are cleaned up automatically.
-//! Tests use unique D-Bus service names (t0001, t0002, ...) to avoid collision
-//! with the system-installed portal.
+//! Tests use unique D-Bus service names (t0001, t0002, ...) on a private
+//! session bus, so no desktop session is needed.
 
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::OnceLock;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tempfile::TempDir;
@@ -38,6 +40,34 @@ pub fn test_workspace() -> TempDir {
     TempDir::new_in(ws.parent().unwrap()).unwrap()
 }
 
+// ---------------------------------------------------------------------------
+// Private session bus
+// ---------------------------------------------------------------------------
+
+/// Address of a dbus-daemon started for this test process. It's killed when
+/// the process exits and closes the daemon's stdin.
+pub fn session_bus() -> &'static str {
+    static BUS: OnceLock<(String, Child)> = OnceLock::new();
+    &BUS.get_or_init(|| {
+        let mut child = Command::new("sh")
+            .args(["-c", "dbus-daemon --session --nofork --print-address=1 & pid=$!; read _; kill $pid"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn().expect("Failed to start dbus-daemon");
+        let mut address = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        assert!(!address.trim().is_empty(), "dbus-daemon printed no address");
+        (address.trim().to_string(), child)
+    }).0
+}
+
+/// python3 connected to the private session bus.
+fn python3() -> Command {
+    let mut cmd = Command::new("python3");
+    cmd.env("DBUS_SESSION_BUS_ADDRESS", session_bus());
+    cmd
+}
+
 // ---------------------------------------------------------------------------
 // Mock file picker script — pure mock, never launches real pikeru
 // ---------------------------------------------------------------------------
@@ -65,6 +95,24 @@ pub fn create_mock_indexer(workspace: &TempDir, description: &str) -> PathBuf {
     script
 }
 
+/// Embedder that maps text after --text, or a file's contents, to how many times it mentions
+/// [cats, dogs, cars] as a JSON array. Each call's args are appended to the returned log.
+#[allow(dead_code)]
+pub fn create_mock_embedder(workspace: &TempDir) -> (PathBuf, PathBuf) {
+    let script = workspace.path().join("mock-embedder.sh");
+    let log = workspace.path().join("embed-calls");
+    std::fs::write(&script, format!(r#"#!/bin/bash
+echo "$@" >> '{}'
+if [ "$1" = --text ]; then text="$2"; else text=$(cat "$1"); fi
+count() {{ grep -oiE "$1" <<< "$text" | wc -l; }}
+echo "[$(count 'cat|kitty|feline'), $(count 'dog|puppy'), $(count 'car')]"
+"#, log.display())).unwrap();
+    let mut perms = std::fs::metadata(&script).unwrap().permissions();
+    perms.set_mode(0o755);
+    std::fs::set_permissions(&script, perms).unwrap();
+    (script, log)
+}
+
 #[allow(dead_code)]
 /// Creates a mock wrapper that sleeps for approximately `delay_ms` milliseconds
 /// per invocation (using Python for sub-second precision), then echoes the
@@ -89,11 +137,66 @@ pub fn create_slow_mock_wrapper(workspace: &TempDir, delay_ms: u64, outputs: &[&
     script
 }
 
+/// Creates a mock wrapper that records its arguments (one per line) in
+/// `capture`, writes `records` to the $PK_RESULT file the way pikeru does,
+/// and prints `outputs` as the selected paths.
+#[allow(dead_code)]
+pub fn create_result_wrapper(workspace: &TempDir, capture: &Path, records: &[&str], outputs: &[&str]) -> PathBuf {
+    let wrapper = workspace.path().join("result-wrapper.sh");
+    let mut script = String::from("#!/bin/bash\n");
+    script.push_str(&format!("printf '%s\\n' \"$@\" > '{}'\n", capture.display()));
+    script.push_str("[ -n \"$PK_RESULT\" ] && printf '");
+    for rec in records {
+        script.push_str(&format!("{}\\0", rec.replace('%', "%%").replace('\'', "'\\''")));
+    }
+    script.push_str("' > \"$PK_RESULT\"\n");
+    for output in outputs {
+        script.push_str(&format!("echo \"{}\"\n", output));
+    }
+    std::fs::write(&wrapper, script).unwrap();
+    let mut perms = std::fs::metadata(&wrapper).unwrap().permissions();
+    perms.set_mode(0o755);
+    std::fs::set_permissions(&wrapper, perms).unwrap();
+    wrapper
+}
+
+/// Writes a portal.conf using `wrapper_path` as the file picker and returns
+/// the database path plus a unique service name and object path.
+pub fn write_test_config(
+    workspace: &TempDir,
+    wrapper_path: &str,
+    indexer_cmd: &str,
+    indexer_check: &str,
+    extensions: &str,
+) -> (PathBuf, String, String) {
+    let conf = workspace.path().join("portal.conf");
+    let content = format!(
+        r#"log_level = trace
+
+[filepicker]
+cmd = {}
+default_save_dir = /tmp/psave
+
+[indexer]
+enable = true
+cmd = {}
+check = {}
+extensions = {}
+"#,
+        wrapper_path, indexer_cmd, indexer_check, extensions
+    );
+    std::fs::write(&conf, content).unwrap();
+    let (service_name, object_path) = make_unique_name();
+    let db_path = workspace.path().join("test.db");
+    (db_path, service_name, object_path)
+}
+
 pub fn create_test_dir(workspace: &TempDir, filenames: &[&str]) -> PathBuf {
     let dir = workspace.path().join("testdir");
     std::fs::create_dir_all(&dir).unwrap();
     for name in filenames {
         let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
         std::fs::write(&path, format!("content of {}", name)).unwrap();
         let epoch = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
         filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(epoch)).unwrap();
@@ -171,11 +274,18 @@ impl PortalGuard {
 
 
     pub fn new(db_path: &str, config_path: &str) -> Self {
+        Self::with_env(db_path, config_path, &[])
+    }
+
+    /// Start the portal with extra environment variables, such as a HOME with its own picker config.
+    pub fn with_env(db_path: &str, config_path: &str, env: &[(&str, &std::path::Path)]) -> Self {
         let (svc, obj) = make_unique_name();
         let portal_bin = env!("CARGO_BIN_EXE_portal");
         let mut child = std::process::Command::new(portal_bin)
             .args(["-c", config_path, "-d", db_path])
             .args(["-s", &svc]).args(["-p", &obj])
+            .env("DBUS_SESSION_BUS_ADDRESS", session_bus())
+            .envs(env.iter().copied())
             .spawn().expect("Failed to spawn portal binary");
 
         // Verify portal is still running before waiting
@@ -189,7 +299,7 @@ impl PortalGuard {
             .to_string_lossy().into_owned();
         let mut waited = 0;
         loop {
-            let output = Command::new("python3")
+            let output = python3()
                 .args([&script, "_ping", &svc])
                 .output();
             if let Ok(out) = output {
@@ -228,7 +338,35 @@ impl Drop for PortalGuard {
 // Test client — uses dbus-python via the python helper script
 // ---------------------------------------------------------------------------
 
-pub struct OpenResult { pub status: u32, pub uris: Vec<String> }
+pub struct OpenResult {
+    pub status: u32,
+    pub uris: Vec<String>,
+    /// `name|type:pattern|...` from the `current_filter` result key.
+    pub current_filter: Option<String>,
+    /// `(id, value)` pairs from the `choices` result key, if present.
+    pub choices: Option<Vec<(String, String)>>,
+}
+
+#[allow(dead_code)]
+pub struct SignalListener {
+    child: std::process::Child,
+    stdout: std::io::BufReader<std::process::ChildStdout>,
+}
+
+#[allow(dead_code)]
+impl SignalListener {
+    /// Waits for the listener to time out and returns the signals as `(name, args)`.
+    pub fn finish(mut self) -> Vec<(String, Vec<String>)> {
+        let mut out = String::new();
+        std::io::Read::read_to_string(&mut self.stdout, &mut out).unwrap();
+        let _ = self.child.wait();
+        out.lines().filter_map(|line| {
+            let (name, args) = line.split_once(':')?;
+            let args = if args.is_empty() { vec![] } else { args.split('|').map(String::from).collect() };
+            Some((name.to_string(), args))
+        }).collect()
+    }
+}
 
 pub struct PortalClient {
     service_name: String,
@@ -249,11 +387,48 @@ impl PortalClient {
 
         let script_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
             .join("tests/dbus_call.py").to_string_lossy().into_owned();
-        let output = Command::new("python3")
+        let output = python3()
             .args([&script_path, "open_file", &self.service_name, &self.object_path, multi_str, dir_str])
             .output()
             .map_err(|e| format!("python3 failed: {}", e))?;
 
+        Self::_parse_open_output(output)
+    }
+
+    /// Calls OpenFile with options given as a JSON object, e.g.
+    /// `{"multiple": true, "filters": [["Images", [[0, "*.png"], [1, "image/jpeg"]]]]}`.
+    /// `"_title"`, `"_parent"` and `"_handle"` keys set the window title, parent window
+    /// and request handle.
+    #[allow(dead_code)]
+    pub fn open_file_with(&self, options_json: &str) -> Result<OpenResult, String> {
+        self._call_with_opts("open_file_opts", options_json)
+    }
+
+    /// Calls SaveFile with options given as a JSON object, like `open_file_with`.
+    #[allow(dead_code)]
+    pub fn save_file_with(&self, options_json: &str) -> Result<OpenResult, String> {
+        self._call_with_opts("save_file_opts", options_json)
+    }
+
+    /// Calls SaveFiles with options given as a JSON object, e.g.
+    /// `{"current_folder": "/tmp", "files": ["a.txt", "b.txt"]}`.
+    #[allow(dead_code)]
+    pub fn save_files_with(&self, options_json: &str) -> Result<OpenResult, String> {
+        self._call_with_opts("save_files_opts", options_json)
+    }
+
+    fn _call_with_opts(&self, method: &str, options_json: &str) -> Result<OpenResult, String> {
+        self._wait_for_portal()?;
+        let script_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/dbus_call.py").to_string_lossy().into_owned();
+        let output = python3()
+            .args([&script_path, method, &self.service_name, &self.object_path, options_json])
+            .output()
+            .map_err(|e| format!("python3 failed: {}", e))?;
+        Self::_parse_open_output(output)
+    }
+
+    fn _parse_open_output(output: std::process::Output) -> Result<OpenResult, String> {
         if !output.status.success() {
             return Err(format!("dbus call failed: {}", String::from_utf8_lossy(&output.stderr)));
         }
@@ -262,8 +437,9 @@ impl PortalClient {
         if !stdout.starts_with("STATUS:") {
             return Err(format!("unexpected output: {}", stdout));
         }
+        let mut lines = stdout.lines();
 
-        let rest = &stdout[7..]; // skip "STATUS:"
+        let rest = &lines.next().unwrap()[7..]; // skip "STATUS:"
         let parts: Vec<&str> = rest.splitn(2, ' ').collect();
         let status: u32 = parts[0].parse::<u32>().map_err(|e| e.to_string())?;
 
@@ -277,7 +453,18 @@ impl PortalClient {
             Vec::new()
         };
 
-        Ok(OpenResult { status, uris })
+        let mut result = OpenResult { status, uris, current_filter: None, choices: None };
+        for line in lines {
+            if let Some(filter) = line.strip_prefix("CURRENT_FILTER:") {
+                result.current_filter = Some(filter.to_string());
+            } else if let Some(choices) = line.strip_prefix("CHOICES:") {
+                result.choices = Some(choices.split('|')
+                    .filter_map(|c| c.split_once('='))
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect());
+            }
+        }
+        Ok(result)
     }
 
     pub fn configure_indexer(&self, respect_gitignore: bool, search_ignore: &str) -> Result<(), String> {
@@ -293,16 +480,128 @@ impl PortalClient {
         self._dbus_method("update", &args.iter().map(|s| s.as_str()).collect::<Vec<&str>>())
     }
 
+    /// Calls SearchIndexer.UpdateRecursive, 0 meaning no depth limit.
+    pub fn update_index_recursive(&self, root: &str, max_depth: u32) -> Result<(), String> {
+        self._wait_for_portal()?;
+        self._dbus_method("update_recursive", &[&self.service_name, &self.object_path, root, &max_depth.to_string()])
+    }
+
     pub fn clear_index_queue(&self) -> Result<(), String> {
         self._wait_for_portal()?;
         self._dbus_method("clear_queue", &[&self.service_name, &self.object_path])
     }
 
+    /// Reads SearchIndexer.GetStatus as `(queued, files_done, files_failed, online, current_dir)`.
+    #[allow(dead_code)]
+    pub fn index_status(&self) -> Result<(u32, u32, u32, bool, String), String> {
+        self._wait_for_portal()?;
+        let output = self._dbus_output("get_status")?;
+        let status = output.trim_end_matches('\n').strip_prefix("STATUS:")
+            .ok_or_else(|| format!("unexpected output: {}", output))?;
+        let mut parts = status.splitn(5, '|');
+        let mut num = || parts.next().and_then(|n| n.parse().ok()).ok_or_else(|| format!("bad status: {}", status));
+        let (queued, done, failed) = (num()?, num()?, num()?);
+        let online = parts.next() == Some("true");
+        Ok((queued, done, failed, online, parts.next().unwrap_or("").to_string()))
+    }
+
+    /// Calls SearchIndexer.Pause, or Resume when `pause` is false.
+    #[allow(dead_code)]
+    pub fn pause_indexer(&self, pause: bool) -> Result<(), String> {
+        self._wait_for_portal()?;
+        self._dbus_method(if pause { "pause" } else { "resume" }, &[&self.service_name, &self.object_path])
+    }
+
+    /// Reads the SearchIndexer.Paused property.
+    #[allow(dead_code)]
+    pub fn index_paused(&self) -> Result<bool, String> {
+        self._wait_for_portal()?;
+        let output = self._dbus_output("get_paused")?;
+        match output.trim() {
+            "PAUSED:true" => Ok(true),
+            "PAUSED:false" => Ok(false),
+            other => Err(format!("unexpected output: {}", other)),
+        }
+    }
+
+    /// Calls SearchIndexer.Embed, returning the vector or the D-Bus error.
+    #[allow(dead_code)]
+    pub fn embed(&self, text: &str) -> Result<Vec<f64>, String> {
+        self._wait_for_portal()?;
+        let script = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/dbus_call.py").to_string_lossy().into_owned();
+        let output = python3()
+            .args([&script, "embed", &self.service_name, &self.object_path, text])
+            .output()
+            .map_err(|e| format!("python3 failed: {}", e))?;
+        let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        match stdout.strip_prefix("VECTOR:") {
+            Some("") => Ok(vec![]),
+            Some(v) => v.split(',').map(|f| f.parse().map_err(|_| format!("bad vector: {}", v))).collect(),
+            None => Err(stdout),
+        }
+    }
+
+    /// Starts recording SearchIndexer signals for `seconds`, returning once the match rule is in place.
+    #[allow(dead_code)]
+    pub fn listen_index_signals(&self, seconds: f32) -> SignalListener {
+        let script = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/dbus_call.py").to_string_lossy().into_owned();
+        let mut child = python3()
+            .args([&script, "listen_signals", &self.service_name, &self.object_path, &seconds.to_string()])
+            .stdout(std::process::Stdio::piped())
+            .spawn().expect("Failed to spawn signal listener");
+        let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+        let mut ready = String::new();
+        std::io::BufRead::read_line(&mut stdout, &mut ready).unwrap();
+        assert_eq!(ready.trim(), "READY");
+        SignalListener { child, stdout }
+    }
+
+    /// Calls Close on the Request object the portal exports at `handle`
+    /// (/org/test/handle unless the call set `"_handle"`).
+    #[allow(dead_code)]
+    pub fn close_request(&self, handle: &str) -> Result<(), String> {
+        self._dbus_method("close_request", &[&self.service_name, handle])
+    }
+
+    /// Reads the FileChooser `version` property.
+    #[allow(dead_code)]
+    pub fn version(&self) -> Result<u32, String> {
+        self._wait_for_portal()?;
+        let output = self._dbus_output("get_version")?;
+        output.trim().strip_prefix("VERSION:").and_then(|v| v.parse().ok())
+            .ok_or_else(|| format!("unexpected output: {}", output))
+    }
+
+    /// FileChooser methods from introspection as `(name, in signature, out signature)`.
+    #[allow(dead_code)]
+    pub fn introspect_methods(&self) -> Result<Vec<(String, String, String)>, String> {
+        self._wait_for_portal()?;
+        Ok(self._dbus_output("introspect")?.lines().filter_map(|line| {
+            let mut parts = line.splitn(3, ':').map(String::from);
+            Some((parts.next()?, parts.next()?, parts.next()?))
+        }).collect())
+    }
+
+    fn _dbus_output(&self, method: &str) -> Result<String, String> {
+        let script = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/dbus_call.py").to_string_lossy().into_owned();
+        let output = python3()
+            .args([&script, method, &self.service_name, &self.object_path])
+            .output()
+            .map_err(|e| format!("python3 failed: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("{} failed: {}", method, String::from_utf8_lossy(&output.stderr)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
     fn _wait_for_portal(&self) -> Result<(), String> {
         let script = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
             .join("tests/dbus_call.py").to_string_lossy().into_owned();
         for wait_ms in [20, 50, 100, 100, 100, 100, 200, 200, 300] {
-            let output = Command::new("python3")
+            let output = python3()
                 .args([&script, "_ping", &self.service_name])
                 .output()
                 .map_err(|e| format!("ping failed: {}", e))?;
@@ -320,7 +619,7 @@ impl PortalClient {
         let mut cmd_args: Vec<String> = vec![script, method.to_string()];
         for a in args { cmd_args.push(a.to_string()); }
 
-        let output = Command::new("python3")
+        let output = python3()
             .args(&cmd_args)
             .output()
             .map_err(|e| format!("python3 failed: {}", e))?;
@@ -1,6 +1,8 @@
 #!/usr/bin/env python3
 """D-Bus caller for the pikeru portal backend using dbus-python."""
 import sys
+import json
+from xml.etree import ElementTree
 import dbus
 
 
@@ -29,6 +31,71 @@ def call_open_file(service, path, multiple, directory):
     print("STATUS:%d URIS:%s" % (status, '|'.join(uris)))
 
 
+def _filter(f):
+    name, patterns = f
+    return dbus.Struct((name, dbus.Array([dbus.Struct((dbus.UInt32(t), p), signature='us')
+                                          for t, p in patterns], signature='(us)')), signature='sa(us)')
+
+
+def _choice(c):
+    id, label, options, initial = c
+    return dbus.Struct((id, label, dbus.Array([dbus.Struct((k, v), signature='ss') for k, v in options],
+                                              signature='(ss)'), initial), signature='ssa(ss)s')
+
+
+def build_options(spec):
+    """Convert a JSON options object into the D-Bus types the portal expects."""
+    opts = {}
+    for key, val in spec.items():
+        if isinstance(val, bool):
+            opts[key] = dbus.Boolean(val)
+        elif key == "filters":
+            opts[key] = dbus.Array([_filter(f) for f in val], signature='(sa(us))')
+        elif key == "current_filter":
+            opts[key] = _filter(val)
+        elif key in ("current_folder", "current_file"):
+            opts[key] = _path(val)
+        elif key == "files":
+            opts[key] = dbus.Array([_path(f) for f in val], signature='ay')
+        elif key == "choices":
+            opts[key] = dbus.Array([_choice(c) for c in val], signature='(ssa(ss)s)')
+        else:
+            opts[key] = val
+    return opts
+
+
+def _path(p):
+    return dbus.ByteArray(p.encode() + b'\0')
+
+
+def call_with_opts(service, path, method, spec):
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, path)
+    proxy = dbus.Interface(obj, "org.freedesktop.impl.portal.FileChooser")
+    spec = json.loads(spec)
+    title = spec.pop("_title", "Test")
+    handle = spec.pop("_handle", "/org/test/handle")
+    parent = spec.pop("_parent", "")
+    result = getattr(proxy, method)(
+        handle,
+        ":org.test.client",
+        parent,
+        title,
+        build_options(spec))
+    print_results(result)
+
+
+def print_results(result):
+    status = int(result[0])
+    uris = [str(u) for u in result[1].get('uris', [])]
+    print("STATUS:%d URIS:%s" % (status, '|'.join(uris)))
+    if 'current_filter' in result[1]:
+        name, patterns = result[1]['current_filter']
+        print("CURRENT_FILTER:%s" % '|'.join([str(name)] + ["%d:%s" % (t, p) for t, p in patterns]))
+    if 'choices' in result[1]:
+        print("CHOICES:%s" % '|'.join("%s=%s" % (k, v) for k, v in result[1]['choices']))
+
+
 def call_configure(service, path, respect_gitignore, search_ignore):
     bus = dbus.SessionBus()
     obj = bus.get_object(service, path)
@@ -51,6 +118,17 @@ def call_update(service, path, dirs):
         print("ERROR:%s" % str(e))
 
 
+def call_update_recursive(service, path, root, max_depth):
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, path)
+    proxy = dbus.Interface(obj, "org.freedesktop.impl.portal.SearchIndexer")
+    try:
+        proxy.UpdateRecursive(root, dbus.UInt32(max_depth))
+        print("OK")
+    except Exception as e:
+        print("ERROR:%s" % str(e))
+
+
 def call_clear_queue(service, path):
     bus = dbus.SessionBus()
     obj = bus.get_object(service, path)
@@ -62,6 +140,95 @@ def call_clear_queue(service, path):
         print("ERROR:%s" % str(e))
 
 
+def call_pause(service, path, method):
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, path)
+    proxy = dbus.Interface(obj, "org.freedesktop.impl.portal.SearchIndexer")
+    try:
+        getattr(proxy, method)()
+        print("OK")
+    except Exception as e:
+        print("ERROR:%s" % str(e))
+
+
+def get_paused(service, path):
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, path)
+    props = dbus.Interface(obj, "org.freedesktop.DBus.Properties")
+    paused = props.Get("org.freedesktop.impl.portal.SearchIndexer", "Paused")
+    print("PAUSED:%s" % ("true" if paused else "false"))
+
+
+def call_get_status(service, path):
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, path)
+    proxy = dbus.Interface(obj, "org.freedesktop.impl.portal.SearchIndexer")
+    queued, current_dir, done, failed, online = proxy.GetStatus()
+    print("STATUS:%d|%d|%d|%s|%s" % (queued, done, failed, "true" if online else "false", current_dir))
+
+
+def call_embed(service, path, text):
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, path)
+    proxy = dbus.Interface(obj, "org.freedesktop.impl.portal.SearchIndexer")
+    try:
+        print("VECTOR:" + ",".join(repr(float(f)) for f in proxy.Embed(text)))
+    except Exception as e:
+        print("ERROR:%s" % str(e))
+
+
+def listen_signals(service, path, seconds):
+    """Print each SearchIndexer signal as NAME:ARG|ARG... for `seconds`, after a READY line."""
+    from dbus.mainloop.glib import DBusGMainLoop
+    from gi.repository import GLib
+    DBusGMainLoop(set_as_default=True)
+    bus = dbus.SessionBus()
+
+    def handler(*args, member=None):
+        print("%s:%s" % (member, "|".join(str(a) for a in args)), flush=True)
+
+    bus.add_signal_receiver(handler, dbus_interface="org.freedesktop.impl.portal.SearchIndexer",
+                            bus_name=service, path=path, member_keyword="member")
+    loop = GLib.MainLoop()
+    GLib.timeout_add(int(float(seconds) * 1000), loop.quit)
+    print("READY", flush=True)
+    loop.run()
+
+
+def call_close_request(service, handle):
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, handle)
+    proxy = dbus.Interface(obj, "org.freedesktop.impl.portal.Request")
+    try:
+        proxy.Close()
+        print("OK")
+    except Exception as e:
+        print("ERROR:%s" % str(e))
+
+
+def call_get_version(service, path):
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, path)
+    props = dbus.Interface(obj, "org.freedesktop.DBus.Properties")
+    print("VERSION:%d" % int(props.Get("org.freedesktop.impl.portal.FileChooser", "version")))
+
+
+def call_introspect(service, path):
+    """Print each FileChooser method as NAME:IN_SIGNATURE:OUT_SIGNATURE."""
+    bus = dbus.SessionBus()
+    obj = bus.get_object(service, path)
+    xml = obj.Introspect(dbus_interface="org.freedesktop.DBus.Introspectable")
+    root = ElementTree.fromstring(xml)
+    for iface in root.findall("interface"):
+        if iface.get("name") != "org.freedesktop.impl.portal.FileChooser":
+            continue
+        for method in iface.findall("method"):
+            args = method.findall("arg")
+            ins = "".join(a.get("type") for a in args if a.get("direction", "in") == "in")
+            outs = "".join(a.get("type") for a in args if a.get("direction") == "out")
+            print("%s:%s:%s" % (method.get("name"), ins, outs))
+
+
 def _ping(service):
     bus = dbus.SessionBus()
     try:
@@ -80,12 +247,38 @@ if __name__ == "__main__":
 
     if method == "open_file":
         call_open_file(service, path, sys.argv[4] == "true", sys.argv[5] == "true")
+    elif method == "open_file_opts":
+        call_with_opts(service, path, "OpenFile", sys.argv[4])
+    elif method == "save_file_opts":
+        call_with_opts(service, path, "SaveFile", sys.argv[4])
+    elif method == "save_files_opts":
+        call_with_opts(service, path, "SaveFiles", sys.argv[4])
     elif method == "configure":
         call_configure(service, path, sys.argv[4] == "true", sys.argv[5] if len(sys.argv) > 5 else "")
     elif method == "update":
         dirs = sys.argv[4:] if len(sys.argv) > 4 else []
         call_update(service, path, dirs)
+    elif method == "update_recursive":
+        call_update_recursive(service, path, sys.argv[4], int(sys.argv[5]))
     elif method == "clear_queue":
         call_clear_queue(service, path)
+    elif method == "get_status":
+        call_get_status(service, path)
+    elif method == "pause":
+        call_pause(service, path, "Pause")
+    elif method == "resume":
+        call_pause(service, path, "Resume")
+    elif method == "get_paused":
+        get_paused(service, path)
+    elif method == "embed":
+        call_embed(service, path, sys.argv[4])
+    elif method == "listen_signals":
+        listen_signals(service, path, sys.argv[4])
+    elif method == "close_request":
+        call_close_request(service, path)
+    elif method == "get_version":
+        call_get_version(service, path)
+    elif method == "introspect":
+        call_introspect(service, path)
     else:
         print("ERROR:unknown %s" % method, file=sys.stderr); sys.exit(1)
@@ -0,0 +1,259 @@
+//! Conformance tests for the org.freedesktop.impl.portal.FileChooser backend interface, version 4.
+//! Each test drives the portal over a private session bus with a mock picker that records
+//! its arguments, so every option and result key can be checked without a desktop.
+
+#[allow(dead_code)]
+mod common;
+use common::*;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+struct Picker {
+    ws: TempDir,
+    guard: PortalGuard,
+    capture: PathBuf,
+}
+
+impl Picker {
+    /// Starts a portal whose picker writes `records` to $PK_RESULT and prints `outputs`.
+    fn new(records: &[&str], outputs: &[&str]) -> Self {
+        Self::in_workspace(test_workspace(), records, outputs)
+    }
+
+    fn in_workspace(ws: TempDir, records: &[&str], outputs: &[&str]) -> Self {
+        let capture = ws.path().join("args.txt");
+        let wrapper = create_result_wrapper(&ws, &capture, records, outputs);
+        let (db_path, _svc, _obj) = write_test_config(
+            &ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+        );
+        let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+        Self { ws, guard, capture }
+    }
+
+    fn client(&self) -> PortalClient {
+        PortalClient::new(&self.guard.service_name, &self.guard.object_path)
+    }
+
+    /// Arguments the picker was run with: multiple, directory, save, path, then pikeru options.
+    fn args(&self) -> Vec<String> {
+        fs::read_to_string(&self.capture).unwrap().lines().map(String::from).collect()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Interface
+// ---------------------------------------------------------------------------
+
+#[test]
+fn version_property_is_4() {
+    let picker = Picker::new(&[], &[]);
+    assert_eq!(picker.client().version(), Ok(4));
+}
+
+#[test]
+fn methods_have_spec_signatures() {
+    let picker = Picker::new(&[], &[]);
+    let mut methods = picker.client().introspect_methods().expect("introspection should work");
+    methods.sort();
+    let expected = ["OpenFile", "SaveFile", "SaveFiles"].map(|name| {
+        (name.to_string(), "osssa{sv}".to_string(), "ua{sv}".to_string())
+    });
+    assert_eq!(methods, expected);
+}
+
+// ---------------------------------------------------------------------------
+// OpenFile
+// ---------------------------------------------------------------------------
+
+#[test]
+fn open_file_single() {
+    let picker = Picker::new(&[], &["/tmp/one.txt"]);
+    let result = picker.client().open_file_with("{}").unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, ["file:///tmp/one.txt"]);
+    assert_eq!(&picker.args()[..3], ["0", "0", "0"]);
+}
+
+#[test]
+fn open_file_multiple() {
+    let picker = Picker::new(&[], &["/tmp/one.txt", "/tmp/two.txt"]);
+    let result = picker.client().open_file_with(r#"{"multiple": true}"#).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, ["file:///tmp/one.txt", "file:///tmp/two.txt"]);
+    assert_eq!(&picker.args()[..3], ["1", "0", "0"]);
+}
+
+#[test]
+fn open_file_directory() {
+    let picker = Picker::new(&[], &["/tmp"]);
+    let result = picker.client().open_file_with(r#"{"directory": true}"#).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, ["file:///tmp"]);
+    assert_eq!(&picker.args()[..3], ["0", "1", "0"]);
+}
+
+#[test]
+fn open_file_uris_are_percent_encoded() {
+    let picker = Picker::new(&["path=/tmp/a #1?.txt", "path=/tmp/café/100%.txt"], &[]);
+    let result = picker.client().open_file_with(r#"{"multiple": true}"#).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, ["file:///tmp/a%20%231%3F.txt", "file:///tmp/caf%C3%A9/100%25.txt"]);
+}
+
+#[test]
+fn open_file_uris_keep_non_utf8_bytes() {
+    // printf makes \377 a lone 0xFF byte, in the result file and on stdout
+    let picker = Picker::new(&["path=/tmp/bad\\377.txt"], &[]);
+    let result = picker.client().open_file_with("{}").unwrap();
+    assert_eq!(result.uris, ["file:///tmp/bad%FF.txt"]);
+    let picker = Picker::new(&[], &["/tmp/raw$(printf '\\377').txt"]);
+    let result = picker.client().open_file_with("{}").unwrap();
+    assert_eq!(result.uris, ["file:///tmp/raw%FF.txt"]);
+}
+
+#[test]
+fn result_file_is_private() {
+    // The picker prints the modes of the result file and its dir as the path
+    let picker = Picker::new(&[], &[r#"/tmp/$(stat -c %a "$PK_RESULT")-$(stat -c %a "$(dirname "$PK_RESULT")")"#]);
+    let result = picker.client().open_file_with("{}").unwrap();
+    assert_eq!(result.uris, ["file:///tmp/600-700"]);
+}
+
+#[test]
+fn open_file_current_folder() {
+    let picker = Picker::new(&[], &["/tmp/one.txt"]);
+    let folder = create_test_dir(&picker.ws, &[]);
+    let result = picker.client().open_file_with(&format!(r#"{{"current_folder": "{}"}}"#, folder.display())).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(picker.args()[3], folder.to_str().unwrap());
+}
+
+#[test]
+fn open_file_accept_label_and_modal() {
+    let picker = Picker::new(&[], &["/tmp/one.txt"]);
+    let result = picker.client().open_file_with(r#"{"_title": "", "accept_label": "_Attach", "modal": false}"#).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(&picker.args()[4..], ["--accept-label", "Attach", "--nonmodal"]);
+}
+
+#[test]
+fn open_file_filters_and_current_filter() {
+    let picker = Picker::new(&["filter=0"], &["/tmp/one.png"]);
+    let result = picker.client().open_file_with(r#"{
+        "_title": "",
+        "filters": [["Images", [[0, "*.png"], [1, "image/*"]]], ["All", [[0, "*"]]]],
+        "current_filter": ["All", [[0, "*"]]]
+    }"#).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(&picker.args()[4..], [
+        "--filter", "Images|glob:*.png|mime:image/*", "--filter", "All|glob:*", "--current-filter", "All",
+    ]);
+    assert_eq!(result.current_filter.as_deref(), Some("Images|0:*.png|1:image/*"));
+}
+
+#[test]
+fn open_file_choices() {
+    let picker = Picker::new(&["choice.0=b", "choice.1=true"], &["/tmp/one.txt"]);
+    let result = picker.client().open_file_with(r#"{
+        "_title": "",
+        "choices": [["pick", "Pick", [["a", "A"], ["b", "B"]], "a"], ["flag", "Flag", [], "false"]]
+    }"#).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(&picker.args()[4..], ["--choice", "pick|Pick|a|a|A|b|B", "--choice", "flag|Flag|false"]);
+    assert_eq!(result.choices, Some(vec![
+        ("pick".to_string(), "b".to_string()),
+        ("flag".to_string(), "true".to_string()),
+    ]));
+}
+
+#[test]
+fn open_file_cancelled() {
+    let picker = Picker::new(&["filter=0", "choice.0=true"], &[]);
+    let result = picker.client().open_file_with(r#"{
+        "filters": [["All", [[0, "*"]]]],
+        "choices": [["flag", "Flag", [], "false"]]
+    }"#).unwrap();
+    assert_eq!(result.status, 1);
+    assert!(result.uris.is_empty());
+    assert_eq!(result.current_filter, None);
+    assert_eq!(result.choices, None);
+}
+
+#[test]
+fn unknown_options_ignored() {
+    let picker = Picker::new(&[], &["/tmp/one.txt"]);
+    let result = picker.client().open_file_with(r#"{"_title": "", "not_an_option": "x", "multiple": "wrong type"}"#).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(&picker.args()[..3], ["0", "0", "0"]);
+    assert_eq!(picker.args().len(), 4);
+}
+
+// ---------------------------------------------------------------------------
+// SaveFile
+// ---------------------------------------------------------------------------
+
+#[test]
+fn save_file_current_folder_and_name() {
+    let picker = Picker::new(&[], &["/tmp/psave/report.txt"]);
+    let folder = create_test_dir(&picker.ws, &[]);
+    let result = picker.client().save_file_with(&format!(
+        r#"{{"current_folder": "{}", "current_name": "report.txt"}}"#, folder.display())).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, ["file:///tmp/psave/report.txt"]);
+    let args = picker.args();
+    assert_eq!(&args[..3], ["0", "0", "1"]);
+    assert_eq!(args[3], folder.join("report.txt").to_str().unwrap());
+}
+
+#[test]
+fn save_file_current_file() {
+    let picker = Picker::new(&[], &["/tmp/psave/report.txt"]);
+    let existing = create_test_dir(&picker.ws, &["old.txt"]).join("old.txt");
+    let result = picker.client().save_file_with(&format!(
+        r#"{{"current_name": "new.txt", "current_file": "{}"}}"#, existing.display())).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(picker.args()[3], existing.to_str().unwrap());
+}
+
+#[test]
+fn save_file_filters_and_choices() {
+    let picker = Picker::new(&["filter=1", "choice.0=latin1"], &["/tmp/psave/report.txt"]);
+    let result = picker.client().save_file_with(r#"{
+        "_title": "",
+        "current_name": "report.txt",
+        "filters": [["Text", [[1, "text/plain"]]], ["Markdown", [[0, "*.md"]]]],
+        "choices": [["enc", "Encoding", [["utf8", "UTF-8"], ["latin1", "Latin-1"]], "utf8"]]
+    }"#).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(&picker.args()[4..], [
+        "--filter", "Text|mime:text/plain", "--filter", "Markdown|glob:*.md",
+        "--choice", "enc|Encoding|utf8|utf8|UTF-8|latin1|Latin-1",
+    ]);
+    assert_eq!(result.current_filter.as_deref(), Some("Markdown|0:*.md"));
+    assert_eq!(result.choices, Some(vec![("enc".to_string(), "latin1".to_string())]));
+}
+
+// ---------------------------------------------------------------------------
+// SaveFiles
+// ---------------------------------------------------------------------------
+
+#[test]
+fn save_files_into_chosen_folder() {
+    let ws = test_workspace();
+    let target = create_test_dir(&ws, &["b.txt"]);
+    let picker = Picker::in_workspace(ws, &["choice.0=true"], &[target.to_str().unwrap()]);
+    let result = picker.client().save_files_with(&format!(r#"{{
+        "current_folder": "{}",
+        "files": ["a.txt", "b.txt"],
+        "choices": [["zip", "Compress", [], "false"]]
+    }}"#, picker.ws.path().display())).unwrap();
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, [
+        format!("file://{}/a.txt", target.display()),
+        format!("file://{}/b_1.txt", target.display()),
+    ]);
+    assert_eq!(result.choices, Some(vec![("zip".to_string(), "true".to_string())]));
+    let args = picker.args();
+    assert_eq!(&args[..4], ["0", "1", "1", picker.ws.path().to_str().unwrap()]);
+}
@@ -0,0 +1,580 @@
+//! Tests for the `pikeru index` subcommands. They run against a database in the test
+//! workspace and, where the portal is needed, one on the private session bus.
+
+#[allow(dead_code)]
+mod common;
+use common::*;
+use rusqlite::Connection;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn pikeru_index(ws: &TempDir, db: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pikeru"))
+        .arg("index").args(args).arg("--db").arg(db)
+        .env("HOME", ws.path())
+        .env("DBUS_SESSION_BUS_ADDRESS", session_bus())
+        .output().expect("Failed to run pikeru")
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+fn seed(db: &Path, rows: &[(&str, &str, &str, f64)]) -> Connection {
+    let conn = open_test_db(db.to_str().unwrap());
+    for row in rows {
+        conn.execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, ?2, ?3, ?4)", *row).unwrap();
+    }
+    conn
+}
+
+fn rows(conn: &Connection) -> Vec<(String, String, String, f64)> {
+    let mut stmt = conn.prepare("SELECT dir, fname, description, mtime FROM descriptions ORDER BY dir, fname").unwrap();
+    let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))).unwrap();
+    rows.filter_map(|r| r.ok()).collect()
+}
+
+/// Portal indexing .txt files with `cat`, plus the args that point `pikeru index` at it.
+fn start_portal(ws: &TempDir) -> (PortalGuard, std::path::PathBuf, Vec<String>) {
+    let wrapper = create_mock_wrapper(ws, &[]);
+    let (db_path, _svc, _obj) = write_test_config(ws, wrapper.to_str().unwrap(), "cat", "exit 0", "txt");
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+    let args = vec!["--service".to_string(), guard.service_name.clone(), "--object-path".to_string(), guard.object_path.clone()];
+    (guard, db_path, args)
+}
+
+#[test]
+fn usage_on_unknown_command() {
+    let ws = test_workspace();
+    let out = pikeru_index(&ws, &ws.path().join("index.db"), &["frobnicate"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Usage: pikeru index"));
+}
+
+#[test]
+fn status_without_portal() {
+    let ws = test_workspace();
+    let db = ws.path().join("index.db");
+    seed(&db, &[("/a", "1.png", "cat", 1.0), ("/a", "2.png", "dog", 1.0), ("/b", "3.png", "owl", 1.0)]);
+    let out = pikeru_index(&ws, &db, &["status", "--service", "org.example.NotThere"]);
+    assert!(out.status.success());
+    let text = stdout(&out);
+    assert!(text.contains("Indexed: 3 files in 2 dirs"), "{}", text);
+    assert!(text.contains("Portal not running"), "{}", text);
+}
+
+#[test]
+fn status_from_portal() {
+    let ws = test_workspace();
+    let (_guard, db_path, bus_args) = start_portal(&ws);
+    let mut args = vec!["status"];
+    args.extend(bus_args.iter().map(String::as_str));
+    let out = pikeru_index(&ws, &db_path, &args);
+    let text = stdout(&out);
+    assert!(text.contains("Indexer: online"), "{}", text);
+    assert!(text.contains("Queue: empty"), "{}", text);
+}
+
+#[test]
+fn pause_and_resume_from_cli() {
+    let ws = test_workspace();
+    let (_guard, db_path, bus_args) = start_portal(&ws);
+    let run = |cmd: &str| {
+        let mut args = vec![cmd];
+        args.extend(bus_args.iter().map(String::as_str));
+        stdout(&pikeru_index(&ws, &db_path, &args))
+    };
+    assert_eq!(run("pause"), "Indexing paused\n");
+    assert!(run("status").contains("Indexer: online, paused"));
+    assert_eq!(run("resume"), "Indexing resumed\n");
+    assert!(run("status").contains("Indexer: online\n"));
+}
+
+#[test]
+fn failures_listed_and_retried() {
+    let ws = test_workspace();
+    let broken = ws.path().join("broken");
+    fs::write(&broken, "").unwrap();
+    let indexer = ws.path().join("fixable.sh");
+    fs::write(&indexer, format!("#!/bin/sh\n[ -e '{}' ] && {{ echo out of memory >&2; exit 2; }}\necho \"desc $1\"\n",
+        broken.display())).unwrap();
+    fs::set_permissions(&indexer, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let wrapper = create_mock_wrapper(&ws, &[]);
+    let (db_path, _svc, _obj) = write_test_config(&ws, wrapper.to_str().unwrap(), indexer.to_str().unwrap(), "exit 0", "txt");
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+    let bus_args = ["--service", &guard.service_name, "--object-path", &guard.object_path];
+    let run = |cmd: &[&str]| stdout(&pikeru_index(&ws, &db_path, &[cmd, &bus_args[..]].concat()));
+    let dir = create_test_dir(&ws, &["a.txt"]);
+    assert_eq!(run(&["failures"]), "No failures\n");
+    run(&["add", dir.to_str().unwrap()]);
+    std::thread::sleep(Duration::from_millis(800));
+
+    let listed = run(&["failures"]);
+    assert!(listed.starts_with(&format!("{}\n  1 attempt by default indexer, last ", dir.join("a.txt").display())), "{}", listed);
+    assert!(listed.contains("  exit code 2\n"), "{}", listed);
+    assert!(listed.contains("out of memory"), "{}", listed);
+    assert!(run(&["status"]).contains("Failed: 1 files"));
+
+    fs::remove_file(&broken).unwrap();
+    assert_eq!(run(&["retry"]), "Cleared 1 failures\nQueued 1 dirs\n");
+    std::thread::sleep(Duration::from_millis(800));
+    assert_eq!(run(&["failures"]), "No failures\n");
+    assert!(run(&["show", dir.join("a.txt").to_str().unwrap()]).starts_with("desc "));
+}
+
+#[test]
+fn retry_requeues_dirs_the_running_batch_did() {
+    let ws = test_workspace();
+    let broken = ws.path().join("broken");
+    fs::write(&broken, "").unwrap();
+    // Each file waits for its go file, so the batch is still on b.txt when a.txt is retried
+    let indexer = ws.path().join("gated.sh");
+    fs::write(&indexer, format!(concat!("#!/bin/sh\nfor i in $(seq 50); do [ -e '{ws}/go-'$(basename \"$1\") ] && break; sleep 0.1; done\n",
+        "[ \"$(basename \"$1\")\" = a.txt ] && [ -e '{b}' ] && {{ echo out of memory >&2; exit 2; }}\necho \"desc $1\"\n"),
+        ws = ws.path().display(), b = broken.display())).unwrap();
+    fs::set_permissions(&indexer, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let wrapper = create_mock_wrapper(&ws, &[]);
+    let (db_path, _svc, _obj) = write_test_config(&ws, wrapper.to_str().unwrap(), indexer.to_str().unwrap(), "exit 0", "txt");
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+    let bus_args = ["--service", &guard.service_name, "--object-path", &guard.object_path];
+    let run = |cmd: &[&str]| stdout(&pikeru_index(&ws, &db_path, &[cmd, &bus_args[..]].concat()));
+    let first = create_test_dir(&ws, &["first/a.txt"]).join("first");
+    let second = create_test_dir(&ws, &["second/b.txt"]).join("second");
+    run(&["add", first.to_str().unwrap()]);
+    std::thread::sleep(Duration::from_millis(300));
+    run(&["add", second.to_str().unwrap()]);
+    fs::write(ws.path().join("go-a.txt"), "").unwrap();
+    std::thread::sleep(Duration::from_millis(800));
+    assert!(run(&["failures"]).contains("out of memory"));
+
+    fs::remove_file(&broken).unwrap();
+    assert_eq!(run(&["retry"]), "Cleared 1 failures\nQueued 1 dirs\n");
+    fs::write(ws.path().join("go-b.txt"), "").unwrap();
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(run(&["failures"]), "No failures\n");
+    assert!(run(&["show", first.join("a.txt").to_str().unwrap()]).starts_with("desc "));
+}
+
+#[test]
+fn add_queues_dirs_with_portal() {
+    let ws = test_workspace();
+    let dir = create_test_dir(&ws, &["a.txt", "b.txt"]);
+    let (_guard, db_path, bus_args) = start_portal(&ws);
+    let mut args = vec!["add", dir.to_str().unwrap()];
+    args.extend(bus_args.iter().map(String::as_str));
+    let out = pikeru_index(&ws, &db_path, &args);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(stdout(&out).trim(), "Queued 1 dirs");
+
+    std::thread::sleep(Duration::from_millis(800));
+    let conn = open_test_db(db_path.to_str().unwrap());
+    assert_eq!(query_descriptions(&conn), [
+        ("a.txt".to_string(), "content of a.txt".to_string()),
+        ("b.txt".to_string(), "content of b.txt".to_string()),
+    ]);
+}
+
+#[test]
+fn add_recursive_queues_subdirs() {
+    let ws = test_workspace();
+    let root = ws.path().join("archive");
+    fs::create_dir_all(root.join("2023/summer")).unwrap();
+    fs::create_dir_all(root.join(".hidden")).unwrap();
+    fs::write(root.join("top.txt"), "top").unwrap();
+    fs::write(root.join("2023/summer/beach.txt"), "beach").unwrap();
+    fs::write(root.join(".hidden/secret.txt"), "secret").unwrap();
+    let (_guard, db_path, bus_args) = start_portal(&ws);
+    let mut args = vec!["add", "-r", root.to_str().unwrap()];
+    args.extend(bus_args.iter().map(String::as_str));
+    let out = pikeru_index(&ws, &db_path, &args);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(stdout(&out).trim(), "Queued 3 dirs");
+
+    std::thread::sleep(Duration::from_millis(1000));
+    let conn = open_test_db(db_path.to_str().unwrap());
+    assert_eq!(query_descriptions(&conn), [
+        ("beach.txt".to_string(), "beach".to_string()),
+        ("top.txt".to_string(), "top".to_string()),
+    ]);
+}
+
+#[test]
+fn add_recursive_follows_picker_search_settings() {
+    let ws = test_workspace();
+    fs::create_dir_all(ws.path().join(".config")).unwrap();
+    fs::write(ws.path().join(".config/pikeru.conf"), "[Settings]\nrespect_gitignore = false\n[SearchIgnore]\nnode_modules/\n").unwrap();
+    let root = ws.path().join("project");
+    for dir in ["src", "build", "node_modules/pkg"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+        fs::write(root.join(dir).join("file.txt"), dir).unwrap();
+    }
+    fs::write(root.join(".gitignore"), "build/\n").unwrap();
+    let (_guard, db_path, bus_args) = start_portal(&ws);
+    let mut args = vec!["add", "-r", root.to_str().unwrap()];
+    args.extend(bus_args.iter().map(String::as_str));
+    let out = pikeru_index(&ws, &db_path, &args);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    // project, src and build, but not node_modules or anything in it
+    assert_eq!(stdout(&out).trim(), "Queued 3 dirs");
+}
+
+#[test]
+fn add_rejects_missing_dir() {
+    let ws = test_workspace();
+    let out = pikeru_index(&ws, &ws.path().join("index.db"), &["add", "/no/such/dir"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("/no/such/dir"));
+}
+
+#[test]
+fn show_prints_description() {
+    let ws = test_workspace();
+    let dir = create_test_dir(&ws, &["photo.png"]);
+    let db = ws.path().join("index.db");
+    seed(&db, &[(dir.to_str().unwrap(), "photo.png", "a cat on a mat\n", 1_000_000.0)]);
+    let out = pikeru_index(&ws, &db, &["show", dir.join("photo.png").to_str().unwrap()]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "a cat on a mat\n");
+    assert!(out.stderr.is_empty());
+}
+
+#[test]
+fn show_warns_when_file_changed() {
+    let ws = test_workspace();
+    let dir = create_test_dir(&ws, &["photo.png"]);
+    let db = ws.path().join("index.db");
+    seed(&db, &[(dir.to_str().unwrap(), "photo.png", "old", 5.0)]);
+    let out = pikeru_index(&ws, &db, &["show", dir.join("photo.png").to_str().unwrap()]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("changed since it was indexed"));
+}
+
+#[test]
+fn show_fails_for_unindexed_file() {
+    let ws = test_workspace();
+    let dir = create_test_dir(&ws, &["photo.png"]);
+    let db = ws.path().join("index.db");
+    seed(&db, &[]);
+    let out = pikeru_index(&ws, &db, &["show", dir.join("photo.png").to_str().unwrap()]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Not indexed"));
+}
+
+#[test]
+fn prune_removes_missing_files() {
+    let ws = test_workspace();
+    let dir = create_test_dir(&ws, &["kept.png"]);
+    let db = ws.path().join("index.db");
+    let d = dir.to_str().unwrap();
+    let conn = seed(&db, &[(d, "kept.png", "kept", 1.0), (d, "gone.png", "gone", 1.0), ("/no/such/dir", "x.png", "x", 1.0)]);
+    let out = pikeru_index(&ws, &db, &["prune"]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out).trim(), "Removed 2 entries");
+    assert_eq!(rows(&conn), [(d.to_string(), "kept.png".to_string(), "kept".to_string(), 1.0)]);
+}
+
+#[test]
+fn export_import_round_trip() {
+    let ws = test_workspace();
+    let src = ws.path().join("src.db");
+    let entries = [
+        ("/photos", "a.png", "plain", 1.5),
+        ("/photos/it's \"quoted\"", "b\\c.png", "line one\nline two\ttab", 1_700_000_000.0),
+        ("/photos", "émoji 🐈.png", "ünïcode \u{1}", 3.0),
+    ];
+    let src_conn = seed(&src, &entries);
+    let out = pikeru_index(&ws, &src, &["export"]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out).lines().count(), 3);
+    let export = ws.path().join("export.jsonl");
+    fs::write(&export, &out.stdout).unwrap();
+
+    let dst = ws.path().join("dst.db");
+    let out = pikeru_index(&ws, &dst, &["import", export.to_str().unwrap()]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(stdout(&out).trim(), "Imported 3 entries");
+    assert_eq!(rows(&Connection::open(&dst).unwrap()), rows(&src_conn));
+}
+
+#[test]
+fn import_from_stdin_updates_existing_and_skips_bad_lines() {
+    let ws = test_workspace();
+    let db = ws.path().join("index.db");
+    let conn = seed(&db, &[("/p", "a.png", "old", 1.0)]);
+    let input = concat!(
+        r#"{"dir":"/p","fname":"a.png","description":"new","mtime":2}"#, "\n",
+        "not json\n",
+        r#"{"dir":"/p","fname":"b.png","mtime":2}"#, "\n",
+        "\n",
+        r#"{ "fname" : "c.png", "dir" : "/p", "mtime" : 3e0, "description" : "é🐈" }"#, "\n",
+    );
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pikeru"))
+        .args(["index", "import", "--db"]).arg(&db)
+        .env("HOME", ws.path())
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    std::io::Write::write_all(child.stdin.as_mut().unwrap(), input.as_bytes()).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(stdout(&out).trim(), "Imported 2 entries");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("line 2") && stderr.contains("line 3"), "{}", stderr);
+    assert_eq!(rows(&conn), [
+        ("/p".to_string(), "a.png".to_string(), "new".to_string(), 2.0),
+        ("/p".to_string(), "c.png".to_string(), "é🐈".to_string(), 3.0),
+    ]);
+}
+
+/// Index migrated to the portal's current schema by starting the portal on it.
+fn migrated_db(ws: &TempDir) -> (PortalGuard, std::path::PathBuf, Connection) {
+    let (guard, db_path, _) = start_portal(ws);
+    let conn = Connection::open(&db_path).unwrap();
+    assert!(conn.pragma_query_value(None, "user_version", |r| r.get::<_, i64>(0)).unwrap() >= 2);
+    (guard, db_path, conn)
+}
+
+#[test]
+fn show_compares_exact_mtime() {
+    let ws = test_workspace();
+    let dir = create_test_dir(&ws, &["photo.png"]);
+    let (_guard, db, conn) = migrated_db(&ws);
+    conn.execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, 'photo.png', 'cat', 1000000000000000)",
+        [dir.to_str().unwrap()]).unwrap();
+    let photo = dir.join("photo.png");
+    let out = pikeru_index(&ws, &db, &["show", photo.to_str().unwrap()]);
+    assert_eq!(stdout(&out), "cat\n");
+    assert!(out.stderr.is_empty());
+
+    filetime::set_file_mtime(&photo, filetime::FileTime::from_unix_time(1_000_000, 1)).unwrap();
+    let out = pikeru_index(&ws, &db, &["show", photo.to_str().unwrap()]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("changed since it was indexed"));
+}
+
+#[test]
+fn export_import_keeps_exact_mtime() {
+    let ws = test_workspace();
+    let (_guard, src, conn) = migrated_db(&ws);
+    let dst = ws.path().join("dst.db");
+    conn.execute("VACUUM INTO ?1", [dst.to_str().unwrap()]).unwrap();
+    conn.execute("INSERT INTO descriptions (dir, fname, description, mtime, size, indexer) VALUES
+                  ('/p', 'a.png', 'cat', 1700000000123456789, 42, 'caption.sh'),
+                  ('/p', 'b.png', 'owl', -5, NULL, NULL)", []).unwrap();
+    let out = pikeru_index(&ws, &src, &["export"]);
+    assert!(out.status.success());
+    assert!(stdout(&out).contains(r#""mtime":1700000000.123456789,"size":42,"indexer":"caption.sh""#), "{}", stdout(&out));
+    let export = ws.path().join("export.jsonl");
+    fs::write(&export, &out.stdout).unwrap();
+
+    let out = pikeru_index(&ws, &dst, &["import", export.to_str().unwrap()]);
+    assert_eq!(stdout(&out).trim(), "Imported 2 entries");
+    let all = |c: &Connection| -> Vec<(String, i64, Option<i64>, Option<String>)> {
+        let mut stmt = c.prepare("SELECT fname, mtime, size, indexer FROM descriptions ORDER BY fname").unwrap();
+        let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))).unwrap();
+        rows.filter_map(|r| r.ok()).collect()
+    };
+    assert_eq!(all(&Connection::open(&dst).unwrap()), all(&conn));
+}
+
+#[test]
+fn indexer_names_exported_and_counted() {
+    let ws = test_workspace();
+    let (_guard, src, conn) = migrated_db(&ws);
+    let dst = ws.path().join("dst.db");
+    conn.execute("VACUUM INTO ?1", [dst.to_str().unwrap()]).unwrap();
+    conn.execute("INSERT INTO descriptions (dir, fname, description, mtime, indexer, indexer_name) VALUES
+                  ('/p', 'a.png', 'cat', 1, 'caption.sh', 'default'),
+                  ('/p', 'b.pdf', 'tax form', 1, 'pdftotext', 'documents'),
+                  ('/p', 'c.pdf', 'recipe', 1, 'pdftotext', 'documents')", []).unwrap();
+    let out = pikeru_index(&ws, &src, &["status"]);
+    assert!(stdout(&out).contains("Indexed: 3 files in 1 dirs\n  default: 1 files\n  documents: 2 files\n"), "{}", stdout(&out));
+
+    let out = pikeru_index(&ws, &src, &["export"]);
+    assert!(stdout(&out).contains(r#""indexer":"pdftotext","indexer_name":"documents""#), "{}", stdout(&out));
+    let export = ws.path().join("export.jsonl");
+    fs::write(&export, &out.stdout).unwrap();
+    let out = pikeru_index(&ws, &dst, &["import", export.to_str().unwrap()]);
+    assert_eq!(stdout(&out).trim(), "Imported 3 entries");
+    let names = |c: &Connection| -> Vec<(String, Option<String>)> {
+        let mut stmt = c.prepare("SELECT fname, indexer_name FROM descriptions ORDER BY fname").unwrap();
+        let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+        rows.filter_map(|r| r.ok()).collect()
+    };
+    assert_eq!(names(&Connection::open(&dst).unwrap()), names(&conn));
+}
+
+/// Dirs with captioned photos, as (dir, fname, description).
+fn photo_rows(ws: &TempDir) -> Vec<(String, &'static str, &'static str)> {
+    let trip = ws.path().join("trip");
+    let home = ws.path().join("home");
+    fs::create_dir_all(&trip).unwrap();
+    fs::create_dir_all(&home).unwrap();
+    let (trip, home) = (trip.to_str().unwrap().to_string(), home.to_str().unwrap().to_string());
+    vec![
+        (trip.clone(), "1.png", "a red car parked near the beach at sunset"),
+        (trip.clone(), "2.png", "a red car in a garage"),
+        (trip.clone(), "3.png", "palm trees on a beach"),
+        (trip.clone(), "4.png", "a blue boat"),
+        (home.clone(), "5.png", "beach towel, car keys and a red bucket"),
+    ]
+}
+
+fn insert_photos(conn: &Connection, rows: &[(String, &str, &str)]) {
+    for (dir, fname, desc) in rows {
+        conn.execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, ?2, ?3, 1)", (dir, fname, desc)).unwrap();
+    }
+}
+
+fn search(ws: &TempDir, db: &Path, args: &[&str]) -> Vec<String> {
+    let mut all = vec!["search"];
+    all.extend(args);
+    let out = pikeru_index(ws, db, &all);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    stdout(&out).lines().map(|l| Path::new(l).file_name().unwrap().to_string_lossy().to_string()).collect()
+}
+
+#[test]
+fn search_ranks_files_with_every_word_first() {
+    let ws = test_workspace();
+    let (_guard, db, conn) = migrated_db(&ws);
+    insert_photos(&conn, &photo_rows(&ws));
+    let found = search(&ws, &db, &["red car beach"]);
+    assert_eq!(found.len(), 4, "{:?}", found);
+    let mut best = found[..2].to_vec();
+    best.sort();
+    assert_eq!(best, ["1.png", "5.png"]);
+    assert_eq!(found[2..], ["2.png", "3.png"]);
+}
+
+#[test]
+fn search_matches_prefixes_within_dirs() {
+    let ws = test_workspace();
+    let (_guard, db, conn) = migrated_db(&ws);
+    insert_photos(&conn, &photo_rows(&ws));
+    let trip = ws.path().join("trip");
+    assert_eq!(search(&ws, &db, &["Bea", trip.to_str().unwrap()]), ["1.png", "3.png"]);
+    assert!(search(&ws, &db, &["...", trip.to_str().unwrap()]).is_empty());
+}
+
+#[test]
+fn search_follows_changes_to_descriptions() {
+    let ws = test_workspace();
+    let (_guard, db, conn) = migrated_db(&ws);
+    insert_photos(&conn, &photo_rows(&ws));
+    conn.execute("UPDATE descriptions SET description = 'a red boat' WHERE fname = '4.png'", []).unwrap();
+    conn.execute("DELETE FROM descriptions WHERE fname = '2.png'", []).unwrap();
+    assert_eq!(search(&ws, &db, &["boat"]), ["4.png"]);
+    assert_eq!(search(&ws, &db, &["garage"]), Vec::<String>::new());
+}
+
+#[test]
+fn search_legacy_index_without_full_text() {
+    let ws = test_workspace();
+    let db = ws.path().join("index.db");
+    let conn = seed(&db, &[]);
+    insert_photos(&conn, &photo_rows(&ws));
+    let found = search(&ws, &db, &["red car beach"]);
+    let mut best = found[..2].to_vec();
+    best.sort();
+    assert_eq!(best, ["1.png", "5.png"]);
+    assert_eq!(found[2..], ["2.png", "3.png"]);
+}
+
+/// Files in a "media" dir with sizes, mtimes and descriptions for trying the query syntax on.
+fn media_db(ws: &TempDir) -> (PortalGuard, std::path::PathBuf) {
+    let (guard, db, conn) = migrated_db(ws);
+    let dir = ws.path().join("media");
+    fs::create_dir_all(&dir).unwrap();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let files = [
+        ("beach.jpg", 2 << 20, now - 3600, "sunny beach with palm trees"),
+        ("beach_old.jpg", 300 << 10, 1_577_880_000, "a beach at night"),
+        ("clip.mp4", 3 << 20, now - 2 * 86400, "video of a beach party"),
+        ("notes.txt", 100, now - 60, "beach packing list"),
+        ("draft_beach.txt", 200, now - 60, "beach notes draft"),
+    ];
+    for (fname, size, mtime, desc) in files {
+        let path = dir.join(fname);
+        fs::File::create(&path).unwrap().set_len(size).unwrap();
+        filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(mtime, 0)).unwrap();
+        conn.execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, ?2, ?3, ?4)",
+            (dir.to_str().unwrap(), fname, desc, mtime * 1_000_000_000)).unwrap();
+    }
+    (guard, db)
+}
+
+fn search_sorted(ws: &TempDir, db: &Path, query: &str) -> Vec<String> {
+    let mut found = search(ws, db, &[query]);
+    found.sort();
+    found
+}
+
+#[test]
+fn search_filters_by_ext_type_and_size() {
+    let ws = test_workspace();
+    let (_guard, db) = media_db(&ws);
+    assert_eq!(search_sorted(&ws, &db, "ext:jpg"), ["beach.jpg", "beach_old.jpg"]);
+    assert_eq!(search_sorted(&ws, &db, "ext:JPG|.mp4"), ["beach.jpg", "beach_old.jpg", "clip.mp4"]);
+    assert_eq!(search_sorted(&ws, &db, "type:video"), ["clip.mp4"]);
+    assert_eq!(search_sorted(&ws, &db, "type:image|video"), ["beach.jpg", "beach_old.jpg", "clip.mp4"]);
+    assert_eq!(search_sorted(&ws, &db, "size:>1M"), ["beach.jpg", "clip.mp4"]);
+    assert_eq!(search_sorted(&ws, &db, "size:<1k"), ["draft_beach.txt", "notes.txt"]);
+    assert_eq!(search_sorted(&ws, &db, "size:300KiB -size:>=1m"), ["beach_old.jpg"]);
+}
+
+#[test]
+fn search_filters_by_modified() {
+    let ws = test_workspace();
+    let (_guard, db) = media_db(&ws);
+    assert_eq!(search_sorted(&ws, &db, "modified:<1d"), ["beach.jpg", "draft_beach.txt", "notes.txt"]);
+    assert_eq!(search_sorted(&ws, &db, "modified:>1w"), ["beach_old.jpg"]);
+    assert_eq!(search_sorted(&ws, &db, "modified:<2021-01-01"), ["beach_old.jpg"]);
+    assert_eq!(search_sorted(&ws, &db, "modified:>=2021-01-01 ext:jpg"), ["beach.jpg"]);
+    // Large videos from last week
+    assert_eq!(search_sorted(&ws, &db, "type:video size:>1M modified:<7d"), ["clip.mp4"]);
+}
+
+#[test]
+fn search_names_descriptions_and_phrases() {
+    let ws = test_workspace();
+    let (_guard, db) = media_db(&ws);
+    assert_eq!(search_sorted(&ws, &db, "name:\"_OLD\""), ["beach_old.jpg"]);
+    assert_eq!(search_sorted(&ws, &db, "desc:palm"), ["beach.jpg"]);
+    assert_eq!(search_sorted(&ws, &db, "desc:beach -ext:txt"), ["beach.jpg", "beach_old.jpg", "clip.mp4"]);
+    assert_eq!(search_sorted(&ws, &db, "\"palm trees\""), ["beach.jpg"]);
+    assert_eq!(search_sorted(&ws, &db, "\"trees palm sunny\""), ["beach.jpg"], "descriptions need all the words");
+    assert!(search_sorted(&ws, &db, "\"palm party\"").is_empty());
+    assert_eq!(search_sorted(&ws, &db, "beach -draft -\"at night\""), ["beach.jpg", "clip.mp4", "notes.txt"]);
+    assert_eq!(search_sorted(&ws, &db, "type:file -type:image -type:video"), ["draft_beach.txt", "notes.txt"]);
+    assert!(search_sorted(&ws, &db, "size:big").is_empty(), "a bad filter is searched as text");
+}
+
+#[test]
+fn search_ranks_by_embedding_similarity() {
+    let ws = test_workspace();
+    let (embedder, _log) = create_mock_embedder(&ws);
+    let dir = ws.path().join("pets");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("cat.txt"), "a cat").unwrap();
+    fs::write(dir.join("both.txt"), "a cat chasing a dog and a dog").unwrap();
+    fs::write(dir.join("dog.txt"), "a dog").unwrap();
+    let wrapper = create_mock_wrapper(&ws, &[]);
+    let (db_path, _svc, _obj) = write_test_config(&ws, wrapper.to_str().unwrap(), "cat", "exit 0", "txt");
+    let conf = ws.path().join("portal.conf");
+    let content = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, format!("{}embed = {}\n", content, embedder.display())).unwrap();
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    let bus_args = ["--service", guard.service_name.as_str(), "--object-path", guard.object_path.as_str()];
+    let mut add = vec!["add", dir.to_str().unwrap()];
+    add.extend(bus_args);
+    assert!(pikeru_index(&ws, &db_path, &add).status.success());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    // No description says kitty, but the embeddings point the same way
+    let mut args = vec!["kitty"];
+    args.extend(bus_args);
+    assert_eq!(search(&ws, &db_path, &args), ["cat.txt", "both.txt"]);
+    assert!(search(&ws, &db_path, &["kitty", "--service", "org.example.NotThere"]).is_empty());
+}
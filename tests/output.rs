@@ -0,0 +1,50 @@
+//! Tests for what pikeru prints when the dialog closes. The dialog needs a display, so
+//! these build the output module on its own.
+
+#[path = "../src/output.rs"]
+mod output;
+use output::{Output, Selection};
+
+fn selection<'a>(paths: &'a [String], filter: Option<&'a str>, choices: &[(&'a str, &'a str)]) -> Selection<'a> {
+    Selection { paths, filter, choices: choices.to_vec(), overwrite: false }
+}
+
+fn paths(p: &[&str]) -> Vec<String> {
+    p.iter().map(|p| p.to_string()).collect()
+}
+
+#[test]
+fn lines_one_per_path() {
+    let chosen = paths(&["/tmp/a.txt", "/tmp/b c.txt"]);
+    assert_eq!(Output::Lines.format(&selection(&chosen, None, &[])), "/tmp/a.txt\n/tmp/b c.txt\n");
+}
+
+#[test]
+fn print0_terminates_every_path() {
+    let chosen = paths(&["/tmp/a.txt", "/tmp/new\nline.txt"]);
+    assert_eq!(Output::Print0.format(&selection(&chosen, None, &[])), "/tmp/a.txt\0/tmp/new\nline.txt\0");
+    assert_eq!(Output::Print0.format(&selection(&[], None, &[])), "");
+}
+
+#[test]
+fn json_selection() {
+    let chosen = paths(&["/tmp/a \"quoted\".txt", "/tmp/tab\there"]);
+    let sel = selection(&chosen, Some("Images"), &[("encoding", "utf8"), ("reencode", "true")]);
+    assert_eq!(Output::Json.format(&sel), concat!(
+        r#"{"paths":["/tmp/a \"quoted\".txt","/tmp/tab\there"],"filter":"Images","#,
+        r#""choices":{"encoding":"utf8","reencode":"true"},"cancelled":false,"overwrite":false}"#, "\n"));
+}
+
+#[test]
+fn json_cancelled() {
+    assert_eq!(Output::Json.format(&selection(&[], None, &[])),
+        "{\"paths\":[],\"filter\":null,\"choices\":{},\"cancelled\":true,\"overwrite\":false}\n");
+}
+
+#[test]
+fn json_overwrite() {
+    let chosen = paths(&["/tmp/exists.txt"]);
+    let sel = Selection { overwrite: true, ..selection(&chosen, None, &[]) };
+    assert_eq!(Output::Json.format(&sel),
+        "{\"paths\":[\"/tmp/exists.txt\"],\"filter\":null,\"choices\":{},\"cancelled\":false,\"overwrite\":true}\n");
+}
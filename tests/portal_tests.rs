@@ -9,39 +9,6 @@ use std::path::{Path, PathBuf};
 use tempfile::TempDir;
 use std::time::Duration;
 
-// ---------------------------------------------------------------------------
-// Helpers
-// ---------------------------------------------------------------------------
-
-fn write_test_config(
-    workspace: &TempDir,
-    wrapper_path: &str,
-    indexer_cmd: &str,
-    indexer_check: &str,
-    extensions: &str,
-) -> (PathBuf, String, String) {
-    let conf = workspace.path().join("portal.conf");
-    let content = format!(
-        r#"log_level = trace
-
-[filepicker]
-cmd = {}
-default_save_dir = /tmp/psave
-
-[indexer]
-enable = true
-cmd = {}
-check = {}
-extensions = {}
-"#,
-        wrapper_path, indexer_cmd, indexer_check, extensions
-    );
-    fs::write(&conf, content).unwrap();
-    let (service_name, object_path) = make_unique_name();
-    let db_path = workspace.path().join("test.db");
-    (db_path, service_name, object_path)
-}
-
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -349,8 +316,52 @@ fn test_paths_with_spaces() {
     let result = client.open_file(false, false).expect("open_file should succeed");
     assert_eq!(result.status, 0);
     assert_eq!(result.uris.len(), 2);
-    assert!(result.uris.contains(&"file:///home/user/my documents/photo.jpg".to_string()));
-    assert!(result.uris.contains(&"file:///tmp/file with spaces.txt".to_string()));
+    assert!(result.uris.contains(&"file:///home/user/my%20documents/photo.jpg".to_string()));
+    assert!(result.uris.contains(&"file:///tmp/file%20with%20spaces.txt".to_string()));
+}
+
+#[test]
+fn test_path_records_with_special_chars() {
+    let ws = test_workspace();
+    let capture = ws.path().join("args.txt");
+    let marker = ws.path().join("injected");
+    let nasty = format!("/tmp/it's \"$(touch {})\"\nline2.txt", marker.display());
+    let record = format!("path={}", nasty);
+    // stdout is ignored when the picker writes path records
+    let wrapper = create_result_wrapper(&ws, &capture, &[&record], &["/tmp/wrong.txt"]);
+    let (db_path, _svc, _obj) = write_test_config(
+        &ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+
+    let client = PortalClient::new(&_guard.service_name, &_guard.object_path);
+    let result = client.open_file(false, false).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, [format!("file:///tmp/it%27s%20%22%24%28touch%20{}%29%22%0Aline2.txt", marker.display())]);
+    assert!(!marker.exists(), "path must not be run by a shell");
+}
+
+#[test]
+fn test_start_path_passed_verbatim() {
+    let ws = test_workspace();
+    let capture = ws.path().join("args.txt");
+    let marker = ws.path().join("injected");
+    let folder = ws.path().join(format!("a'b\"$(touch {})", marker.display()));
+    fs::create_dir_all(&folder).unwrap();
+    let wrapper = create_result_wrapper(&ws, &capture, &[], &["/tmp/one.txt"]);
+    let (db_path, _svc, _obj) = write_test_config(
+        &ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+
+    let client = PortalClient::new(&_guard.service_name, &_guard.object_path);
+    let json_path = folder.to_str().unwrap().replace('\\', "\\\\").replace('"', "\\\"");
+    let spec = format!(r#"{{"current_folder": "{}"}}"#, json_path);
+    let result = client.open_file_with(&spec).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    let args: Vec<String> = fs::read_to_string(&capture).unwrap().lines().map(String::from).collect();
+    assert_eq!(args[3], folder.to_str().unwrap());
+    assert!(!marker.exists(), "path must not be run by a shell");
 }
 
 #[test]
@@ -1029,6 +1040,7 @@ fn test_graceful_shutdown() {
     let portal_bin = env!("CARGO_BIN_EXE_portal");
     let mut child = std::process::Command::new(portal_bin)
         .args(["-c", ws.path().join("portal.conf").to_str().unwrap(), "-d", db_path.to_str().unwrap()])
+        .env("DBUS_SESSION_BUS_ADDRESS", session_bus())
         .spawn()
         .expect("Failed to spawn portal");
     std::thread::sleep(Duration::from_millis(500));
@@ -1508,3 +1520,2010 @@ extensions = txt,cache,log,tmp,png,jpg
     let count = common::count_descriptions(&conn);
     assert_eq!(count, 0, "No files should be indexed when indexer is disabled after configure");
 }
+
+// ---------------------------------------------------------------------------
+// Parallel indexing workers
+// ---------------------------------------------------------------------------
+
+/// Indexer that takes half a second per file and logs how many copies were running at its start.
+fn create_peak_indexer(ws: &TempDir) -> (PathBuf, PathBuf) {
+    let running = ws.path().join("running");
+    let peaks = ws.path().join("peaks");
+    fs::create_dir_all(&running).unwrap();
+    let script = ws.path().join("peak-indexer.sh");
+    fs::write(&script, format!(
+        "#!/bin/bash\ntouch '{r}'/$$\nls '{r}' | wc -l >> '{p}'\nsleep 0.5\nrm '{r}'/$$\necho \"desc $1\"\n",
+        r = running.display(), p = peaks.display(),
+    )).unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    (script, peaks)
+}
+
+fn start_worker_portal(ws: &TempDir, indexer: &Path, workers: Option<&str>) -> (PortalGuard, PathBuf) {
+    let fp_cmd = create_mock_wrapper(ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    if let Some(n) = workers {
+        let content = fs::read_to_string(&conf).unwrap();
+        fs::write(&conf, format!("{}workers = {}\n", content, n)).unwrap();
+    }
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    std::thread::sleep(Duration::from_millis(200));
+    (guard, db_path)
+}
+
+fn max_peak(peaks: &Path) -> usize {
+    fs::read_to_string(peaks).unwrap().lines().map(|l| l.trim().parse::<usize>().unwrap()).max().unwrap()
+}
+
+#[test]
+fn test_indexer_workers_run_in_parallel() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let files: Vec<String> = (0..6).map(|i| format!("f{}.txt", i)).collect();
+    let root = create_test_dir(&ws, &files.iter().map(String::as_str).collect::<Vec<_>>());
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, Some("3"));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    // Six half-second files take 1.5s with one worker, 1s with three.
+    std::thread::sleep(Duration::from_millis(2200));
+
+    let conn = open_test_db(db_path.to_str().unwrap());
+    let mut descs = query_descriptions(&conn);
+    descs.sort();
+    assert_eq!(descs.len(), 6);
+    for ((fname, desc), expected) in descs.iter().zip(&files) {
+        assert_eq!(fname, expected);
+        assert_eq!(desc.trim(), format!("desc {}", root.join(expected).display()));
+    }
+    let peak = max_peak(&peaks);
+    assert!(peak > 1 && peak <= 3, "expected 2-3 indexers at once, got {}", peak);
+}
+
+#[test]
+fn test_indexer_workers_default_to_one() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["a.txt", "b.txt", "c.txt"]);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(2500));
+
+    let conn = open_test_db(db_path.to_str().unwrap());
+    assert_eq!(count_descriptions(&conn), 3);
+    assert_eq!(max_peak(&peaks), 1);
+}
+
+#[test]
+fn test_indexer_bad_workers_value_ignored() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["a.txt", "b.txt"]);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, Some("0"));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(2000));
+
+    let conn = open_test_db(db_path.to_str().unwrap());
+    assert_eq!(count_descriptions(&conn), 2);
+    assert_eq!(max_peak(&peaks), 1);
+}
+
+#[test]
+fn test_clear_queue_stops_parallel_workers() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let files: Vec<String> = (0..12).map(|i| format!("f{}.txt", i)).collect();
+    let root = create_test_dir(&ws, &files.iter().map(String::as_str).collect::<Vec<_>>());
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, Some("2"));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(client.clear_index_queue().is_ok());
+    std::thread::sleep(Duration::from_millis(1500));
+
+    let conn = open_test_db(db_path.to_str().unwrap());
+    let count = count_descriptions(&conn);
+    assert!(count < files.len(), "clear should stop queueing files, but {} were indexed", count);
+}
+
+// ---------------------------------------------------------------------------
+// Persistent indexing queue
+// ---------------------------------------------------------------------------
+
+fn stored_queue(db_path: &Path) -> Vec<(String, bool)> {
+    let conn = open_test_db(db_path.to_str().unwrap());
+    let mut stmt = conn.prepare("SELECT dir, done FROM index_queue ORDER BY dir").unwrap();
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+    rows.filter_map(|r| r.ok()).collect()
+}
+
+fn queue_dirs(ws: &TempDir, count: usize) -> Vec<PathBuf> {
+    (0..count).map(|i| {
+        let dir = ws.path().join(format!("queued{}", i));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "data").unwrap();
+        fs::write(dir.join("b.txt"), "data").unwrap();
+        dir
+    }).collect()
+}
+
+#[test]
+fn test_queue_stored_while_indexing() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let dirs = queue_dirs(&ws, 3);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&dirs.iter().map(|d| d.to_str().unwrap()).collect::<Vec<_>>()).is_ok());
+    std::thread::sleep(Duration::from_millis(300));
+    let queue = stored_queue(&db_path);
+    assert_eq!(queue.iter().map(|(d, _)| PathBuf::from(d)).collect::<Vec<_>>(), dirs);
+    assert!(queue.iter().any(|(_, done)| !done));
+
+    // Six half-second files, then the finished batch is dropped.
+    std::thread::sleep(Duration::from_millis(3500));
+    assert_eq!(count_descriptions(&open_test_db(db_path.to_str().unwrap())), 6);
+    assert!(stored_queue(&db_path).is_empty());
+}
+
+#[test]
+fn test_queue_resumed_after_restart() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let dirs = queue_dirs(&ws, 3);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&dirs.iter().map(|d| d.to_str().unwrap()).collect::<Vec<_>>()).is_ok());
+    std::thread::sleep(Duration::from_millis(1300));
+    // Kill the portal partway through the batch.
+    drop(guard);
+    let before = count_descriptions(&open_test_db(db_path.to_str().unwrap()));
+    assert!(before < 6, "batch finished before the restart");
+    assert!(!stored_queue(&db_path).is_empty());
+
+    let conf = ws.path().join("portal.conf");
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    std::thread::sleep(Duration::from_millis(3500));
+    let mut files: Vec<(String, String)> = {
+        let conn = open_test_db(db_path.to_str().unwrap());
+        let mut stmt = conn.prepare("SELECT dir, fname FROM descriptions").unwrap();
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        rows.filter_map(|r| r.ok()).collect()
+    };
+    files.sort();
+    let expected: Vec<(String, String)> = dirs.iter()
+        .flat_map(|d| ["a.txt", "b.txt"].map(|f| (d.to_str().unwrap().to_string(), f.to_string())))
+        .collect();
+    assert_eq!(files, expected, "every file indexed exactly once");
+    assert!(stored_queue(&db_path).is_empty());
+}
+
+#[test]
+fn test_clear_queue_clears_stored_queue() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let dirs = queue_dirs(&ws, 3);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&dirs.iter().map(|d| d.to_str().unwrap()).collect::<Vec<_>>()).is_ok());
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(client.clear_index_queue().is_ok());
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(stored_queue(&db_path).is_empty());
+}
+
+#[test]
+fn test_stored_queue_ignored_when_disabled() {
+    let ws = test_workspace();
+    let dir = create_indexable_tree(&ws);
+    let wrapper = create_mock_wrapper(&ws, &[]);
+    let (db_path, conf) = write_indexer_test_config(&ws, wrapper.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap().replace("enable = true", "enable = false");
+    fs::write(&conf, content).unwrap();
+    {
+        let conn = open_test_db(db_path.to_str().unwrap());
+        conn.execute("CREATE TABLE index_queue (dir TEXT PRIMARY KEY, done INTEGER NOT NULL DEFAULT 0)", []).unwrap();
+        conn.execute("INSERT INTO index_queue (dir) VALUES (?1)", [dir.to_str().unwrap()]).unwrap();
+    }
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    std::thread::sleep(Duration::from_millis(800));
+    assert_eq!(count_descriptions(&open_test_db(db_path.to_str().unwrap())), 0);
+}
+
+#[test]
+fn test_stored_queue_follows_search_ignore() {
+    let ws = test_workspace();
+    let dir = create_test_dir(&ws, &["a.txt", "debug.log"]);
+    fs::create_dir_all(ws.path().join(".config")).unwrap();
+    fs::write(ws.path().join(".config/pikeru.conf"), "[SearchIgnore]\n*.log\n").unwrap();
+    let wrapper = create_mock_wrapper(&ws, &[]);
+    let (db_path, conf) = write_indexer_test_config(&ws, wrapper.to_str().unwrap());
+    {
+        let conn = open_test_db(db_path.to_str().unwrap());
+        conn.execute("CREATE TABLE index_queue (dir TEXT PRIMARY KEY, done INTEGER NOT NULL DEFAULT 0)", []).unwrap();
+        conn.execute("INSERT INTO index_queue (dir) VALUES (?1)", [dir.to_str().unwrap()]).unwrap();
+    }
+    let _guard = PortalGuard::with_env(db_path.to_str().unwrap(), conf.to_str().unwrap(), &[("HOME", ws.path())]);
+    std::thread::sleep(Duration::from_millis(800));
+    let names: Vec<String> = indexed_files(&db_path).into_iter().map(|(_, fname, _)| fname).collect();
+    assert_eq!(names, ["a.txt"], "the resumed queue should skip what the picker ignores");
+}
+
+// ---------------------------------------------------------------------------
+// Index status and signals
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_index_status_idle() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let (guard, _db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert_eq!(client.index_status(), Ok((0, 0, 0, true, String::new())));
+}
+
+#[test]
+fn test_index_status_while_indexing() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["a.txt", "b.txt", "c.txt", "d.txt"]);
+    let (guard, _db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(700));
+    let (queued, done, failed, online, dir) = client.index_status().unwrap();
+    assert_eq!((queued, failed, online), (1, 0, true));
+    assert!((1..4).contains(&done), "expected the batch in progress, got {} done", done);
+    assert_eq!(dir, root.to_str().unwrap());
+
+    std::thread::sleep(Duration::from_millis(2000));
+    assert_eq!(client.index_status(), Ok((0, 4, 0, true, String::new())));
+}
+
+#[test]
+fn test_progress_and_directory_indexed_signals() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let dirs = queue_dirs(&ws, 2);
+    let (guard, _db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    let listener = client.listen_index_signals(3.5);
+    assert!(client.update_index(&dirs.iter().map(|d| d.to_str().unwrap()).collect::<Vec<_>>()).is_ok());
+    let signals = listener.finish();
+
+    let progress: Vec<&Vec<String>> = signals.iter().filter(|(name, _)| name == "Progress").map(|(_, a)| a).collect();
+    assert_eq!(progress.len(), 5, "one per file plus the end of the batch: {:?}", signals);
+    for (i, args) in progress[..4].iter().enumerate() {
+        assert_eq!(args[1], (i + 1).to_string());
+        assert_eq!(args[2], "0");
+    }
+    assert_eq!(*progress[4], ["", "4", "0", "0"]);
+    let mut indexed: Vec<&str> = signals.iter().filter(|(name, _)| name == "DirectoryIndexed")
+        .map(|(_, a)| a[0].as_str()).collect();
+    indexed.sort();
+    assert_eq!(indexed, dirs.iter().map(|d| d.to_str().unwrap()).collect::<Vec<_>>());
+    assert!(!signals.iter().any(|(name, _)| name == "IndexerOffline"));
+}
+
+#[test]
+fn test_failed_files_counted() {
+    let ws = test_workspace();
+    let indexer = ws.path().join("picky-indexer.sh");
+    fs::write(&indexer, "#!/bin/bash\ncase \"$1\" in *bad*) exit 1;; esac\necho desc\n").unwrap();
+    fs::set_permissions(&indexer, fs::Permissions::from_mode(0o755)).unwrap();
+    let root = create_test_dir(&ws, &["good1.txt", "bad.txt", "good2.txt"]);
+    let (guard, _db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(client.index_status(), Ok((0, 2, 1, true, String::new())));
+}
+
+#[test]
+fn test_indexer_offline_signal() {
+    let ws = test_workspace();
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(&ws, fp_cmd.to_str().unwrap(), "false");
+    let content = fs::read_to_string(&conf).unwrap().replace("check = exit 0", "check = exit 1");
+    fs::write(&conf, content).unwrap();
+    let root = create_test_dir(&ws, &["a.txt"]);
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    let listener = client.listen_index_signals(1.5);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    let signals = listener.finish();
+    let offline: Vec<&Vec<String>> = signals.iter().filter(|(name, _)| name == "IndexerOffline").map(|(_, a)| a).collect();
+    assert_eq!(offline, [&vec!["default".to_string()]], "{:?}", signals);
+
+    // Waiting to retry, so the dir is still queued.
+    let (queued, done, failed, online, dir) = client.index_status().unwrap();
+    assert_eq!((queued, done, failed, online), (1, 0, 0, false));
+    assert_eq!(dir, root.to_str().unwrap());
+}
+
+// ---------------------------------------------------------------------------
+// Pruning deleted files and following renames
+// ---------------------------------------------------------------------------
+
+fn indexed_files(db_path: &Path) -> Vec<(String, String, String)> {
+    let conn = open_test_db(db_path.to_str().unwrap());
+    let mut stmt = conn.prepare("SELECT dir, fname, description FROM descriptions ORDER BY dir, fname").unwrap();
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+    rows.filter_map(|r| r.ok()).collect()
+}
+
+fn invocations(peaks: &Path) -> usize {
+    fs::read_to_string(peaks).unwrap_or_default().lines().count()
+}
+
+#[test]
+fn test_renamed_file_keeps_description() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["cat.txt"]);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(invocations(&peaks), 1);
+
+    fs::rename(root.join("cat.txt"), root.join("kitten.txt")).unwrap();
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let r = root.to_str().unwrap().to_string();
+    assert_eq!(indexed_files(&db_path), [
+        (r, "kitten.txt".to_string(), format!("desc {}\n", root.join("cat.txt").display())),
+    ]);
+    assert_eq!(invocations(&peaks), 1, "a renamed file shouldn't be captioned again");
+}
+
+#[test]
+fn test_moved_file_keeps_description() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["cat.txt"]);
+    let other = ws.path().join("other");
+    fs::create_dir_all(&other).unwrap();
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    fs::rename(root.join("cat.txt"), other.join("cat.txt")).unwrap();
+    assert!(client.update_index(&[other.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let files = indexed_files(&db_path);
+    assert_eq!(files.len(), 1);
+    assert_eq!(PathBuf::from(&files[0].0), other);
+    assert_eq!(files[0].1, "cat.txt");
+    assert_eq!(invocations(&peaks), 1);
+}
+
+#[test]
+fn test_reindex_prunes_deleted_files() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["a.txt", "bb.txt"]);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1500));
+    assert_eq!(indexed_files(&db_path).len(), 2);
+
+    fs::remove_file(root.join("bb.txt")).unwrap();
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(500));
+
+    let files = indexed_files(&db_path);
+    assert_eq!(files.iter().map(|f| f.1.as_str()).collect::<Vec<_>>(), ["a.txt"]);
+    assert_eq!(invocations(&peaks), 2);
+}
+
+#[test]
+fn test_gc_sweep_removes_deleted_files() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["kept.txt"]);
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(&ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, format!("{}gc_interval = 1\n", content)).unwrap();
+    {
+        let conn = open_test_db(db_path.to_str().unwrap());
+        let r = root.to_str().unwrap();
+        for (dir, fname) in [(r, "kept.txt"), (r, "gone.txt"), ("/no/such/drive", "x.txt")] {
+            conn.execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, ?2, 'd', 1)", [dir, fname]).unwrap();
+        }
+    }
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    std::thread::sleep(Duration::from_millis(1800));
+
+    let files: Vec<(String, String)> = indexed_files(&db_path).into_iter().map(|(d, f, _)| (d, f)).collect();
+    assert_eq!(files, [
+        ("/no/such/drive".to_string(), "x.txt".to_string()),
+        (root.to_str().unwrap().to_string(), "kept.txt".to_string()),
+    ]);
+}
+
+#[test]
+fn test_gc_sweep_removes_failures_of_deleted_files() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["kept.txt"]);
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(&ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, format!("{}gc_interval = 1\n", content)).unwrap();
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    // The failures table is there once the portal has migrated the index
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let r = root.to_str().unwrap();
+    for (dir, fname) in [(r, "kept.txt"), (r, "gone.txt"), ("/no/such/drive", "x.txt")] {
+        conn.execute("INSERT INTO failures (dir, fname, mtime, error, attempts, last_attempt) VALUES (?1, ?2, 1, 'e', 1, 1)",
+            [dir, fname]).unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(1800));
+
+    let mut stmt = conn.prepare("SELECT dir, fname FROM failures ORDER BY dir, fname").unwrap();
+    let rows: Vec<(String, String)> = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?))).unwrap().filter_map(|r| r.ok()).collect();
+    assert_eq!(rows, [
+        ("/no/such/drive".to_string(), "x.txt".to_string()),
+        (r.to_string(), "kept.txt".to_string()),
+    ]);
+}
+
+#[test]
+fn test_gc_sweep_disabled() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &[]);
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(&ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, format!("{}gc_interval = 0\n", content)).unwrap();
+    open_test_db(db_path.to_str().unwrap())
+        .execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, 'gone.txt', 'd', 1)", [root.to_str().unwrap()]).unwrap();
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    std::thread::sleep(Duration::from_millis(1500));
+    assert_eq!(indexed_files(&db_path).len(), 1);
+}
+
+// ---------------------------------------------------------------------------
+// Index schema migrations
+// ---------------------------------------------------------------------------
+
+/// How many migrations the portal applies to index.db.
+const SCHEMA_VERSION: i64 = 8;
+
+fn schema_version(db_path: &Path) -> i64 {
+    let conn = rusqlite::Connection::open(db_path).unwrap();
+    conn.pragma_query_value(None, "user_version", |r| r.get(0)).unwrap()
+}
+
+fn stored_rows(db_path: &Path) -> Vec<(String, String, i64, Option<String>)> {
+    let conn = rusqlite::Connection::open(db_path).unwrap();
+    let mut stmt = conn.prepare("SELECT fname, description, mtime, indexer FROM descriptions ORDER BY fname").unwrap();
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+    rows.filter_map(|r| r.ok()).collect()
+}
+
+fn set_mtime_nanos(path: &Path, secs: i64, nanos: u32) {
+    filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(secs, nanos)).unwrap();
+}
+
+#[test]
+fn test_legacy_index_migrated() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["a.txt", "b.txt"]);
+    let db_path = ws.path().join("index.db");
+    {
+        let conn = open_test_db(db_path.to_str().unwrap());
+        let r = root.to_str().unwrap();
+        // a.txt is unchanged, but indexed twice by an old bug. b.txt changed since.
+        for (fname, desc, mtime) in [("a.txt", "old", 1_000_000.0), ("a.txt", "new", 1_000_000.0), ("b.txt", "stale", 5.0)] {
+            conn.execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![r, fname, desc, mtime]).unwrap();
+        }
+    }
+    let (guard, _) = start_worker_portal(&ws, &indexer, None);
+    assert_eq!(schema_version(&db_path), SCHEMA_VERSION);
+    let cmd = Some(indexer.to_str().unwrap().to_string());
+    assert_eq!(stored_rows(&db_path), [
+        ("a.txt".to_string(), "new".to_string(), 1_000_000_000_000_000, cmd.clone()),
+        ("b.txt".to_string(), "stale".to_string(), 5_000_000_000, cmd.clone()),
+    ]);
+    assert!(indexer_names(&db_path).iter().all(|(_, _, n)| n.as_deref() == Some("default")));
+    let conn = open_test_db(db_path.to_str().unwrap());
+    assert!(conn.execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, 'a.txt', 'dup', 1)",
+        [root.to_str().unwrap()]).is_err(), "paths should be unique");
+    let matches: i64 = conn.query_row("SELECT count(*) FROM descriptions_fts WHERE descriptions_fts MATCH 'new OR stale'",
+        [], |r| r.get(0)).unwrap();
+    assert_eq!(matches, 2, "existing descriptions should be searchable");
+
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(invocations(&peaks), 1, "only the changed file is reindexed");
+    assert_eq!(stored_rows(&db_path)[1].1, format!("desc {}\n", root.join("b.txt").display()));
+}
+
+#[test]
+fn test_migrated_index_reopened() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    drop(guard);
+    let conf = ws.path().join("portal.conf");
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    assert_eq!(schema_version(&db_path), SCHEMA_VERSION);
+}
+
+#[test]
+fn test_subsecond_change_reindexed() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["a.txt"]);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    // Too small a change to show up in f32 seconds
+    set_mtime_nanos(&root.join("a.txt"), 1_000_000, 500_000_000);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(invocations(&peaks), 2);
+    assert_eq!(stored_rows(&db_path)[0].2, 1_000_000_500_000_000);
+}
+
+#[test]
+fn test_indexer_change_marks_entries_stale() {
+    let ws = test_workspace();
+    let (indexer, _peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["a.txt"]);
+    let (guard, db_path) = start_worker_portal(&ws, &indexer, None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    drop(client);
+    drop(guard);
+
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let (_, conf) = write_indexer_test_config_with_cmd(&ws, fp_cmd.to_str().unwrap(), "cat");
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(stored_rows(&db_path), [
+        ("a.txt".to_string(), "content of a.txt".to_string(), 1_000_000_000_000_000, Some("cat".to_string())),
+    ]);
+}
+
+// ---------------------------------------------------------------------------
+// Embeddings
+// ---------------------------------------------------------------------------
+
+/// Portal captioning .txt files with `cat`, and embedding them with `embedder` if given.
+fn start_embedding_portal(ws: &TempDir, indexer: &str, embedder: Option<&Path>) -> (PortalGuard, PathBuf) {
+    let fp_cmd = create_mock_wrapper(ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(ws, fp_cmd.to_str().unwrap(), indexer);
+    if let Some(embedder) = embedder {
+        let content = fs::read_to_string(&conf).unwrap();
+        fs::write(&conf, format!("{}embed = {}\n", content, embedder.display())).unwrap();
+    }
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    (guard, db_path)
+}
+
+fn stored_embeddings(db_path: &Path) -> Vec<(String, Vec<f32>, String)> {
+    let conn = rusqlite::Connection::open(db_path).unwrap();
+    let mut stmt = conn.prepare("SELECT d.fname, e.vector, e.embedder FROM embeddings e
+                                 JOIN descriptions d ON d.id = e.id ORDER BY d.fname").unwrap();
+    let rows = stmt.query_map([], |row| {
+        let blob: Vec<u8> = row.get(1)?;
+        let vector = blob.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        Ok((row.get(0)?, vector, row.get(2)?))
+    }).unwrap();
+    rows.filter_map(|r| r.ok()).collect()
+}
+
+fn pet_dir(ws: &TempDir) -> PathBuf {
+    let dir = ws.path().join("pets");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("cat.txt"), "a cat on a car").unwrap();
+    fs::write(dir.join("dog.txt"), "a dog").unwrap();
+    dir
+}
+
+#[test]
+fn test_embeddings_stored_when_indexing() {
+    let ws = test_workspace();
+    let (embedder, _log) = create_mock_embedder(&ws);
+    let dir = pet_dir(&ws);
+    let (guard, db_path) = start_embedding_portal(&ws, "cat", Some(&embedder));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(800));
+
+    let e = embedder.to_str().unwrap().to_string();
+    assert_eq!(stored_embeddings(&db_path), [
+        ("cat.txt".to_string(), vec![1.0, 0.0, 1.0], e.clone()),
+        ("dog.txt".to_string(), vec![0.0, 1.0, 0.0], e.clone()),
+    ]);
+
+    // A changed file gets a new vector
+    fs::write(dir.join("dog.txt"), "a dog and a puppy").unwrap();
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(800));
+    assert_eq!(stored_embeddings(&db_path)[1].1, [0.0, 2.0, 0.0]);
+}
+
+#[test]
+fn test_raw_f32_embeddings() {
+    let ws = test_workspace();
+    let embedder = ws.path().join("raw-embedder.sh");
+    fs::write(&embedder, "#!/bin/bash\nprintf '\\x00\\x00\\x80\\x3f\\x00\\x00\\x00\\xc0'\n").unwrap();
+    fs::set_permissions(&embedder, fs::Permissions::from_mode(0o755)).unwrap();
+    let dir = create_test_dir(&ws, &["a.txt"]);
+    let (guard, db_path) = start_embedding_portal(&ws, "cat", Some(&embedder));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(800));
+    assert_eq!(stored_embeddings(&db_path)[0].1, [1.0, -2.0]);
+    assert_eq!(client.embed("anything"), Ok(vec![1.0, -2.0]));
+}
+
+#[test]
+fn test_embed_method() {
+    let ws = test_workspace();
+    let (embedder, log) = create_mock_embedder(&ws);
+    let (guard, _) = start_embedding_portal(&ws, "cat", Some(&embedder));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert_eq!(client.embed("kitty and puppy"), Ok(vec![1.0, 1.0, 0.0]));
+    assert_eq!(fs::read_to_string(log).unwrap(), "--text kitty and puppy\n");
+}
+
+#[test]
+fn test_embed_method_without_embedder() {
+    let ws = test_workspace();
+    let (guard, _) = start_embedding_portal(&ws, "cat", None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let err = client.embed("kitty").unwrap_err();
+    assert!(err.contains("NotSupported"), "{}", err);
+}
+
+#[test]
+fn test_embeddings_added_without_recaptioning() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let (embedder, _log) = create_mock_embedder(&ws);
+    let dir = pet_dir(&ws);
+    let (guard, db_path) = start_embedding_portal(&ws, indexer.to_str().unwrap(), None);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(stored_embeddings(&db_path).is_empty());
+    drop(client);
+    drop(guard);
+
+    let (guard, _) = start_embedding_portal(&ws, indexer.to_str().unwrap(), Some(&embedder));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(800));
+    assert_eq!(stored_embeddings(&db_path).len(), 2);
+    assert_eq!(invocations(&peaks), 2, "descriptions shouldn't be regenerated");
+}
+
+// ---------------------------------------------------------------------------
+// Named indexers
+// ---------------------------------------------------------------------------
+
+fn create_script(ws: &TempDir, name: &str, body: &str) -> PathBuf {
+    let script = ws.path().join(name);
+    fs::write(&script, format!("#!/bin/bash\n{}\n", body)).unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    script
+}
+
+/// Portal with the usual [indexer] section followed by `sections`.
+fn start_named_portal(ws: &TempDir, indexer: &Path, sections: &str) -> (PortalGuard, PathBuf) {
+    let fp_cmd = create_mock_wrapper(ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, format!("{}\n{}", content, sections)).unwrap();
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    std::thread::sleep(Duration::from_millis(200));
+    (guard, db_path)
+}
+
+fn indexer_names(db_path: &Path) -> Vec<(String, String, Option<String>)> {
+    let conn = rusqlite::Connection::open(db_path).unwrap();
+    let mut stmt = conn.prepare("SELECT fname, description, indexer_name FROM descriptions ORDER BY fname").unwrap();
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+    rows.filter_map(|r| r.ok()).collect()
+}
+
+#[test]
+fn test_files_routed_by_extension() {
+    let ws = test_workspace();
+    let text = create_script(&ws, "text.sh", "echo text");
+    let pdf = create_script(&ws, "pdf.sh", "echo pdf");
+    let audio = create_script(&ws, "audio.sh", "echo audio");
+    let root = create_test_dir(&ws, &["a.txt", "b.pdf", "c.MP3", "d.log", "e.bin"]);
+    let (guard, db_path) = start_named_portal(&ws, &text, &format!(
+        "[indexer.documents]\ncmd = {}\ncheck = exit 0\nextensions = pdf, LOG\n\n\
+         [indexer.audio]\ncmd = {}\ncheck = exit 0\nextensions = mp3,wav\n",
+        pdf.display(), audio.display()));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let row = |f: &str, d: &str, n: &str| (f.to_string(), format!("{}\n", d), Some(n.to_string()));
+    assert_eq!(indexer_names(&db_path), [
+        row("a.txt", "text", "default"),
+        row("b.pdf", "pdf", "documents"),
+        row("c.MP3", "audio", "audio"),
+        // Named sections come before [indexer], which also lists log
+        row("d.log", "pdf", "documents"),
+    ]);
+}
+
+#[test]
+fn test_slow_indexer_doesnt_hold_up_others() {
+    let ws = test_workspace();
+    let text = create_script(&ws, "text.sh", "echo text");
+    let slow = create_script(&ws, "slow.sh", "sleep 2\necho slow");
+    // Enough pdfs that the text file is almost surely listed behind a couple of them
+    let mut files: Vec<String> = (0..20).map(|i| format!("{}.pdf", i)).collect();
+    files.push("notes.txt".to_string());
+    let root = create_test_dir(&ws, &files.iter().map(String::as_str).collect::<Vec<_>>());
+    let (guard, db_path) = start_named_portal(&ws, &text, &format!(
+        "[indexer.documents]\ncmd = {}\ncheck = exit 0\nextensions = pdf\nworkers = 1\n", slow.display()));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let names: Vec<String> = indexer_names(&db_path).into_iter().map(|(f, _, _)| f).collect();
+    assert_eq!(names, ["notes.txt"], "the text file shouldn't wait behind the pdfs");
+}
+
+#[test]
+fn test_named_indexer_timeout() {
+    let ws = test_workspace();
+    let (indexer, _) = create_peak_indexer(&ws);
+    let finished = ws.path().join("finished");
+    let slow = create_script(&ws, "slow.sh", &format!("sleep 2\ntouch '{}'\necho slow", finished.display()));
+    let root = create_test_dir(&ws, &["a.pdf", "b.txt"]);
+    let (guard, db_path) = start_named_portal(&ws, &indexer, &format!(
+        "[indexer.documents]\ncmd = {}\ncheck = exit 0\nextensions = pdf\ntimeout = 1\n", slow.display()));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(3000));
+
+    let names: Vec<String> = indexer_names(&db_path).into_iter().map(|(f, _, _)| f).collect();
+    assert_eq!(names, ["b.txt"]);
+    let (_, done, failed, online, _) = client.index_status().unwrap();
+    assert_eq!((done, failed, online), (1, 1, true));
+    assert!(!finished.exists(), "the timed out command should be stopped");
+}
+
+#[test]
+fn test_named_indexer_workers() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let text_ws = test_workspace();
+    let (text, text_peaks) = create_peak_indexer(&text_ws);
+    let files: Vec<String> = (0..6).map(|i| format!("f{}.pdf", i)).chain((0..3).map(|i| format!("t{}.txt", i))).collect();
+    let root = create_test_dir(&ws, &files.iter().map(String::as_str).collect::<Vec<_>>());
+    let (guard, db_path) = start_named_portal(&ws, &text, &format!(
+        "[indexer.documents]\ncmd = {}\ncheck = exit 0\nextensions = pdf\nworkers = 3\n", indexer.display()));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(3000));
+
+    let conn = open_test_db(db_path.to_str().unwrap());
+    assert_eq!(count_descriptions(&conn), 9);
+    let peak = max_peak(&peaks);
+    assert!(peak > 1 && peak <= 3, "expected 2-3 pdfs at once, got {}", peak);
+    assert_eq!(max_peak(&text_peaks), 1, "[indexer] keeps its own single worker");
+}
+
+#[test]
+fn test_named_indexer_without_cmd_skipped() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let root = create_test_dir(&ws, &["a.pdf", "b.txt"]);
+    let (guard, db_path) = start_named_portal(&ws, &indexer, "[indexer.documents]\nextensions = pdf,txt\n");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let names: Vec<(String, Option<String>)> = indexer_names(&db_path).into_iter().map(|(f, _, n)| (f, n)).collect();
+    assert_eq!(names, [("b.txt".to_string(), Some("default".to_string()))]);
+    assert_eq!(invocations(&peaks), 1);
+}
+
+#[test]
+fn test_named_indexer_default_or_unnamed_ignored() {
+    let ws = test_workspace();
+    let (indexer, peaks) = create_peak_indexer(&ws);
+    let ran = ws.path().join("ran");
+    let other = create_script(&ws, "other.sh", &format!("touch '{}'\necho other", ran.display()));
+    let root = create_test_dir(&ws, &["a.pdf", "b.txt"]);
+    let (guard, db_path) = start_named_portal(&ws, &indexer, &format!(
+        "[indexer.default]\ncmd = {o}\ncheck = exit 0\nextensions = pdf,txt\n\n[indexer.]\ncmd = {o}\ncheck = exit 0\nextensions = pdf,txt\n",
+        o = other.display()));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let names: Vec<(String, Option<String>)> = indexer_names(&db_path).into_iter().map(|(f, _, n)| (f, n)).collect();
+    assert_eq!(names, [("b.txt".to_string(), Some("default".to_string()))]);
+    assert_eq!(invocations(&peaks), 1);
+    assert!(!ran.exists(), "neither section should get a route");
+}
+
+// ---------------------------------------------------------------------------
+// HTTP indexer
+// ---------------------------------------------------------------------------
+
+/// Stand-in caption server on localhost that gives every request `reply`, or holds the
+/// connection open without answering if it's empty. Keeps each request as (head, body).
+struct CaptionServer {
+    url: String,
+    requests: std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>,
+}
+
+fn start_caption_server(reply: String) -> CaptionServer {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/sdapi/v1/interrogate", listener.local_addr().unwrap());
+    let requests = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let log = requests.clone();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().filter_map(Result::ok) {
+            let mut data = vec![];
+            let mut buf = [0; 4096];
+            let head_end = loop {
+                let n = stream.read(&mut buf).unwrap_or(0);
+                if n == 0 { break None; }
+                data.extend_from_slice(&buf[..n]);
+                if let Some(i) = data.windows(4).position(|w| w == b"\r\n\r\n") { break Some(i); }
+            };
+            let Some(head_end) = head_end else { continue };
+            let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+            let len: usize = head.lines()
+                .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                .unwrap_or(0);
+            while data.len() < head_end + 4 + len {
+                let n = stream.read(&mut buf).unwrap_or(0);
+                if n == 0 { break; }
+                data.extend_from_slice(&buf[..n]);
+            }
+            let body = String::from_utf8_lossy(&data[head_end + 4..]).to_string();
+            log.lock().unwrap().push((head, body));
+            if reply.is_empty() {
+                std::thread::sleep(Duration::from_secs(5));
+            } else {
+                let _ = stream.write_all(reply.as_bytes());
+            }
+        }
+    });
+    CaptionServer { url, requests }
+}
+
+fn json_reply(status: &str, body: &str) -> String {
+    format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body)
+}
+
+fn start_http_portal(ws: &TempDir, settings: &str) -> (PortalGuard, PathBuf) {
+    let fp_cmd = create_mock_wrapper(ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(ws, fp_cmd.to_str().unwrap(), "exit 1");
+    let content = fs::read_to_string(&conf).unwrap().replace("check = exit 0\n", "");
+    fs::write(&conf, format!("{}type = http\n{}", content, settings)).unwrap();
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    std::thread::sleep(Duration::from_millis(200));
+    (guard, db_path)
+}
+
+fn image_dir(ws: &TempDir) -> PathBuf {
+    let dir = create_test_dir(ws, &["notes.md"]);
+    fs::write(dir.join("cat.png"), "hello").unwrap();
+    dir
+}
+
+#[test]
+fn test_http_indexer_posts_images() {
+    let ws = test_workspace();
+    let server = start_caption_server(json_reply("200 OK",
+        r#"{"model": {"name": "clip", "tags": ["a", "}"]}, "caption": "a cat \"sitting\"\non a car é"}"#));
+    let dir = image_dir(&ws);
+    let (guard, db_path) = start_http_portal(&ws, &format!("url = {}\n", server.url));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let rows: Vec<_> = stored_rows(&db_path).into_iter().map(|(f, d, _, i)| (f, d, i)).collect();
+    assert_eq!(rows, [("cat.png".to_string(), "a cat \"sitting\"\non a car é".to_string(), Some(server.url.clone()))]);
+    let requests = server.requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    let (head, body) = &requests[0];
+    assert!(head.starts_with("POST /sdapi/v1/interrogate HTTP/1.1\r\n"), "{}", head);
+    assert!(head.contains("Content-Type: application/json"), "{}", head);
+    assert_eq!(body, r#"{"image":"aGVsbG8=","model":"clip"}"#);
+    let (_, done, failed, online, _) = client.index_status().unwrap();
+    assert_eq!((done, failed, online), (1, 0, true));
+}
+
+#[test]
+fn test_http_indexer_reads_field_from_chunked_reply() {
+    let ws = test_workspace();
+    let body = r#"{"caption": "wrong", "text": "a chunked caption"}"#;
+    let (first, rest) = body.split_at(20);
+    let server = start_caption_server(format!(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+        first.len(), first, rest.len(), rest));
+    let dir = image_dir(&ws);
+    let (guard, db_path) = start_http_portal(&ws, &format!("url = {}\nfield = text\n", server.url));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let descs: Vec<String> = stored_rows(&db_path).into_iter().map(|(_, d, _, _)| d).collect();
+    assert_eq!(descs, ["a chunked caption"]);
+}
+
+#[test]
+fn test_http_indexer_error_reply_fails_file() {
+    let ws = test_workspace();
+    let server = start_caption_server(json_reply("500 Internal Server Error", r#"{"error": "Invalid image"}"#));
+    let dir = image_dir(&ws);
+    let (guard, db_path) = start_http_portal(&ws, &format!("url = {}\n", server.url));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    assert!(stored_rows(&db_path).is_empty());
+    let (_, done, failed, online, _) = client.index_status().unwrap();
+    assert_eq!((done, failed, online), (0, 1, true), "a server that answers is online");
+}
+
+#[test]
+fn test_http_indexer_oversized_reply_fails_file() {
+    let ws = test_workspace();
+    let caption = "a cat ".repeat(200_000);
+    let server = start_caption_server(json_reply("200 OK", &format!(r#"{{"caption": "{}"}}"#, caption)));
+    let dir = image_dir(&ws);
+    let (guard, db_path) = start_http_portal(&ws, &format!("url = {}\n", server.url));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    assert!(stored_rows(&db_path).is_empty());
+    let (_, done, failed, online, _) = client.index_status().unwrap();
+    assert_eq!((done, failed, online), (0, 1, true));
+}
+
+#[test]
+fn test_http_indexer_timeout() {
+    let ws = test_workspace();
+    let server = start_caption_server(String::new());
+    let dir = image_dir(&ws);
+    let (guard, db_path) = start_http_portal(&ws, &format!("url = {}\ntimeout = 1\n", server.url));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(2000));
+
+    assert!(stored_rows(&db_path).is_empty());
+    assert_eq!(server.requests.lock().unwrap().len(), 1);
+    let (_, _, failed, _, _) = client.index_status().unwrap();
+    assert_eq!(failed, 1);
+}
+
+#[test]
+fn test_http_indexer_offline_without_server() {
+    let ws = test_workspace();
+    let url = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/caption", listener.local_addr().unwrap())
+    };
+    let dir = image_dir(&ws);
+    let (guard, db_path) = start_http_portal(&ws, &format!("url = {}\n", url));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+
+    assert!(stored_rows(&db_path).is_empty());
+    let (_, _, failed, online, _) = client.index_status().unwrap();
+    assert_eq!((failed, online), (0, false), "the file waits for the server to come back");
+}
+
+#[test]
+fn test_legacy_index_migrated_for_http_indexer() {
+    let ws = test_workspace();
+    let dir = image_dir(&ws);
+    {
+        let conn = open_test_db(ws.path().join("index.db").to_str().unwrap());
+        conn.execute("INSERT INTO descriptions (dir, fname, description, mtime) VALUES (?1, 'cat.png', 'a cat', 5)",
+            [dir.to_str().unwrap()]).unwrap();
+    }
+    let url = "http://127.0.0.1:1/caption";
+    let (_guard, db_path) = start_http_portal(&ws, &format!("url = {}\n", url));
+
+    assert_eq!(schema_version(&db_path), SCHEMA_VERSION);
+    let rows: Vec<_> = stored_rows(&db_path).into_iter().map(|(f, d, _, i)| (f, d, i)).collect();
+    assert_eq!(rows, [("cat.png".to_string(), "a cat".to_string(), Some(url.to_string()))]);
+    assert_eq!(indexer_names(&db_path)[0].2.as_deref(), Some("default"));
+}
+
+// ---------------------------------------------------------------------------
+// Watched roots
+// ---------------------------------------------------------------------------
+
+fn start_watching_portal(ws: &TempDir, roots: &[&Path]) -> (PortalGuard, PathBuf) {
+    let fp_cmd = create_mock_wrapper(ws, &[]);
+    let indexer = create_mock_indexer(ws, "watched");
+    let (db_path, conf) = write_indexer_test_config_with_cmd(ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    let roots: Vec<String> = roots.iter().map(|r| r.display().to_string()).collect();
+    let content = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, format!("{}watch_roots = {}\n", content, roots.join(", "))).unwrap();
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    (guard, db_path)
+}
+
+fn indexed_names(db_path: &Path) -> Vec<String> {
+    indexed_files(db_path).into_iter().map(|(_, fname, _)| fname).collect::<std::collections::BTreeSet<_>>()
+        .into_iter().collect()
+}
+
+#[test]
+fn test_watch_roots_indexed_at_startup() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["top.txt", "a/x.txt", "a/b/y.txt", "skipped/z.txt", ".cache/w.txt"]);
+    let other = ws.path().join("other");
+    fs::create_dir_all(&other).unwrap();
+    fs::write(other.join("o.txt"), "o").unwrap();
+    fs::write(root.join(".gitignore"), "skipped\n").unwrap();
+    let (_guard, db_path) = start_watching_portal(&ws, &[&root, &other]);
+    std::thread::sleep(Duration::from_millis(1500));
+
+    assert_eq!(indexed_names(&db_path), ["o.txt", "top.txt", "x.txt", "y.txt"]);
+}
+
+#[test]
+fn test_watch_roots_follow_picker_search_settings() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["top.txt", "node_modules/pkg/x.txt", "build/y.txt"]);
+    fs::write(root.join(".gitignore"), "build\n").unwrap();
+    fs::create_dir_all(ws.path().join(".config")).unwrap();
+    fs::write(ws.path().join(".config/pikeru.conf"),
+        "[Settings]\nrespect_gitignore = false\n[SearchIgnore]\nnode_modules/\n").unwrap();
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let indexer = create_mock_indexer(&ws, "watched");
+    let (db_path, conf) = write_indexer_test_config_with_cmd(&ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, format!("{}watch_roots = {}\n", content, root.display())).unwrap();
+    let _guard = PortalGuard::with_env(db_path.to_str().unwrap(), conf.to_str().unwrap(), &[("HOME", ws.path())]);
+    std::thread::sleep(Duration::from_millis(1500));
+
+    assert_eq!(indexed_names(&db_path), ["top.txt", "y.txt"]);
+}
+
+#[test]
+fn test_watch_roots_index_new_files() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["a/x.txt"]);
+    let (_guard, db_path) = start_watching_portal(&ws, &[&root]);
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(indexed_names(&db_path), ["x.txt"]);
+
+    // Copied off a camera, moved in from elsewhere, and a whole new dir
+    fs::write(root.join("a/new.txt"), "new").unwrap();
+    let outside = ws.path().join("outside.txt");
+    fs::write(&outside, "moved").unwrap();
+    fs::rename(&outside, root.join("moved.txt")).unwrap();
+    fs::create_dir_all(root.join("trip/day1")).unwrap();
+    fs::write(root.join("trip/day1/beach.txt"), "beach").unwrap();
+    std::thread::sleep(Duration::from_millis(2500));
+    assert_eq!(indexed_names(&db_path), ["beach.txt", "moved.txt", "new.txt", "x.txt"]);
+
+    // The new dir is watched too
+    fs::write(root.join("trip/day1/sunset.txt"), "sunset").unwrap();
+    std::thread::sleep(Duration::from_millis(2500));
+    assert!(indexed_names(&db_path).contains(&"sunset.txt".to_string()));
+}
+
+#[test]
+fn test_watch_roots_reindex_modified_files() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["x.txt"]);
+    let (_guard, db_path) = start_watching_portal(&ws, &[&root]);
+    std::thread::sleep(Duration::from_millis(1000));
+    let before = stored_rows(&db_path)[0].2;
+
+    fs::write(root.join("x.txt"), "changed").unwrap();
+    std::thread::sleep(Duration::from_millis(2500));
+    assert_ne!(stored_rows(&db_path)[0].2, before);
+}
+
+#[test]
+fn test_watch_roots_ignored_when_indexer_disabled() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["x.txt"]);
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let conf = ws.path().join("portal.conf");
+    fs::write(&conf, format!("[filepicker]\ncmd = {}\n\n[indexer]\nenable = false\ncmd = cat\nextensions = txt\nwatch_roots = {}\n",
+        fp_cmd.display(), root.display())).unwrap();
+    let db_path = ws.path().join("index.db");
+    let _guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    std::thread::sleep(Duration::from_millis(1000));
+    fs::write(root.join("y.txt"), "y").unwrap();
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(indexed_names(&db_path).is_empty());
+}
+
+// ---------------------------------------------------------------------------
+// Indexing schedule
+// ---------------------------------------------------------------------------
+
+fn start_scheduled_portal(ws: &TempDir, settings: &str) -> (PortalGuard, PathBuf) {
+    let fp_cmd = create_mock_wrapper(ws, &[]);
+    let indexer = create_mock_indexer(ws, "scheduled");
+    let (db_path, conf) = write_indexer_test_config_with_cmd(ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, format!("{}{}", content, settings)).unwrap();
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    (guard, db_path)
+}
+
+fn indexed_count(db_path: &Path) -> usize {
+    count_descriptions(&open_test_db(db_path.to_str().unwrap()))
+}
+
+#[test]
+fn test_pause_and_resume() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["a.txt", "b.txt", "c.txt"]);
+    let (guard, db_path) = start_scheduled_portal(&ws, "");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert_eq!(client.index_paused(), Ok(false));
+    assert!(client.pause_indexer(true).is_ok());
+    assert_eq!(client.index_paused(), Ok(true));
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(indexed_count(&db_path), 0);
+    assert_eq!(client.index_status().unwrap().0, 1, "the dir stays queued while paused");
+
+    assert!(client.pause_indexer(false).is_ok());
+    assert_eq!(client.index_paused(), Ok(false));
+    std::thread::sleep(Duration::from_millis(800));
+    assert_eq!(indexed_count(&db_path), 3);
+}
+
+#[test]
+fn test_max_files_per_minute() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["a.txt", "b.txt", "c.txt", "d.txt"]);
+    let (guard, db_path) = start_scheduled_portal(&ws, "max_files_per_minute = 120\n");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let start = std::time::Instant::now();
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    let deadline = start + Duration::from_secs(15);
+    while client.index_status().unwrap().1 < 4 {
+        assert!(std::time::Instant::now() < deadline, "files were not indexed in time");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    // One file every half second, so at least three gaps between four files
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(1400), "indexing wasn't rate limited: {:?}", elapsed);
+    assert_eq!(indexed_count(&db_path), 4);
+}
+
+#[test]
+fn test_only_when_idle() {
+    let ws = test_workspace();
+    let loadavg = ws.path().join("loadavg");
+    fs::write(&loadavg, "9999.00 9999.00 9999.00 1/100 1234\n").unwrap();
+    let root = create_test_dir(&ws, &["a.txt"]);
+    let (guard, db_path) = start_scheduled_portal(&ws, &format!("only_when_idle = true\nloadavg_path = {}\n", loadavg.display()));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(indexed_count(&db_path), 0);
+
+    fs::write(&loadavg, "0.00 0.00 0.00 1/100 1234\n").unwrap();
+    std::thread::sleep(Duration::from_millis(2500));
+    assert_eq!(indexed_count(&db_path), 1);
+}
+
+#[test]
+fn test_pause_on_battery() {
+    let ws = test_workspace();
+    let supplies = ws.path().join("power_supply");
+    fs::create_dir_all(supplies.join("AC")).unwrap();
+    fs::write(supplies.join("AC/type"), "Mains\n").unwrap();
+    fs::create_dir_all(supplies.join("BAT0")).unwrap();
+    fs::write(supplies.join("BAT0/type"), "Battery\n").unwrap();
+    fs::write(supplies.join("BAT0/status"), "Discharging\n").unwrap();
+    let root = create_test_dir(&ws, &["a.txt"]);
+    let (guard, db_path) = start_scheduled_portal(&ws, &format!("pause_on_battery = true\npower_supply_path = {}\n", supplies.display()));
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(indexed_count(&db_path), 0);
+
+    fs::write(supplies.join("BAT0/status"), "Charging\n").unwrap();
+    std::thread::sleep(Duration::from_millis(2500));
+    assert_eq!(indexed_count(&db_path), 1);
+}
+
+#[test]
+fn test_retry_backs_off_from_retry_delay() {
+    let ws = test_workspace();
+    // Offline for the first try and check, fine after that
+    let calls = ws.path().join("calls");
+    let flaky = create_script(&ws, "flaky.sh", &format!(
+        "echo x >> '{c}'\n[ $(wc -l < '{c}') -le 2 ] && exit 1\necho \"desc $1\"", c = calls.display()));
+    let root = create_test_dir(&ws, &["a.txt"]);
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(&ws, fp_cmd.to_str().unwrap(), flaky.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap().replace("check = exit 0", &format!("check = {}", flaky.display()));
+    fs::write(&conf, format!("{}retry_delay = 0.5\n", content)).unwrap();
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(1500));
+
+    let (_, done, failed, online, _) = client.index_status().unwrap();
+    assert_eq!((done, failed, online), (1, 0, true));
+    assert_eq!(indexed_count(&db_path), 1);
+}
+
+// ---------------------------------------------------------------------------
+// Failed files
+// ---------------------------------------------------------------------------
+
+fn failures(db_path: &Path) -> Vec<(String, Option<i32>, String, u32)> {
+    let conn = rusqlite::Connection::open(db_path).unwrap();
+    let mut stmt = conn.prepare("SELECT fname, exit_code, error, attempts FROM failures ORDER BY fname").unwrap();
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+    rows.filter_map(|r| r.ok()).collect()
+}
+
+fn offline_attempts(db_path: &Path) -> Vec<u32> {
+    let conn = rusqlite::Connection::open(db_path).unwrap();
+    let mut stmt = conn.prepare("SELECT offline_attempts FROM failures ORDER BY fname").unwrap();
+    let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+    rows.filter_map(|r| r.ok()).collect()
+}
+
+/// An indexer that fails on files named bad*, counting its calls in `calls`.
+fn create_picky_indexer(ws: &TempDir) -> (PathBuf, PathBuf) {
+    let calls = ws.path().join("calls");
+    let indexer = create_script(ws, "picky.sh", &format!(
+        "echo \"$1\" >> '{}'\ncase \"$(basename \"$1\")\" in bad*) echo \"can't read $1\" >&2; exit 3;; esac\necho \"desc $1\"",
+        calls.display()));
+    (indexer, calls)
+}
+
+fn call_count(calls: &Path, fname: &str) -> usize {
+    fs::read_to_string(calls).unwrap_or_default().lines().filter(|l| l.ends_with(fname)).count()
+}
+
+fn index_once(client: &PortalClient, dir: &Path) {
+    assert!(client.update_index(&[dir.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(600));
+}
+
+#[test]
+fn test_failure_recorded() {
+    let ws = test_workspace();
+    let (indexer, _) = create_picky_indexer(&ws);
+    let root = create_test_dir(&ws, &["bad.txt", "good.txt"]);
+    let (guard, db_path) = start_named_portal(&ws, &indexer, "");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    index_once(&client, &root);
+
+    let rows = failures(&db_path);
+    assert_eq!(rows.len(), 1, "{:?}", rows);
+    let (fname, code, error, attempts) = &rows[0];
+    assert_eq!((fname.as_str(), *code, *attempts), ("bad.txt", Some(3), 1));
+    assert!(error.contains("can't read"), "stderr should be kept: {}", error);
+    assert_eq!(indexer_names(&db_path).len(), 1);
+    let (_, done, failed, _, _) = client.index_status().unwrap();
+    assert_eq!((done, failed), (1, 1));
+}
+
+#[test]
+fn test_failing_file_skipped_until_changed() {
+    let ws = test_workspace();
+    let (indexer, calls) = create_picky_indexer(&ws);
+    let root = create_test_dir(&ws, &["bad.txt"]);
+    let (guard, db_path) = start_named_portal(&ws, &indexer, "");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    for _ in 0..5 {
+        index_once(&client, &root);
+    }
+    assert_eq!(call_count(&calls, "bad.txt"), 3, "tried up to max_attempts times");
+    assert_eq!(failures(&db_path)[0].3, 3);
+
+    fs::write(root.join("bad.txt"), "changed").unwrap();
+    index_once(&client, &root);
+    assert_eq!(call_count(&calls, "bad.txt"), 4, "a changed file gets tried again");
+    assert_eq!(failures(&db_path)[0].3, 1, "attempts start over for the new version");
+}
+
+#[test]
+fn test_max_attempts_setting() {
+    let ws = test_workspace();
+    let (indexer, calls) = create_picky_indexer(&ws);
+    let root = create_test_dir(&ws, &["bad.txt"]);
+    let (guard, _db_path) = start_named_portal(&ws, &indexer, "max_attempts = 1\n");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    index_once(&client, &root);
+    index_once(&client, &root);
+    assert_eq!(call_count(&calls, "bad.txt"), 1);
+}
+
+#[test]
+fn test_retry_delay_too_long_ignored() {
+    let ws = test_workspace();
+    let indexer = create_mock_indexer(&ws, "delay");
+    let root = create_test_dir(&ws, &["a.txt"]);
+    let (guard, db_path) = start_named_portal(&ws, &indexer, "retry_delay = inf\nretry_delay = 1e40\n");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    index_once(&client, &root);
+    assert_eq!(indexed_count(&db_path), 1);
+}
+
+#[test]
+fn test_success_clears_failure() {
+    let ws = test_workspace();
+    let broken = ws.path().join("broken");
+    fs::write(&broken, "").unwrap();
+    let indexer = create_script(&ws, "fixable.sh", &format!(
+        "[ -e '{}' ] && {{ echo broken >&2; exit 1; }}\necho \"desc $1\"", broken.display()));
+    let root = create_test_dir(&ws, &["a.txt"]);
+    let (guard, db_path) = start_named_portal(&ws, &indexer, "");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    index_once(&client, &root);
+    assert_eq!(failures(&db_path).len(), 1);
+
+    fs::remove_file(&broken).unwrap();
+    index_once(&client, &root);
+    assert!(failures(&db_path).is_empty());
+    assert_eq!(indexer_names(&db_path).len(), 1);
+}
+
+#[test]
+fn test_file_that_knocks_indexer_offline_is_skipped() {
+    let ws = test_workspace();
+    // bad.txt "crashes" the indexer, so the next check fails once
+    let crashed = ws.path().join("crashed");
+    let indexer = create_script(&ws, "crashy.sh", &format!(
+        "case \"$(basename \"$1\")\" in bad*) touch '{c}'; exit 1;; esac\necho \"desc $1\"", c = crashed.display()));
+    let check = create_script(&ws, "check.sh", &format!("[ -e '{c}' ] && {{ rm '{c}'; exit 1; }}\nexit 0", c = crashed.display()));
+    let root = create_test_dir(&ws, &["bad.txt", "good.txt"]);
+    let fp_cmd = create_mock_wrapper(&ws, &[]);
+    let (db_path, conf) = write_indexer_test_config_with_cmd(&ws, fp_cmd.to_str().unwrap(), indexer.to_str().unwrap());
+    let content = fs::read_to_string(&conf).unwrap().replace("check = exit 0", &format!("check = {}", check.display()));
+    fs::write(&conf, format!("{}retry_delay = 0.2\n", content)).unwrap();
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), conf.to_str().unwrap());
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    assert!(client.update_index(&[root.to_str().unwrap()]).is_ok());
+    std::thread::sleep(Duration::from_millis(2500));
+
+    let (queued, done, failed, _, _) = client.index_status().unwrap();
+    assert_eq!((queued, done, failed), (0, 1, 1), "the batch should finish without bad.txt");
+    assert_eq!(failures(&db_path)[0].3, 0, "going offline isn't counted as the file failing");
+    assert_eq!(offline_attempts(&db_path), [3]);
+    assert_eq!(indexer_names(&db_path).len(), 1);
+}
+
+// ---------------------------------------------------------------------------
+// Recursive updates
+// ---------------------------------------------------------------------------
+
+fn start_recursive_portal(ws: &TempDir) -> (PortalGuard, PortalClient, PathBuf) {
+    let indexer = create_mock_indexer(ws, "recursive");
+    let (guard, db_path) = start_named_portal(ws, &indexer, "");
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    (guard, client, db_path)
+}
+
+#[test]
+fn test_update_recursive_indexes_tree() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["top.txt", "a/x.txt", "a/b/y.txt", "a/b/c/z.txt", ".hidden/h.txt"]);
+    let (_guard, client, db_path) = start_recursive_portal(&ws);
+    assert!(client.update_index_recursive(root.to_str().unwrap(), 0).is_ok());
+    std::thread::sleep(Duration::from_millis(1200));
+
+    assert_eq!(indexed_names(&db_path), ["top.txt", "x.txt", "y.txt", "z.txt"]);
+    let (queued, done, _, _, _) = client.index_status().unwrap();
+    assert_eq!((queued, done), (0, 4));
+}
+
+#[test]
+fn test_update_recursive_max_depth() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["top.txt", "a/x.txt", "a/b/y.txt", "a/b/c/z.txt"]);
+    let (_guard, client, db_path) = start_recursive_portal(&ws);
+    assert!(client.update_index_recursive(root.to_str().unwrap(), 1).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(indexed_names(&db_path), ["top.txt", "x.txt"]);
+
+    assert!(client.update_index_recursive(root.join("a").to_str().unwrap(), 2).is_ok());
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(indexed_names(&db_path), ["top.txt", "x.txt", "y.txt", "z.txt"]);
+}
+
+#[test]
+fn test_update_recursive_skips_ignored_dirs() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["top.txt", "build/out.txt", "build/deep/more.txt", "node_modules/m.txt", "src/s.txt"]);
+    fs::write(root.join(".gitignore"), "build\n").unwrap();
+    let (_guard, client, db_path) = start_recursive_portal(&ws);
+    assert!(client.configure_indexer(true, "node_modules").is_ok());
+    assert!(client.update_index_recursive(root.to_str().unwrap(), 0).is_ok());
+    std::thread::sleep(Duration::from_millis(1200));
+
+    assert_eq!(indexed_names(&db_path), ["s.txt", "top.txt"]);
+}
+
+#[test]
+fn test_update_recursive_while_indexing() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["a/x.txt", "b/y.txt"]);
+    let (_guard, client, db_path) = start_recursive_portal(&ws);
+    assert!(client.update_index(&[root.join("a").to_str().unwrap()]).is_ok());
+    assert!(client.update_index_recursive(root.to_str().unwrap(), 0).is_ok());
+    std::thread::sleep(Duration::from_millis(1200));
+
+    assert_eq!(indexed_names(&db_path), ["x.txt", "y.txt"]);
+    assert_eq!(client.index_status().unwrap().0, 0);
+}
+
+#[test]
+fn test_clear_queue_stops_recursive_walk() {
+    let ws = test_workspace();
+    let root = create_test_dir(&ws, &["top.txt"]);
+    // Big enough that walking it outlasts the ClearQueue call
+    for (a, b, c) in (0..30).flat_map(|a| (0..30).flat_map(move |b| (0..30).map(move |c| (a, b, c)))) {
+        fs::create_dir_all(root.join(format!("{}/{}/{}", a, b, c))).unwrap();
+    }
+    let (_guard, client, db_path) = start_recursive_portal(&ws);
+    assert!(client.update_index_recursive(root.to_str().unwrap(), 0).is_ok());
+    assert!(client.clear_index_queue().is_ok());
+    std::thread::sleep(Duration::from_millis(3000));
+
+    assert!(indexed_names(&db_path).is_empty());
+    assert_eq!(client.index_status().unwrap().0, 0);
+}
+
+// ---------------------------------------------------------------------------
+// FileChooser filters
+// ---------------------------------------------------------------------------
+
+const FILTERS_JSON: &str = r#"{
+    "filters": [["Images", [[0, "*.png"], [1, "image/jpeg"]]], ["PDF", [[0, "*.pdf"]]]],
+    "current_filter": ["PDF", [[0, "*.pdf"]]]
+}"#;
+
+fn start_filter_portal(ws: &TempDir, records: &[&str]) -> (PortalGuard, PathBuf) {
+    let capture = ws.path().join("args.txt");
+    let wrapper = create_result_wrapper(ws, &capture, records, &["/tmp/doc.pdf"]);
+    let (db_path, _svc, _obj) = write_test_config(
+        ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+    (guard, capture)
+}
+
+#[test]
+fn test_filters_passed_to_wrapper() {
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(FILTERS_JSON).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+
+    let args: Vec<String> = fs::read_to_string(&capture).unwrap().lines().map(String::from).collect();
+    let extra = &args[4..];
+    assert_eq!(extra, ["--title", "Test", "--filter", "Images|glob:*.png|mime:image/jpeg", "--filter", "PDF|glob:*.pdf", "--current-filter", "PDF"]);
+}
+
+#[test]
+fn test_filter_names_are_escaped() {
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(r#"{"filters": [["A|B \\ C", [[0, "*.a"]]]]}"#)
+        .expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+
+    let args = fs::read_to_string(&capture).unwrap();
+    assert!(args.lines().any(|l| l == r"A\|B \\ C|glob:*.a"), "args: {}", args);
+}
+
+#[test]
+fn test_current_filter_returned() {
+    let ws = test_workspace();
+    let (guard, _capture) = start_filter_portal(&ws, &["filter=1"]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(FILTERS_JSON).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.current_filter.as_deref(), Some("PDF|0:*.pdf"));
+}
+
+#[test]
+fn test_current_filter_without_filter_list() {
+    // A lone current_filter applies unconditionally, so it's sent as the only filter.
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &["filter=0"]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(r#"{"current_filter": ["Text", [[1, "text/plain"]]]}"#)
+        .expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.current_filter.as_deref(), Some("Text|1:text/plain"));
+
+    let args: Vec<String> = fs::read_to_string(&capture).unwrap().lines().map(String::from).collect();
+    assert_eq!(&args[4..], ["--title", "Test", "--filter", "Text|mime:text/plain", "--current-filter", "Text"]);
+}
+
+#[test]
+fn test_no_filter_result_without_record() {
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with("{}").expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.current_filter, None);
+    // only the title follows the positional args
+    assert_eq!(fs::read_to_string(&capture).unwrap().lines().count(), 6);
+}
+
+#[test]
+fn test_bad_filter_index_ignored() {
+    let ws = test_workspace();
+    let (guard, _capture) = start_filter_portal(&ws, &["filter=7"]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(FILTERS_JSON).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.current_filter, None);
+}
+
+// ---------------------------------------------------------------------------
+// FileChooser choices
+// ---------------------------------------------------------------------------
+
+const CHOICES_JSON: &str = r#"{
+    "choices": [
+        ["encoding", "Encoding", [["utf8", "Unicode (UTF-8)"], ["latin15", "Western"]], "latin15"],
+        ["reencode", "Reencode", [], "false"]
+    ]
+}"#;
+
+#[test]
+fn test_choices_passed_to_wrapper() {
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(CHOICES_JSON).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+
+    let args: Vec<String> = fs::read_to_string(&capture).unwrap().lines().map(String::from).collect();
+    assert_eq!(&args[4..], [
+        "--title", "Test",
+        "--choice", "encoding|Encoding|latin15|utf8|Unicode (UTF-8)|latin15|Western",
+        "--choice", "reencode|Reencode|false",
+    ]);
+}
+
+#[test]
+fn test_choice_labels_are_escaped() {
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(r#"{"choices": [["c", "A|B", [["x", "X \\ Y"]], "x"]]}"#)
+        .expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+
+    let args = fs::read_to_string(&capture).unwrap();
+    assert!(args.lines().any(|l| l == r"c|A\|B|x|x|X \\ Y"), "args: {}", args);
+}
+
+#[test]
+fn test_choices_returned() {
+    let ws = test_workspace();
+    let (guard, _capture) = start_filter_portal(&ws, &["choice.0=utf8", "choice.1=true"]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(CHOICES_JSON).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.choices, Some(vec![
+        ("encoding".to_string(), "utf8".to_string()),
+        ("reencode".to_string(), "true".to_string()),
+    ]));
+}
+
+#[test]
+fn test_no_choices_result_without_record() {
+    let ws = test_workspace();
+    let (guard, _capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(CHOICES_JSON).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.choices, None);
+}
+
+#[test]
+fn test_unknown_choice_index_ignored() {
+    let ws = test_workspace();
+    let (guard, _capture) = start_filter_portal(&ws, &["choice.5=utf8", "choice.1=true"]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(CHOICES_JSON).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.choices, Some(vec![("reencode".to_string(), "true".to_string())]));
+}
+
+// ---------------------------------------------------------------------------
+// FileChooser SaveFiles
+// ---------------------------------------------------------------------------
+
+fn start_save_files_portal(ws: &TempDir, chosen: &Path) -> (PortalGuard, PathBuf) {
+    let capture = ws.path().join("args.txt");
+    let wrapper = create_result_wrapper(ws, &capture, &[], &[chosen.to_str().unwrap()]);
+    let (db_path, _svc, _obj) = write_test_config(
+        ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+    (guard, capture)
+}
+
+#[test]
+fn test_save_files_returns_uri_per_file() {
+    let ws = test_workspace();
+    let target = create_test_dir(&ws, &[]);
+    let (guard, capture) = start_save_files_portal(&ws, &target);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.save_files_with(&format!(
+        r#"{{"current_folder": "{}", "files": ["a.txt", "b.pdf"]}}"#, ws.path().display()))
+        .expect("save_files should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, [
+        format!("file://{}/a.txt", target.display()),
+        format!("file://{}/b.pdf", target.display()),
+    ]);
+
+    // directory mode for saving, starting in current_folder
+    let args: Vec<String> = fs::read_to_string(&capture).unwrap().lines().map(String::from).collect();
+    assert_eq!(&args[..4], ["0", "1", "1", ws.path().to_str().unwrap()]);
+}
+
+#[test]
+fn test_save_files_renames_collisions() {
+    let ws = test_workspace();
+    let target = create_test_dir(&ws, &["a.txt", "a_1.txt", "noext"]);
+    let (guard, _capture) = start_save_files_portal(&ws, &target);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.save_files_with(r#"{"files": ["a.txt", "noext", "b.txt", "b.txt", "noext"]}"#)
+        .expect("save_files should succeed");
+    assert_eq!(result.status, 0);
+    let names: Vec<&str> = result.uris.iter().map(|u| u.rsplit('/').next().unwrap()).collect();
+    assert_eq!(names, ["a_2.txt", "noext_1", "b.txt", "b_1.txt", "noext_2"]);
+}
+
+#[test]
+fn test_save_files_stays_in_chosen_dir() {
+    let ws = test_workspace();
+    let target = create_test_dir(&ws, &[]);
+    let (guard, _capture) = start_save_files_portal(&ws, &target);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.save_files_with(r#"{"files": ["../../etc/evil.txt", ".."]}"#)
+        .expect("save_files should succeed");
+    assert_eq!(result.status, 0);
+    assert_eq!(result.uris, [
+        format!("file://{}/evil.txt", target.display()),
+        format!("file://{}/download", target.display()),
+    ]);
+}
+
+#[test]
+fn test_save_files_cancelled() {
+    let ws = test_workspace();
+    let capture = ws.path().join("args.txt");
+    let wrapper = create_result_wrapper(&ws, &capture, &[], &[]);
+    let (db_path, _svc, _obj) = write_test_config(
+        &ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.save_files_with(r#"{"files": ["a.txt"]}"#).expect("save_files should succeed");
+    assert_eq!(result.status, 1);
+    assert!(result.uris.is_empty());
+}
+
+// ---------------------------------------------------------------------------
+// Request.Close
+// ---------------------------------------------------------------------------
+
+/// Wrapper that stays open until killed, leaving `marker` behind if it isn't.
+fn start_hanging_portal(ws: &TempDir, marker: &Path) -> PortalGuard {
+    let wrapper = ws.path().join("hanging-wrapper.sh");
+    fs::write(&wrapper, format!("#!/bin/bash\nsleep 3\ntouch '{}'\necho /tmp/late.txt\n", marker.display())).unwrap();
+    fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+    let (db_path, _svc, _obj) = write_test_config(
+        ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap())
+}
+
+fn close_when_exported(client: &PortalClient) {
+    for _ in 0..50 {
+        if client.close_request("/org/test/handle").is_ok() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("request object was never exported");
+}
+
+#[test]
+fn test_close_request_cancels_dialog() {
+    let ws = test_workspace();
+    let marker = ws.path().join("finished");
+    let guard = start_hanging_portal(&ws, &marker);
+    let (service, object) = (guard.service_name.clone(), guard.object_path.clone());
+    let call = std::thread::spawn(move || PortalClient::new(&service, &object).open_file(false, false));
+
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    close_when_exported(&client);
+    let result = call.join().unwrap().expect("open_file should return");
+    assert_eq!(result.status, 2);
+    assert!(result.uris.is_empty());
+
+    // the picker process group was killed, not left running
+    std::thread::sleep(Duration::from_millis(3500));
+    assert!(!marker.exists(), "picker kept running after Close");
+}
+
+#[test]
+fn test_request_removed_after_dialog() {
+    let ws = test_workspace();
+    let capture = ws.path().join("args.txt");
+    let wrapper = create_result_wrapper(&ws, &capture, &[], &["/tmp/a.txt"]);
+    let (db_path, _svc, _obj) = write_test_config(
+        &ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file(false, false).expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    assert!(client.close_request("/org/test/handle").is_err());
+}
+
+// ---------------------------------------------------------------------------
+// Dialog title, accept_label, modal and current_file
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_dialog_appearance_passed_to_wrapper() {
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(r#"{"_title": "Pick a photo", "accept_label": "_Upload", "modal": false}"#)
+        .expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+
+    let args: Vec<String> = fs::read_to_string(&capture).unwrap().lines().map(String::from).collect();
+    assert_eq!(&args[4..], ["--title", "Pick a photo", "--accept-label", "Upload", "--nonmodal"]);
+}
+
+#[test]
+fn test_empty_title_not_passed() {
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(r#"{"_title": "", "accept_label": "Save__as", "modal": true}"#)
+        .expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+
+    let args: Vec<String> = fs::read_to_string(&capture).unwrap().lines().map(String::from).collect();
+    assert_eq!(&args[4..], ["--accept-label", "Save_as"]);
+}
+
+#[test]
+fn test_save_current_file_used_as_path() {
+    let ws = test_workspace();
+    let dir = create_test_dir(&ws, &["report.txt"]);
+    let existing = dir.join("report.txt");
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.save_file_with(&format!(
+        r#"{{"current_folder": "/tmp", "current_name": "other.txt", "current_file": "{}"}}"#, existing.display()))
+        .expect("save_file should succeed");
+    assert_eq!(result.status, 0);
+
+    let args: Vec<String> = fs::read_to_string(&capture).unwrap().lines().map(String::from).collect();
+    assert_eq!(args[3], existing.to_str().unwrap());
+}
+
+// ---------------------------------------------------------------------------
+// Concurrent requests
+// ---------------------------------------------------------------------------
+
+fn open_in_thread(guard: &PortalGuard, handle: &str) -> std::thread::JoinHandle<Result<OpenResult, String>> {
+    let (service, object) = (guard.service_name.clone(), guard.object_path.clone());
+    let options = format!(r#"{{"_handle": "{}"}}"#, handle);
+    std::thread::spawn(move || PortalClient::new(&service, &object).open_file_with(&options))
+}
+
+#[test]
+fn test_concurrent_dialogs_run_in_parallel() {
+    let ws = test_workspace();
+    let wrapper = create_slow_mock_wrapper(&ws, 2000, &["/tmp/a.txt"]);
+    let (db_path, _svc, _obj) = write_test_config(
+        &ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+
+    let start = std::time::Instant::now();
+    let calls: Vec<_> = (0..3).map(|i| open_in_thread(&guard, &format!("/org/test/handle{}", i))).collect();
+    for call in calls {
+        let result = call.join().unwrap().expect("open_file should succeed");
+        assert_eq!(result.status, 0);
+        assert_eq!(result.uris, ["file:///tmp/a.txt"]);
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed < Duration::from_millis(4000), "dialogs were serialized: {:?}", elapsed);
+}
+
+#[test]
+fn test_close_only_cancels_its_own_request() {
+    let ws = test_workspace();
+    let wrapper = create_slow_mock_wrapper(&ws, 1500, &["/tmp/a.txt"]);
+    let (db_path, _svc, _obj) = write_test_config(
+        &ws, wrapper.to_str().unwrap(), "echo idx", "exit 0", "txt",
+    );
+    let guard = PortalGuard::new(db_path.to_str().unwrap(), ws.path().join("portal.conf").to_str().unwrap());
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+
+    let first = open_in_thread(&guard, "/org/test/first");
+    let second = open_in_thread(&guard, "/org/test/second");
+    for _ in 0..50 {
+        if client.close_request("/org/test/first").is_ok() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(first.join().unwrap().expect("first call should return").status, 2);
+    let second = second.join().unwrap().expect("second call should return");
+    assert_eq!(second.status, 0);
+    assert_eq!(second.uris, ["file:///tmp/a.txt"]);
+}
+
+// ---------------------------------------------------------------------------
+// Parent window
+// ---------------------------------------------------------------------------
+
+fn parent_args(parent: &str) -> Vec<String> {
+    let ws = test_workspace();
+    let (guard, capture) = start_filter_portal(&ws, &[]);
+    let client = PortalClient::new(&guard.service_name, &guard.object_path);
+    let result = client.open_file_with(&format!(r#"{{"_title": "", "_parent": "{}"}}"#, parent))
+        .expect("open_file should succeed");
+    assert_eq!(result.status, 0);
+    fs::read_to_string(&capture).unwrap().lines().skip(4).map(String::from).collect()
+}
+
+#[test]
+fn test_x11_parent_passed_to_wrapper() {
+    assert_eq!(parent_args("x11:4a0000c"), ["--parent", "x11:4a0000c"]);
+}
+
+#[test]
+fn test_wayland_parent_passed_to_wrapper() {
+    assert_eq!(parent_args("wayland:abc-123"), ["--parent", "wayland:abc-123"]);
+}
+
+#[test]
+fn test_unknown_parent_left_to_pikeru() {
+    // pikeru parses the handle and ignores ones it doesn't understand
+    assert_eq!(parent_args("mir:42"), ["--parent", "mir:42"]);
+    assert!(parent_args("").is_empty());
+}
+
+/// An X server on a display of its own, killed on drop.
+struct Xvfb {
+    child: std::process::Child,
+    display: String,
+}
+
+impl Xvfb {
+    /// None when Xvfb isn't installed.
+    fn start() -> Option<Self> {
+        let n = (100..1000).map(|n| n + std::process::id() % 900).find(|n| !Path::new(&format!("/tmp/.X11-unix/X{}", n)).exists())?;
+        let display = format!(":{}", n);
+        let child = match std::process::Command::new("Xvfb").args([display.as_str(), "-nolisten", "tcp"])
+            .stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).spawn() {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => panic!("Failed to start Xvfb: {}", e),
+        };
+        Some(Self { child, display })
+    }
+}
+
+impl Drop for Xvfb {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn test_picker_transient_for_x11_parent() {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, CreateWindowAux, WindowClass};
+    let Some(xvfb) = Xvfb::start() else {
+        eprintln!("Xvfb not found, skipping");
+        return;
+    };
+    let mut tries = 0;
+    let (conn, screen) = loop {
+        match x11rb::connect(Some(&xvfb.display)) {
+            Ok(conn) => break conn,
+            Err(e) if tries == 100 => panic!("Xvfb never accepted connections: {}", e),
+            Err(_) => std::thread::sleep(Duration::from_millis(100)),
+        }
+        tries += 1;
+    };
+    let root = conn.setup().roots[screen].root;
+    let parent = conn.generate_id().unwrap();
+    conn.create_window(x11rb::COPY_DEPTH_FROM_PARENT, parent, root, 0, 0, 100, 100, 0,
+                       WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new()).unwrap();
+    conn.map_window(parent).unwrap();
+    conn.flush().unwrap();
+
+    let ws = test_workspace();
+    let mut picker = std::process::Command::new(env!("CARGO_BIN_EXE_pikeru"))
+        .args(["-m", "file", "-p", ws.path().to_str().unwrap(), "--parent", &format!("x11:{:x}", parent)])
+        .env("DISPLAY", &xvfb.display)
+        .env("HOME", ws.path())
+        .env("ICED_BACKEND", "tiny-skia")
+        .env("DBUS_SESSION_BUS_ADDRESS", session_bus())
+        .stdout(std::process::Stdio::null())
+        .spawn().expect("Failed to run pikeru");
+    let transient_for_parent = |w: u32| {
+        let prop = conn.get_property(false, w, AtomEnum::WM_TRANSIENT_FOR, AtomEnum::WINDOW, 0, 1)
+            .ok().and_then(|c| c.reply().ok());
+        prop.is_some_and(|p| p.value32().and_then(|mut v| v.next()) == Some(parent))
+    };
+    let mut found = false;
+    for _ in 0..300 {
+        let children = conn.query_tree(root).unwrap().reply().unwrap().children;
+        found = children.into_iter().any(transient_for_parent);
+        if found {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let _ = picker.kill();
+    let _ = picker.wait();
+    assert!(found, "no picker window was made transient for the parent");
+}
@@ -0,0 +1,89 @@
+//! Tests for the search box's query syntax and how it scores files. The search runs in the
+//! picker, so these build the query module on its own.
+
+#[allow(dead_code)]
+#[path = "../src/query.rs"]
+mod query;
+use fuzzy_matcher::FuzzyMatcher;
+use query::{Kind, Query, SearchItem};
+use std::collections::HashMap;
+
+fn item(path: &str) -> SearchItem {
+    SearchItem { path: path.to_string(), size: 100, mtime: 0, dir: false, kind: None }
+}
+
+fn score(query: &Query, item: &SearchItem, found: &[HashMap<String, i64>]) -> Option<i64> {
+    let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
+    query.score(item, found, |name, text| matcher.fuzzy_match(name, text))
+}
+
+#[test]
+fn name_match_outranks_description_match() {
+    let query = Query::parse("beach");
+    let named = item("/photos/beach.jpg");
+    let described = item("/photos/IMG_0001.jpg");
+    // Every word, the best bm25 rank and an identical embedding, and then some
+    for description in [2999, i64::MAX / 4] {
+        let found = [HashMap::from([(described.path.clone(), description)])];
+        let named_score = score(&query, &named, &found).unwrap();
+        let described_score = score(&query, &described, &found).unwrap();
+        assert!(named_score > described_score, "{} <= {}", named_score, described_score);
+    }
+}
+
+#[test]
+fn description_still_ranks_fuzzy_names() {
+    let query = Query::parse("beach");
+    let fuzzy = item("/photos/b_e_a_c_h.jpg");
+    let both = item("/photos/bleach.jpg");
+    let found = [HashMap::from([(both.path.clone(), 1000)])];
+    assert!(score(&query, &both, &found) > score(&query, &fuzzy, &found));
+    assert_eq!(score(&query, &item("/photos/sunset.jpg"), &found), None);
+}
+
+fn searches(input: &str) -> Vec<(String, bool)> {
+    Query::parse(input).searches().to_vec()
+}
+
+fn words(text: &str) -> Vec<(String, bool)> {
+    vec![(text.to_string(), true)]
+}
+
+#[test]
+fn lone_minus_is_a_word() {
+    assert_eq!(searches("beach -"), words("beach -"));
+    assert_eq!(searches("- beach"), words("- beach"));
+}
+
+#[test]
+fn unterminated_quote_takes_the_rest() {
+    assert_eq!(searches("\"beach sun"), [("beach sun".to_string(), false)]);
+    let query = Query::parse("name:\"two words");
+    assert_eq!(query.searches(), []);
+    assert!(score(&query, &item("/x/Two Words.txt"), &[]).is_some());
+    assert_eq!(score(&query, &item("/x/two.txt"), &[]), None);
+}
+
+#[test]
+fn size_below_zero_is_a_word() {
+    assert_eq!(searches("size:<0"), words("size:<0"));
+    assert_eq!(searches("size:<=0"), []);
+}
+
+#[test]
+fn empty_field_is_a_word() {
+    assert_eq!(searches("name:"), words("name:"));
+    assert_eq!(searches("desc:"), words("desc:"));
+    assert_eq!(searches("ext: beach"), words("ext: beach"));
+}
+
+#[test]
+fn type_follows_what_the_picker_loaded() {
+    let query = Query::parse("type:video");
+    let clip = SearchItem { kind: Some(Kind::Video), ..item("/x/clip.jpg") };
+    assert!(score(&query, &clip, &[]).is_some());
+    assert_eq!(score(&query, &item("/x/clip.jpg"), &[]), None);
+    assert!(score(&query, &item("/x/clip.mp4"), &[]).is_some(), "guessed from the name until loaded");
+    let query = Query::parse("type:audio");
+    assert!(score(&query, &item("/x/song.mp3"), &[]).is_some());
+}
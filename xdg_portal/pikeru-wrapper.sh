@@ -7,20 +7,27 @@
 # 3. "0" if opening files was requested, "1" if writing to a file was
 #    requested. For example, when uploading files in Firefox, this will be "0".
 #    When saving a web page in Firefox, this will be "1".
+#    If this and the directory input are both "1", the caller is saving several
+#    files and the chosen directory is where they go.
 # 4. If writing to a file, this is recommended path provided by the caller. For
 #    example, when saving a web page in Firefox, this will be the recommended
 #    path Firefox provided, such as "~/Downloads/webpage_title.html".
 #    Note that if the path already exists, we keep appending "_" to it until we
 #    get a path that does not exist.
-# 5. The output path, to which results should be written.
+#    When saving several files, this is the directory to start in.
+# 5+. Extra pikeru arguments for the request, such as the caller's window title
+#    (--title 'Open File') and window (--parent x11:XID), select button text
+#    (--accept-label Upload), file filters (--filter 'Name|glob:*.png|mime:image/jpeg'),
+#    the default one (--current-filter Name) and extra options to show
+#    (--choice 'id|Label|initial|option|Option label|...').
 #
 # Output:
-# The script should print the selected paths to the output path (argument #5),
-# one path per line.
+# The script should print the selected paths to stdout, one path per line.
 # If nothing is printed, then the operation is assumed to have been canceled.
-#
-# Notes:
-# Mime filters not yet implemented in this xdg portal backend.
+# pikeru also writes the paths, which may contain newlines, and other results like
+# the chosen filter and choices, as NUL-terminated key=value records to the file
+# named by $PK_RESULT, which the portal creates. Path records there take precedence over stdout.
+# If the caller closes the request, the script's process group gets SIGTERM.
 
 multiple="$1"
 directory="$2"
@@ -40,20 +47,38 @@ else
 fi
 
 
-cmd="pikeru -m $mode -t 'File Picker' -p \"$path\""
+# Callers that don't give a title still get one
+title=(-t 'File Picker')
+for arg in "${@:5}"; do
+    [ "$arg" = --title ] && title=()
+done
+
+cmd=(pikeru -m "$mode" "${title[@]}" -p "$path" "${@:5}")
 
 # iced has a problem with crashing when no gpu is available so disable and retry if that happens
 [ -r "$HOME/.cache/pikeru/no_gpu" ] && export ICED_BACKEND=tiny-skia
-output="$(eval "$cmd")"
+output="$("${cmd[@]}")"
 if [ $? = 139 ] && [ ! -r "$HOME/.cache/pikeru/no_gpu" ]; then
     touch "$HOME/.cache/pikeru/no_gpu"
     export ICED_BACKEND=tiny-skia
-    output="$(eval "$cmd")"
+    output="$("${cmd[@]}")"
 fi
 
 if [ ! -z "$POSTPROCESSOR" ] && [ -r "$POSTPROCESSOR" ] && [ ! -z "$POSTPROCESS_DIR" ]; then
     mkdir -p "$POSTPROCESS_DIR"
-    echo "$output" | bash "$POSTPROCESSOR"
+    output="$(echo "$output" | bash "$POSTPROCESSOR")"
+    # The postprocessor works on lines, so its paths replace the ones pikeru wrote
+    if [ -n "$PK_RESULT" ] && [ -r "$PK_RESULT" ]; then
+        records=()
+        while IFS= read -r -d '' rec; do
+            [[ "$rec" == path=* ]] || records+=("$rec")
+        done < "$PK_RESULT"
+        while IFS= read -r file; do
+            [ -n "$file" ] && records+=("path=$file")
+        done <<< "$output"
+        printf '%s\0' "${records[@]}" > "$PK_RESULT"
+    fi
+    echo "$output"
 else
     echo "$output"
 fi